@@ -0,0 +1,54 @@
+/* C interface of nipaa-pac, built with `cargo build --release --features ffi` (libnipaa_pac.so / nipaa_pac.dll) */
+#ifndef NIPAA_PAC_H
+#define NIPAA_PAC_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Opened archive */
+typedef struct NipaaPac NipaaPac;
+
+/* Data buffer allocated by library, released with nipaa_pac_buffer_free */
+typedef struct NipaaPacBuffer {
+    uint8_t *data;
+    size_t len;
+} NipaaPacBuffer;
+
+/* Functions returning pointers give NULL on failure, functions returning int give 0 on success
+ * and -1 on failure. Message of the last error on current thread is returned by nipaa_pac_last_error. */
+
+/* Open archive at UTF-8 path */
+NipaaPac *nipaa_pac_open(const char *path);
+/* Open archive from memory, data is copied */
+NipaaPac *nipaa_pac_open_memory(const uint8_t *data, size_t len);
+/* Close archive, NULL is ignored */
+void nipaa_pac_close(NipaaPac *arc);
+
+size_t nipaa_pac_entry_count(const NipaaPac *arc);
+/* UTF-8 name, undecodable bytes are written as %XX. Owned by archive handle */
+const char *nipaa_pac_entry_name(const NipaaPac *arc, size_t index);
+/* Name exactly as stored in archive (SHIFT-JIS). Owned by archive handle */
+const char *nipaa_pac_entry_raw_name(const NipaaPac *arc, size_t index);
+/* Size of entry data as stored in archive, 0 if there is no such entry */
+uint32_t nipaa_pac_entry_size(const NipaaPac *arc, size_t index);
+
+/* Entry data as stored in archive */
+int nipaa_pac_read_entry(const NipaaPac *arc, size_t index, NipaaPacBuffer *out);
+/* Converted entry data: sprites as image_format ("bmp", "png" or "webp"), animations as json */
+int nipaa_pac_read_converted(const NipaaPac *arc, size_t index, const char *image_format, NipaaPacBuffer *out);
+/* Release buffer data and reset it, empty buffers are ignored */
+void nipaa_pac_buffer_free(NipaaPacBuffer *buf);
+
+/* Message of last error on current thread, NULL if there was none.
+ * Valid until next failing call on this thread */
+const char *nipaa_pac_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
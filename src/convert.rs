@@ -0,0 +1,144 @@
+//! Conversion of `.pac` archives to and from `.zip`, streaming to `.tar`
+
+use std::io::{Read, Seek, Write};
+use std::time::SystemTime;
+use anyhow::{Result, Context};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::error::PacError;
+use crate::img::ImageFormat;
+use crate::manifest::ExtractManifest;
+use crate::pac::{ExtractOptions, ExtractTarget, PacArc, PacArcBuilder, BMZ_MAGIC, MAX_INFLATED_SIZE};
+use crate::source::PacSource;
+
+/// Convert entries of `arc` selected by `opts`, passing each one with its target and
+/// whether it's already compressed to `write`. Returns manifest of written entries
+fn write_converted<S: PacSource>(
+    arc: &PacArc<S>,
+    opts: &ExtractOptions,
+    mut write: impl FnMut(&ExtractTarget, &[u8], bool) -> Result<()>,
+) -> Result<ExtractManifest> {
+    let targets = arc.plan_extraction(opts, false)?;
+    let mut manifest = ExtractManifest::default();
+    for (entry, target) in arc.entries.iter().zip(targets.iter()) {
+        let Some(target) = target else {
+            continue
+        };
+        let raw = arc.read_raw(entry)?;
+        let data = opts.converters.convert_raw(&target.name, &raw, opts.image_format, arc.format.endian)
+            .with_context(|| format!("Failed to convert {}", arc.describe_entry(entry)))?;
+        // png and webp are compressed already
+        let compressed = raw.starts_with(BMZ_MAGIC) && opts.converters.detect(&target.name, &raw, arc.format.endian).is_some() && matches!(opts.image_format, ImageFormat::Png | ImageFormat::Webp);
+        write(target, &data, compressed)?;
+        manifest.entries.push(target.manifest_entry());
+    }
+    Ok(manifest)
+}
+
+/// Write entries of `arc` to zip, converted and named the same way as on extraction
+/// (selection, name decoding, collision policy and image format of `opts` apply).
+/// Extraction manifest is stored too, so original names survive the zip
+pub fn pac_to_zip<S: PacSource, W: Write + Seek>(arc: &PacArc<S>, out: W, opts: &ExtractOptions) -> Result<ExtractManifest> {
+    let mut zip = ZipWriter::new(out);
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+
+    let manifest = write_converted(arc, opts, |target, data, compressed| {
+        zip.start_file(target.file.as_str(), if compressed { stored } else { deflated })?;
+        zip.write_all(data)?;
+        Ok(())
+    })?;
+
+    zip.start_file(ExtractManifest::FILE_NAME, deflated)?;
+    zip.write_all(serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+    zip.finish()?;
+    Ok(manifest)
+}
+
+/// Same as `pac_to_zip`, but writes tar stream, which doesn't need seeking (e.g. stdout)
+pub fn pac_to_tar<S: PacSource, W: Write>(arc: &PacArc<S>, out: W, opts: &ExtractOptions) -> Result<ExtractManifest> {
+    let mut tar = tar::Builder::new(out);
+    let mtime = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
+    let mut append = |path: &str, data: &[u8]| -> Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        tar.append_data(&mut header, path, data)
+            .with_context(|| format!("Failed to write {path} to tar"))
+    };
+
+    let manifest = write_converted(arc, opts, |target, data, _| append(&target.file, data))?;
+    append(ExtractManifest::FILE_NAME, serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+    tar.into_inner()?.flush()?;
+    Ok(manifest)
+}
+
+/// Add files of zip to `builder`, converting them the same way as on packing directory.
+///
+/// If all files are inside single top-level directory, it's treated as zip root.
+/// Files listed in extraction manifest (as written by `pac_to_zip` or extraction) go first,
+/// under their original names, then the rest in name order, named by their path relative to root.
+/// Returns number of added entries
+pub fn zip_to_pac<R: Read + Seek>(zip: R, builder: &mut PacArcBuilder) -> Result<usize> {
+    let mut zip = ZipArchive::new(zip)?;
+    let mut files = vec![];
+    for i in 0..zip.len() {
+        let file = zip.by_index(i)?;
+        if file.is_file() {
+            let name = file.enclosed_name()
+                .and_then(|path| path.to_str().map(|p| p.replace('\\', "/")))
+                .with_context(|| format!("Invalid file name in zip: {}", file.name()))?;
+            files.push((name, i));
+        }
+    }
+    files.sort();
+
+    let root = match files.first().and_then(|(name, _)| name.split_once('/')) {
+        Some((dir, _)) if files.iter().all(|(name, _)| name.starts_with(&format!("{dir}/"))) => format!("{dir}/"),
+        _ => String::new(),
+    };
+    let mut read = |index: usize| -> Result<Vec<u8>> {
+        let mut file = zip.by_index(index)?;
+        // size in zip header is not trusted for allocation
+        let mut data = Vec::with_capacity((file.size() as usize).min(MAX_INFLATED_SIZE));
+        file.read_to_end(&mut data)
+            .with_context(|| format!("Failed to read {} from zip", file.name()))?;
+        Ok(data)
+    };
+    let take = |files: &mut Vec<(String, usize)>, name: &str| {
+        files.iter().position(|(f, _)| f == name).map(|pos| files.remove(pos).1)
+    };
+
+    let mut added = 0;
+    let manifest_path = format!("{root}{}", ExtractManifest::FILE_NAME);
+    if let Some(index) = take(&mut files, &manifest_path) {
+        let manifest: ExtractManifest = serde_json::from_slice(&read(index)?)
+            .map_err(|e| PacError::Format(e.to_string()))
+            .with_context(|| format!("Failed to parse {manifest_path}"))?;
+        for entry in manifest.entries {
+            let Some(index) = take(&mut files, &format!("{root}{}", entry.file)) else {
+                eprintln!("warning: {} is listed in manifest, but missing", entry.file);
+                continue
+            };
+            let (pac_file, _) = builder.convert_source(read(index)?, &entry.file)
+                .with_context(|| format!("Failed to convert {}", entry.file))?;
+            match entry.raw_name_bytes()? {
+                Some(raw_name) => builder.add_entry_raw(pac_file, raw_name)?,
+                None => builder.add_entry(pac_file, &entry.name)?,
+            }
+            added += 1;
+        }
+    }
+
+    for (name, index) in files {
+        let rel_name = &name[root.len()..];
+        let (pac_file, entry_name) = builder.convert_source(read(index)?, rel_name)
+            .with_context(|| format!("Failed to convert {name}"))?;
+        builder.add_entry(pac_file, &entry_name)?;
+        added += 1;
+    }
+
+    Ok(added)
+}
@@ -0,0 +1,364 @@
+//! Registry of converters between in-archive formats and editable files.
+//!
+//! Each [`FileConverter`] recognizes one in-archive format, converts it on extraction and
+//! builds it back on packing. Entries no converter handles are stored and extracted as is.
+//! Built-in converters can be disabled and new ones registered without changes to archive code:
+//! ```no_run
+//! use nipaa_pac::{Converters, ExtractOptions, PacArc};
+//!
+//! let mut converters = Converters::default();
+//! converters.disable("ttp").unwrap();
+//! let opts = ExtractOptions { converters, ..Default::default() };
+//! PacArc::open("Rika.pac").unwrap().extract_all("out", &opts).unwrap();
+//! ```
+
+use std::io::{Cursor, Write};
+use std::path::Path;
+use std::sync::{Arc, LazyLock};
+use anyhow::{Result, bail};
+use binrw::{BinReaderExt, Endian};
+use serde::Deserialize;
+
+use crate::deflate;
+use crate::error::PacError;
+use crate::img::{self, ImageFormat};
+use crate::pac::{inflate_bmz, inflate_bmz_to, PackOptions, PacFile, BMZ_MAGIC};
+use crate::ttp::TtpFile;
+
+/// Conversion of single in-archive format
+pub trait FileConverter: Send + Sync {
+    /// Short name, used to disable converter
+    fn name(&self) -> &str;
+
+    /// Check whether entry with extension `ext` (empty if entry name is unknown)
+    /// and data `raw` (as stored in archive with byte order `endian`) is in this format
+    fn detect(&self, ext: &str, raw: &[u8], endian: Endian) -> bool;
+
+    /// Extension of entries in this format
+    fn original_ext(&self) -> &str;
+
+    /// Extension of converted files, `image_format` is requested format of sprites
+    fn converted_ext(&self, image_format: ImageFormat) -> &str;
+
+    /// Check whether files with extension `conv_ext` are built back by this converter
+    fn accepts(&self, conv_ext: &str) -> bool;
+
+    /// Convert entry data as stored in archive
+    fn convert(&self, raw: &[u8], image_format: ImageFormat, endian: Endian) -> Result<Vec<u8>>;
+
+    /// Write data `convert` would return to `out`, returning its size.
+    /// Converters able to produce output in chunks override this to avoid keeping all of it in memory
+    fn convert_to(&self, raw: &[u8], image_format: ImageFormat, endian: Endian, out: &mut dyn Write) -> Result<u64> {
+        let data = self.convert(raw, image_format, endian)?;
+        out.write_all(&data)?;
+        Ok(data.len() as u64)
+    }
+
+    /// Size of data `convert` would return, if it can be told without conversion
+    fn converted_size(&self, _raw: &[u8], _image_format: ImageFormat, _endian: Endian) -> Option<u64> {
+        None
+    }
+
+    /// Build entry from converted file with extension `conv_ext`
+    fn convert_back(&self, data: Vec<u8>, conv_ext: &str, opts: &PackOptions) -> Result<PacFile>;
+}
+
+/// ZLC3 (zlib-compressed BMP) sprites, extracted as BMP, PNG or WebP
+pub struct BmzConverter;
+
+impl FileConverter for BmzConverter {
+    fn name(&self) -> &str {
+        "bmz"
+    }
+
+    fn detect(&self, _ext: &str, raw: &[u8], _endian: Endian) -> bool {
+        is_zlc3(raw)
+    }
+
+    fn original_ext(&self) -> &str {
+        "bmz"
+    }
+
+    fn converted_ext(&self, image_format: ImageFormat) -> &str {
+        image_format.ext()
+    }
+
+    fn accepts(&self, conv_ext: &str) -> bool {
+        ImageFormat::from_ext(conv_ext).is_some()
+    }
+
+    /// BMZ data is inflated straight from `raw`, without intermediate copy
+    fn convert(&self, raw: &[u8], image_format: ImageFormat, endian: Endian) -> Result<Vec<u8>> {
+        image_format.from_bmp(inflate_zlc3(raw, endian)?)
+    }
+
+    /// BMP is inflated in chunks, other formats need whole bitmap for transcoding
+    fn convert_to(&self, raw: &[u8], image_format: ImageFormat, endian: Endian, out: &mut dyn Write) -> Result<u64> {
+        if image_format != ImageFormat::Bmp {
+            let data = self.convert(raw, image_format, endian)?;
+            out.write_all(&data)?;
+            return Ok(data.len() as u64)
+        }
+        inflate_bmz_to(&raw[ZLC3_HEADER_SIZE..], zlc3_declared(raw, endian)?, out)
+    }
+
+    fn converted_size(&self, raw: &[u8], image_format: ImageFormat, endian: Endian) -> Option<u64> {
+        (image_format == ImageFormat::Bmp).then(|| zlc3_size(raw, endian)).flatten()
+    }
+
+    fn convert_back(&self, data: Vec<u8>, conv_ext: &str, opts: &PackOptions) -> Result<PacFile> {
+        let data = match ImageFormat::from_ext(conv_ext) {
+            Some(ImageFormat::Bmp) | None => data,
+            Some(format) => img::to_bmp(&data, format)?,
+        };
+        if !opts.compress {
+            return Ok(PacFile::Other { data })
+        }
+        Ok(compress_zlc3(&data, opts))
+    }
+}
+
+/// ZLC3 header: magic and uncompressed size
+const ZLC3_HEADER_SIZE: usize = 8;
+
+fn is_zlc3(raw: &[u8]) -> bool {
+    raw.len() >= ZLC3_HEADER_SIZE && raw.starts_with(BMZ_MAGIC)
+}
+
+fn zlc3_size(raw: &[u8], endian: Endian) -> Option<u64> {
+    let size: u32 = Cursor::new(raw.get(4..ZLC3_HEADER_SIZE)?).read_type(endian).ok()?;
+    Some(size as u64)
+}
+
+fn zlc3_declared(raw: &[u8], endian: Endian) -> Result<u32> {
+    Ok(Cursor::new(&raw[4..ZLC3_HEADER_SIZE]).read_type(endian)?)
+}
+
+/// Inflate ZLC3 stream, rejecting streams inflating to more than size declared in header
+fn inflate_zlc3(raw: &[u8], endian: Endian) -> Result<Vec<u8>> {
+    Ok(inflate_bmz(&raw[ZLC3_HEADER_SIZE..], zlc3_declared(raw, endian)?)?)
+}
+
+fn compress_zlc3(data: &[u8], opts: &PackOptions) -> PacFile {
+    let uncompressed_size = data.len() as u32;
+    let compressed_data = deflate::compress(data, opts.level);
+    PacFile::Bmz { uncompressed_size, compressed_data }
+}
+
+/// Animations, extracted as json
+pub struct TtpConverter;
+
+impl FileConverter for TtpConverter {
+    fn name(&self) -> &str {
+        "ttp"
+    }
+
+    fn detect(&self, _ext: &str, raw: &[u8], endian: Endian) -> bool {
+        Cursor::new(raw).read_type::<TtpFile>(endian).is_ok()
+    }
+
+    fn original_ext(&self) -> &str {
+        "ttp"
+    }
+
+    fn converted_ext(&self, _image_format: ImageFormat) -> &str {
+        "json"
+    }
+
+    fn accepts(&self, conv_ext: &str) -> bool {
+        conv_ext == "json"
+    }
+
+    fn convert(&self, raw: &[u8], _image_format: ImageFormat, endian: Endian) -> Result<Vec<u8>> {
+        let ttp: TtpFile = Cursor::new(raw).read_type(endian)?;
+        Ok(serde_json::to_string_pretty(&ttp)?.into_bytes())
+    }
+
+    fn convert_back(&self, data: Vec<u8>, _conv_ext: &str, _opts: &PackOptions) -> Result<PacFile> {
+        let ttp: TtpFile = serde_json::from_slice(&data)
+            .map_err(|e| PacError::Format(e.to_string()))?;
+        ttp.validate()?;
+        Ok(PacFile::Ttp(ttp))
+    }
+}
+
+/// User-defined mapping of entries with extension `original` to files with extension `converted`,
+/// e.g. from config:
+/// ```toml
+/// [[extensions]]
+/// original = "bmz2"
+/// converted = "bmp"
+/// zlc3 = true
+/// ```
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ExtMapping {
+    pub original: String,
+    pub converted: String,
+    /// Entries are ZLC3 containers, which are inflated on extraction and compressed on packing.
+    /// Otherwise only extension is changed
+    #[serde(default)]
+    pub zlc3: bool,
+}
+
+impl FileConverter for ExtMapping {
+    fn name(&self) -> &str {
+        &self.original
+    }
+
+    fn detect(&self, ext: &str, raw: &[u8], _endian: Endian) -> bool {
+        ext.eq_ignore_ascii_case(&self.original) && (!self.zlc3 || is_zlc3(raw))
+    }
+
+    fn original_ext(&self) -> &str {
+        &self.original
+    }
+
+    fn converted_ext(&self, _image_format: ImageFormat) -> &str {
+        &self.converted
+    }
+
+    fn accepts(&self, conv_ext: &str) -> bool {
+        conv_ext.eq_ignore_ascii_case(&self.converted)
+    }
+
+    fn convert(&self, raw: &[u8], _image_format: ImageFormat, endian: Endian) -> Result<Vec<u8>> {
+        match self.zlc3 {
+            true => inflate_zlc3(raw, endian),
+            false => Ok(raw.to_vec()),
+        }
+    }
+
+    fn converted_size(&self, raw: &[u8], _image_format: ImageFormat, endian: Endian) -> Option<u64> {
+        match self.zlc3 {
+            true => zlc3_size(raw, endian),
+            false => Some(raw.len() as u64),
+        }
+    }
+
+    fn convert_back(&self, data: Vec<u8>, _conv_ext: &str, opts: &PackOptions) -> Result<PacFile> {
+        match self.zlc3 {
+            true => Ok(compress_zlc3(&data, opts)),
+            false => Ok(PacFile::Other { data }),
+        }
+    }
+}
+
+/// Ordered set of converters, first one detecting (or accepting) file handles it
+#[derive(Clone)]
+pub struct Converters(Vec<Arc<dyn FileConverter>>);
+
+static BUILTIN: LazyLock<Converters> = LazyLock::new(Converters::default);
+
+impl Default for Converters {
+    /// Built-in converters: `bmz`, `ttp`
+    fn default() -> Self {
+        Self(vec![Arc::new(BmzConverter), Arc::new(TtpConverter)])
+    }
+}
+
+impl Converters {
+    /// Registry without any converters, all entries are stored and extracted as is
+    pub fn empty() -> Self {
+        Self(vec![])
+    }
+
+    /// Shared instance of default registry
+    pub fn builtin() -> &'static Self {
+        &BUILTIN
+    }
+
+    /// Add converter, taking precedence over already registered ones
+    pub fn register(&mut self, converter: impl FileConverter + 'static) {
+        self.0.insert(0, Arc::new(converter));
+    }
+
+    /// Remove converter by name
+    pub fn disable(&mut self, name: &str) -> Result<()> {
+        let len = self.0.len();
+        self.0.retain(|c| c.name() != name);
+        if self.0.len() == len {
+            bail!(PacError::InvalidInput(format!("unknown converter: {name} (expected {})", self.names().join(", "))))
+        }
+        Ok(())
+    }
+
+    /// Names of registered converters
+    pub fn names(&self) -> Vec<&str> {
+        self.0.iter().map(|c| c.name()).collect()
+    }
+
+    /// Add user-defined extension mappings, taking precedence over built-in converters
+    pub fn with_mappings(mut self, mappings: &[ExtMapping]) -> Result<Self> {
+        for mapping in mappings.iter().rev() {
+            if mapping.original.is_empty() || mapping.converted.is_empty() {
+                bail!(PacError::InvalidInput(format!("extension mapping {} -> {} has empty extension", mapping.original, mapping.converted)))
+            }
+            self.register(mapping.clone());
+        }
+        Ok(self)
+    }
+
+    /// Converter of entry named `name` (may be empty if unknown) with data `raw`
+    /// as stored in archive with byte order `endian`
+    pub fn detect(&self, name: &str, raw: &[u8], endian: Endian) -> Option<&dyn FileConverter> {
+        let ext = Path::new(name).extension().and_then(|e| e.to_str()).unwrap_or_default();
+        self.0.iter().map(|c| c.as_ref()).find(|c| c.detect(ext, raw, endian))
+    }
+
+    /// Converter of files with converted extension `conv_ext`
+    pub fn accepting(&self, conv_ext: &str) -> Option<&dyn FileConverter> {
+        self.0.iter().map(|c| c.as_ref()).find(|c| c.accepts(conv_ext))
+    }
+
+    /// Convert data of entry named `name` as stored in archive, data of unknown formats is returned as is
+    pub fn convert_raw(&self, name: &str, raw: &[u8], image_format: ImageFormat, endian: Endian) -> Result<Vec<u8>> {
+        match self.detect(name, raw, endian) {
+            Some(converter) => converter.convert(raw, image_format, endian),
+            None => Ok(raw.to_vec()),
+        }
+    }
+
+    /// Same as `convert_raw`, but data is written to `out`. Returns its size
+    pub fn convert_raw_to(&self, name: &str, raw: &[u8], image_format: ImageFormat, endian: Endian, out: &mut dyn Write) -> Result<u64> {
+        match self.detect(name, raw, endian) {
+            Some(converter) => converter.convert_to(raw, image_format, endian, out),
+            None => {
+                out.write_all(raw)?;
+                Ok(raw.len() as u64)
+            },
+        }
+    }
+
+    /// Size of data `convert_raw` would return, if it can be told without conversion
+    pub fn converted_size_raw(&self, name: &str, raw: &[u8], image_format: ImageFormat, endian: Endian) -> Option<u64> {
+        match self.detect(name, raw, endian) {
+            Some(converter) => converter.converted_size(raw, image_format, endian),
+            None => Some(raw.len() as u64),
+        }
+    }
+
+    /// Get converted (extracted) extension of entry with extension `orig_ext`
+    pub fn converted_ext<'a>(&'a self, orig_ext: &'a str, image_format: ImageFormat) -> &'a str {
+        match self.0.iter().find(|c| c.original_ext().eq_ignore_ascii_case(orig_ext)) {
+            Some(converter) => converter.converted_ext(image_format),
+            None => orig_ext,
+        }
+    }
+
+    /// Get original (packed) extension of file with extension `conv_ext`
+    pub fn original_ext<'a>(&'a self, conv_ext: &'a str) -> &'a str {
+        match self.accepting(conv_ext) {
+            Some(converter) => converter.original_ext(),
+            None => conv_ext,
+        }
+    }
+
+    /// Build entry from converted file, files no converter accepts are stored as is
+    pub fn convert_back(&self, data: Vec<u8>, conv_ext: &str, opts: &PackOptions) -> Result<PacFile> {
+        match self.accepting(conv_ext) {
+            Some(converter) => converter.convert_back(data, conv_ext, opts),
+            None => Ok(PacFile::Other { data }),
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//! Deflate implementations BMZ streams are compressed and inflated with.
+//!
+//! `miniz_oxide` is always compiled in; `libdeflate` and `zlib-ng` cargo features add faster ones.
+//! Backend is chosen for the whole process with [`set_backend`]. Default one is `miniz`,
+//! so archives packed at the same level stay byte-identical to ones packed by previous versions
+
+use std::fmt::Display;
+use std::io::{ErrorKind, Read, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use anyhow::{Result, bail};
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use miniz_oxide::deflate::core::{compress as compress_chunk, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush, TDEFLStatus};
+use miniz_oxide::inflate::{decompress_to_vec_zlib_with_limit, DecompressError, TINFLStatus};
+use miniz_oxide::inflate::stream::InflateState;
+use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus};
+
+use crate::error::PacError;
+use crate::pac::PackOptions;
+
+/// Deflate implementation
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum DeflateBackend {
+    #[default]
+    Miniz,
+    #[cfg(feature = "libdeflate")]
+    Libdeflate,
+    #[cfg(feature = "zlib-ng")]
+    ZlibNg,
+}
+
+static BACKEND: AtomicU8 = AtomicU8::new(0);
+
+/// Size of output chunks of `DeflateBackend::inflate_to`, and of input and output chunks of `compress_to`
+const CHUNK_SIZE: usize = 64 << 10;
+
+/// Use `backend` for all following compression and inflation of BMZ streams
+pub fn set_backend(backend: DeflateBackend) {
+    let index = DeflateBackend::AVAILABLE.iter().position(|b| *b == backend).expect("all variants are available");
+    BACKEND.store(index as u8, Ordering::Relaxed);
+}
+
+/// Backend set by `set_backend`
+pub fn backend() -> DeflateBackend {
+    DeflateBackend::AVAILABLE[BACKEND.load(Ordering::Relaxed) as usize]
+}
+
+/// Compress `data` to zlib stream with backend set by `set_backend`. With `PackOptions::ZOPFLI_LEVEL`
+/// data is compressed with zopfli too, and the smaller stream is kept
+/// (zopfli loses to plain deflate on some bitmaps with long runs)
+pub fn compress(data: &[u8], level: u8) -> Vec<u8> {
+    let deflated = backend().compress(data, level);
+    if level != PackOptions::ZOPFLI_LEVEL {
+        return deflated
+    }
+    let mut zopfli = vec![];
+    zopfli::compress(zopfli::Options::default(), zopfli::Format::Zlib, data, &mut zopfli).expect("writing to vec");
+    match zopfli.len() < deflated.len() {
+        true => zopfli,
+        false => deflated,
+    }
+}
+
+/// Compress data read from `reader` to zlib stream written to `out`, in chunks, without keeping
+/// whole data in memory. Data is always compressed with `miniz` (zopfli level is treated as maximum one),
+/// output is the same as of `DeflateBackend::Miniz.compress`. Returns number of read and written bytes
+pub fn compress_to(reader: &mut dyn Read, level: u8, out: &mut dyn Write) -> Result<(u64, u64)> {
+    let flags = create_comp_flags_from_zip_params(level.min(PackOptions::MAX_LEVEL).into(), 1, 0);
+    let mut compressor = CompressorOxide::new(flags);
+    let mut input = vec![0; CHUNK_SIZE];
+    let mut output = vec![0; CHUNK_SIZE];
+    let (mut read, mut written) = (0, 0);
+    loop {
+        let len = match reader.read(&mut input) {
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        read += len as u64;
+        let flush = match len {
+            0 => TDEFLFlush::Finish,
+            _ => TDEFLFlush::None,
+        };
+        let mut pos = 0;
+        loop {
+            let (status, bytes_in, bytes_out) = compress_chunk(&mut compressor, &input[pos..len], &mut output, flush);
+            pos += bytes_in;
+            out.write_all(&output[..bytes_out])?;
+            written += bytes_out as u64;
+            match status {
+                TDEFLStatus::Done => return Ok((read, written)),
+                // rest of pending output is flushed along with next chunk
+                TDEFLStatus::Okay if flush == TDEFLFlush::None && pos == len && bytes_out < output.len() => break,
+                TDEFLStatus::Okay => {},
+                status => unreachable!("deflate to buffer failed: {status:?}"),
+            }
+        }
+    }
+}
+
+impl DeflateBackend {
+    /// Backends compiled in
+    pub const AVAILABLE: &[Self] = &[
+        DeflateBackend::Miniz,
+        #[cfg(feature = "libdeflate")]
+        DeflateBackend::Libdeflate,
+        #[cfg(feature = "zlib-ng")]
+        DeflateBackend::ZlibNg,
+    ];
+
+    /// Compress `data` to zlib stream. `level` is in range of `PackOptions` (0-10) and is mapped to
+    /// backend's own range, maximum level to maximum one
+    pub fn compress(self, data: &[u8], level: u8) -> Vec<u8> {
+        let level = level.min(PackOptions::MAX_LEVEL);
+        match self {
+            DeflateBackend::Miniz => compress_to_vec_zlib(data, level),
+            #[cfg(feature = "libdeflate")]
+            DeflateBackend::Libdeflate => {
+                use libdeflater::{CompressionLvl, Compressor};
+                let level = match level {
+                    PackOptions::MAX_LEVEL => CompressionLvl::best(),
+                    level => CompressionLvl::new(level as i32).expect("levels 0-9 are valid"),
+                };
+                let mut compressor = Compressor::new(level);
+                let mut out = vec![0; compressor.zlib_compress_bound(data.len())];
+                let len = compressor.zlib_compress(data, &mut out).expect("buffer of compress bound size");
+                out.truncate(len);
+                out
+            },
+            #[cfg(feature = "zlib-ng")]
+            DeflateBackend::ZlibNg => {
+                use std::io::Write;
+                use flate2::{write::ZlibEncoder, Compression};
+                let mut encoder = ZlibEncoder::new(vec![], Compression::new(level.min(9) as u32));
+                encoder.write_all(data).expect("writing to vec");
+                encoder.finish().expect("writing to vec")
+            },
+        }
+    }
+
+    /// Inflate zlib stream to `out`, producing at most `limit` bytes. With `miniz` stream is inflated
+    /// in chunks, without keeping whole output in memory; other backends inflate it in one go.
+    /// Returns number of written bytes
+    pub fn inflate_to(self, data: &[u8], limit: usize, out: &mut dyn Write) -> anyhow::Result<u64> {
+        if self != DeflateBackend::Miniz {
+            let inflated = self.inflate(data, limit).map_err(PacError::from)?;
+            out.write_all(&inflated)?;
+            return Ok(inflated.len() as u64)
+        }
+
+        let mut state = InflateState::new_boxed(DataFormat::Zlib);
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let (mut consumed, mut written) = (0, 0);
+        loop {
+            let result = miniz_oxide::inflate::stream::inflate(&mut state, &data[consumed..], &mut chunk, MZFlush::None);
+            consumed += result.bytes_consumed;
+            written += result.bytes_written;
+            if written > limit {
+                Err(PacError::from(DecompressError { status: TINFLStatus::HasMoreOutput, output: vec![] }))?
+            }
+            out.write_all(&chunk[..result.bytes_written])?;
+            let status = match result.status {
+                Ok(MZStatus::StreamEnd) => return Ok(written as u64),
+                Ok(_) if result.bytes_consumed + result.bytes_written > 0 => continue,
+                Ok(_) => TINFLStatus::FailedCannotMakeProgress,
+                Err(MZError::Buf) => TINFLStatus::FailedCannotMakeProgress,
+                Err(_) => TINFLStatus::Failed,
+            };
+            Err(PacError::from(DecompressError { status, output: vec![] }))?
+        }
+    }
+
+    /// Inflate zlib stream, producing at most `limit` bytes
+    pub fn inflate(self, data: &[u8], limit: usize) -> Result<Vec<u8>, DecompressError> {
+        match self {
+            DeflateBackend::Miniz => decompress_to_vec_zlib_with_limit(data, limit),
+            #[cfg(feature = "libdeflate")]
+            DeflateBackend::Libdeflate => {
+                use libdeflater::{DecompressionError, Decompressor};
+                // output size isn't known up front, buffer grows until stream fits
+                let mut decompressor = Decompressor::new();
+                let mut out = vec![0; data.len().saturating_mul(4).clamp(1, limit.max(1))];
+                loop {
+                    match decompressor.zlib_decompress(data, &mut out) {
+                        Ok(len) => {
+                            out.truncate(len);
+                            return Ok(out)
+                        },
+                        Err(DecompressionError::InsufficientSpace) if out.len() < limit => {
+                            let len = out.len().saturating_mul(2).min(limit);
+                            out.resize(len, 0);
+                        },
+                        Err(DecompressionError::InsufficientSpace) =>
+                            return Err(DecompressError { status: TINFLStatus::HasMoreOutput, output: vec![] }),
+                        Err(DecompressionError::BadData) =>
+                            return Err(DecompressError { status: TINFLStatus::Failed, output: vec![] }),
+                    }
+                }
+            },
+            #[cfg(feature = "zlib-ng")]
+            DeflateBackend::ZlibNg => {
+                use std::io::Read;
+                use flate2::read::ZlibDecoder;
+                let mut out = vec![];
+                let read = ZlibDecoder::new(data).take(limit as u64 + 1).read_to_end(&mut out);
+                match read {
+                    Err(_) => Err(DecompressError { status: TINFLStatus::Failed, output: out }),
+                    Ok(len) if len > limit => Err(DecompressError { status: TINFLStatus::HasMoreOutput, output: vec![] }),
+                    Ok(_) => Ok(out),
+                }
+            },
+        }
+    }
+}
+
+impl FromStr for DeflateBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(backend) = Self::AVAILABLE.iter().find(|b| b.to_string() == s) {
+            return Ok(*backend)
+        }
+        match s {
+            "libdeflate" | "zlib-ng" => bail!(PacError::InvalidInput(format!("deflate backend {s} is not compiled in (build with `--features {s}`)"))),
+            _ => {
+                let names: Vec<String> = Self::AVAILABLE.iter().map(|b| b.to_string()).collect();
+                bail!(PacError::InvalidInput(format!("unknown deflate backend: {s} (expected {})", names.join(", "))))
+            },
+        }
+    }
+}
+
+impl Display for DeflateBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DeflateBackend::Miniz => "miniz",
+            #[cfg(feature = "libdeflate")]
+            DeflateBackend::Libdeflate => "libdeflate",
+            #[cfg(feature = "zlib-ng")]
+            DeflateBackend::ZlibNg => "zlib-ng",
+        })
+    }
+}
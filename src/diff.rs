@@ -0,0 +1,212 @@
+//! Comparison of two archives, or of archive and directory it was extracted to
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use anyhow::{Result, Context};
+use encoding_rs::SHIFT_JIS;
+use sha2::{Digest, Sha256};
+
+use crate::img::ImageFormat;
+use crate::manifest::{ExtractManifest, ManifestEntry};
+use crate::pac::{ExtractOptions, PacArc, PacEntryRead};
+use crate::source::PacSource;
+
+/// Single difference between archives
+pub enum Change {
+    /// Entry present only in new archive
+    Added { name: String, size: u32 },
+    /// Entry present only in old archive
+    Removed { name: String, size: u32 },
+    /// Entry with same content under different name (detected with content hashing only)
+    Renamed { from: String, to: String, size: u32 },
+    /// Entry present in both archives with different size or content
+    Changed { name: String, old_size: u32, new_size: u32 },
+}
+
+impl Change {
+    /// Size difference introduced by change, in bytes
+    pub fn size_delta(&self) -> i64 {
+        match *self {
+            Change::Added { size, .. } => size as i64,
+            Change::Removed { size, .. } => -(size as i64),
+            Change::Renamed { .. } => 0,
+            Change::Changed { old_size, new_size, .. } => new_size as i64 - old_size as i64,
+        }
+    }
+}
+
+/// Compare entries of `old` and `new` archives by name and size.
+///
+/// With `hash_content` entries of equal size are also compared by content,
+/// and removed entries having same content as added ones are reported as renamed.
+/// Removed entries go first (in `old` order), then the rest in `new` order.
+pub fn diff<A: PacSource, B: PacSource>(old: &PacArc<A>, new: &PacArc<B>, hash_content: bool) -> Result<Vec<Change>> {
+    let old_entries = named_entries(old)?;
+    let new_entries = named_entries(new)?;
+    let old_by_name: HashMap<&str, &PacEntryRead> = old_entries.iter()
+        .map(|(name, entry)| (name.as_str(), *entry))
+        .collect();
+    let new_by_name: HashMap<&str, &PacEntryRead> = new_entries.iter()
+        .map(|(name, entry)| (name.as_str(), *entry))
+        .collect();
+
+    let removed: Vec<_> = old_entries.iter()
+        .filter(|(name, _)| !new_by_name.contains_key(name.as_str()))
+        .collect();
+
+    // content hash -> indices into `removed` not yet matched to added entry
+    let mut removed_by_hash: HashMap<u64, Vec<usize>> = HashMap::new();
+    if hash_content {
+        for (idx, (_, entry)) in removed.iter().enumerate() {
+            removed_by_hash.entry(content_hash(old, entry)?).or_default().push(idx);
+        }
+    }
+
+    let mut renamed = vec![None; removed.len()];
+    let mut changes = vec![];
+    for (name, entry) in new_entries.iter() {
+        match old_by_name.get(name.as_str()) {
+            Some(old_entry) => {
+                let differs = old_entry.size != entry.size || (hash_content
+                    && content_hash(old, old_entry)? != content_hash(new, entry)?);
+                if differs {
+                    changes.push(Change::Changed {
+                        name: name.clone(),
+                        old_size: old_entry.size,
+                        new_size: entry.size,
+                    })
+                }
+            },
+            None => {
+                let from = if hash_content {
+                    removed_by_hash.get_mut(&content_hash(new, entry)?).and_then(|idx| idx.pop())
+                } else {
+                    None
+                };
+                match from {
+                    Some(idx) => {
+                        renamed[idx] = Some(name.as_str());
+                        changes.push(Change::Renamed {
+                            from: removed[idx].0.clone(),
+                            to: name.clone(),
+                            size: entry.size,
+                        })
+                    },
+                    None => changes.push(Change::Added { name: name.clone(), size: entry.size }),
+                }
+            },
+        }
+    }
+
+    let removed = removed.iter().zip(renamed)
+        .filter(|(_, renamed)| renamed.is_none())
+        .map(|((name, entry), _)| Change::Removed { name: name.clone(), size: entry.size });
+    Ok(removed.chain(changes).collect())
+}
+
+fn named_entries<S: PacSource>(arc: &PacArc<S>) -> Result<Vec<(String, &PacEntryRead)>> {
+    arc.entries.iter()
+        .map(|entry| Ok((entry.name()?, entry)))
+        .collect()
+}
+
+fn content_hash<S: PacSource>(arc: &PacArc<S>, entry: &PacEntryRead) -> Result<u64> {
+    let mut hasher = DefaultHasher::new();
+    arc.read_raw(entry)?.hash(&mut hasher);
+    Ok(hasher.finish())
+}
+
+/// Difference between extracted directory and archive
+pub enum Drift {
+    /// File differs from entry converted the same way as on extraction
+    Modified { name: String, file: String },
+    /// File of extracted entry is missing
+    Missing { name: String, file: String },
+    /// File doesn't correspond to any archive entry
+    Added { file: String },
+}
+
+/// Result of `diff_extracted`
+#[derive(Default)]
+pub struct ExtractedDiff {
+    /// Number of files identical to their entries
+    pub unchanged: usize,
+    pub drift: Vec<Drift>,
+}
+
+/// Compare files in `dir` with entries of `arc` they were extracted from.
+///
+/// Files are matched to entries using extraction manifest of `dir`, or, if there is none,
+/// by names extraction with `opts` would give them. Every entry is converted the same way
+/// as on extraction (to image format of its file extension) and compared with file by SHA-256
+pub fn diff_extracted<S: PacSource>(arc: &PacArc<S>, dir: &Path, opts: &ExtractOptions) -> Result<ExtractedDiff> {
+    let targets: Vec<ManifestEntry> = match ExtractManifest::load(dir)? {
+        Some(manifest) => manifest.entries,
+        None => arc.plan_extraction(opts, false)?.into_iter().flatten().map(|t| t.manifest_entry()).collect(),
+    };
+    let mut by_name: HashMap<&[u8], &PacEntryRead> = HashMap::new();
+    for entry in arc.entries.iter() {
+        by_name.entry(&entry.name.0).or_insert(entry);
+    }
+
+    let mut result = ExtractedDiff::default();
+    let mut matched = HashSet::new();
+    for target in targets.iter() {
+        let name_bytes = match target.raw_name_bytes()? {
+            Some(bytes) => bytes,
+            None => SHIFT_JIS.encode(&target.name).0.into_owned(),
+        };
+        let Some(entry) = by_name.get(&name_bytes[..]) else {
+            continue
+        };
+        matched.insert(target.file.as_str());
+        let path = dir.join(&target.file);
+        if !path.is_file() {
+            result.drift.push(Drift::Missing { name: target.name.clone(), file: target.file.clone() });
+            continue
+        }
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+        let image_format = ImageFormat::from_ext(ext).unwrap_or(opts.image_format);
+        let converted = opts.converters.convert_raw(&target.name, &arc.read_raw(entry)?, image_format, arc.format.endian)
+            .with_context(|| format!("Failed to convert {}", arc.describe_entry(entry)))?;
+        let data = std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        match Sha256::digest(&converted) == Sha256::digest(&data) {
+            true => result.unchanged += 1,
+            false => result.drift.push(Drift::Modified { name: target.name.clone(), file: target.file.clone() }),
+        }
+        // entry data kept next to converted file is compared as is
+        if let Some(raw_file) = &target.raw_file {
+            matched.insert(raw_file.as_str());
+            match std::fs::read(dir.join(raw_file)) {
+                Ok(data) if data == arc.read_raw(entry)?[..] => {},
+                Ok(_) => result.drift.push(Drift::Modified { name: target.name.clone(), file: raw_file.clone() }),
+                Err(_) => result.drift.push(Drift::Missing { name: target.name.clone(), file: raw_file.clone() }),
+            }
+        }
+    }
+
+    let mut files = vec![];
+    list_files(dir, "", &mut files)?;
+    files.sort();
+    for file in files {
+        if file != ExtractManifest::FILE_NAME && !matched.contains(file.as_str()) {
+            result.drift.push(Drift::Added { file });
+        }
+    }
+    Ok(result)
+}
+
+/// Paths of all files under `dir`, relative to it
+fn list_files(dir: &Path, prefix: &str, files: &mut Vec<String>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+        match entry.file_type()?.is_dir() {
+            true => list_files(&entry.path(), &format!("{name}/"), files)?,
+            false => files.push(name),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,97 @@
+//! Typed causes of library errors.
+//!
+//! Library functions return [`anyhow::Error`] rather than `PacError` itself, so errors carry
+//! context of the failed operation (which archive, entry and offset) as a chain. Every error raised
+//! by the library has `PacError` in that chain (errors of I/O and of parsed formats included),
+//! its cause can be told with [`ErrorKind::of`]:
+//! ```no_run
+//! use nipaa_pac::{ErrorKind, PacArc};
+//!
+//! match PacArc::open("Rika.pac") {
+//!     Err(e) if ErrorKind::of(&e) == Some(ErrorKind::SizeLimit) => eprintln!("hostile archive: {e:#}"),
+//!     Err(e) => eprintln!("{e:#}"),
+//!     Ok(arc) => println!("{} entries", arc.entries.len()),
+//! }
+//! ```
+
+use miniz_oxide::inflate::{DecompressError, TINFLStatus};
+
+use crate::pac::MAX_INFLATED_SIZE;
+
+/// Cause of failed operation
+#[derive(Debug, thiserror::Error)]
+pub enum PacError {
+    /// Entry or resource name can't be encoded to (or decoded from) SHIFT-JIS, or doesn't fit in its field
+    #[error("{0}")]
+    NameEncoding(String),
+    /// Declared size doesn't fit in archive or exceeds safety limit
+    #[error("{0}")]
+    SizeLimit(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// Entry data can't be decoded (damaged BMZ stream, unreadable image)
+    #[error("{0}")]
+    Decode(String),
+    /// Malformed archive, patch or animation structure
+    #[error("{0}")]
+    Format(String),
+    /// Request can't be carried out as given: unknown option value, missing or conflicting entry,
+    /// file name already taken
+    #[error("{0}")]
+    InvalidInput(String),
+}
+
+impl PacError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            PacError::NameEncoding(_) => ErrorKind::NameEncoding,
+            PacError::SizeLimit(_) => ErrorKind::SizeLimit,
+            PacError::Io(_) => ErrorKind::Io,
+            PacError::Decode(_) => ErrorKind::Decode,
+            PacError::Format(_) => ErrorKind::Format,
+            PacError::InvalidInput(_) => ErrorKind::InvalidInput,
+        }
+    }
+}
+
+impl From<DecompressError> for PacError {
+    fn from(e: DecompressError) -> Self {
+        match e.status {
+            TINFLStatus::HasMoreOutput => PacError::SizeLimit(format!("bmz stream inflates to more than {MAX_INFLATED_SIZE} bytes")),
+            _ => PacError::Decode(e.to_string()),
+        }
+    }
+}
+
+impl From<image::ImageError> for PacError {
+    fn from(e: image::ImageError) -> Self {
+        PacError::Decode(e.to_string())
+    }
+}
+
+/// Class of [`PacError`]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorKind {
+    NameEncoding,
+    SizeLimit,
+    Io,
+    Decode,
+    Format,
+    InvalidInput,
+}
+
+impl ErrorKind {
+    /// Class of first typed cause in error chain of `e`.
+    /// Plain I/O errors count too, `None` if nothing in chain is classified
+    pub fn of(e: &anyhow::Error) -> Option<Self> {
+        e.chain().find_map(|cause| {
+            if let Some(e) = cause.downcast_ref::<PacError>() {
+                Some(e.kind())
+            } else if cause.is::<std::io::Error>() {
+                Some(ErrorKind::Io)
+            } else {
+                None
+            }
+        })
+    }
+}
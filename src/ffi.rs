@@ -0,0 +1,230 @@
+//! C ABI for embedding the parser into C/C++ tools, declared in `include/nipaa_pac.h`.
+//!
+//! Functions returning pointers give `NULL` on failure, functions returning `int` give 0 on success
+//! and -1 on failure. Message of the last error on current thread is available from
+//! `nipaa_pac_last_error`.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use anyhow::{Result, anyhow, bail};
+
+use crate::error::PacError;
+use crate::img::ImageFormat;
+use crate::converter::Converters;
+use crate::pac::{NameDecoding, PacArc, PacEntryRead};
+use crate::source::DynSource;
+
+/// Opened archive
+pub struct NipaaPac {
+    arc: PacArc<DynSource>,
+    /// Entry names (with `%XX` escapes of undecodable bytes) and raw SHIFT-JIS names
+    names: Vec<(CString, CString)>,
+}
+
+/// Data buffer allocated by library, released with `nipaa_pac_buffer_free`
+#[repr(C)]
+pub struct NipaaPacBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(e: anyhow::Error) {
+    let message = CString::new(format!("{e:#}").replace('\0', " ")).expect("NUL bytes are replaced");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Run `f`, recording errors and panics as last error
+fn guard<T>(fallback: T, f: impl FnOnce() -> Result<T>) -> T {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(e)) => {
+            set_error(e);
+            fallback
+        },
+        Err(_) => {
+            set_error(anyhow!("panic in nipaa-pac"));
+            fallback
+        },
+    }
+}
+
+fn open(arc: PacArc<DynSource>) -> Result<*mut NipaaPac> {
+    let names = arc.entries.iter()
+        .map(|entry| Ok((
+            CString::new(entry.decode_name(NameDecoding::Hex)?)?,
+            CString::new(entry.name.to_vec())?,
+        )))
+        .collect::<Result<_>>()?;
+    Ok(Box::into_raw(Box::new(NipaaPac { arc, names })))
+}
+
+unsafe fn arc_ref<'a>(arc: *const NipaaPac) -> Result<&'a NipaaPac> {
+    arc.as_ref().ok_or_else(|| PacError::InvalidInput("archive handle is NULL".to_string()).into())
+}
+
+unsafe fn entry_data(
+    arc: *const NipaaPac,
+    index: usize,
+    out: *mut NipaaPacBuffer,
+    f: impl FnOnce(&PacArc<DynSource>, &PacEntryRead) -> Result<Vec<u8>>,
+) -> c_int {
+    guard(-1, || {
+        let arc = arc_ref(arc)?;
+        let Some(out) = out.as_mut() else {
+            bail!(PacError::InvalidInput("output buffer is NULL".to_string()))
+        };
+        let Some(entry) = arc.arc.entries.get(index) else {
+            bail!(PacError::InvalidInput(format!("no entry at index {index} ({} entries)", arc.arc.entries.len())))
+        };
+        let data = f(&arc.arc, entry)?.into_boxed_slice();
+        out.len = data.len();
+        out.data = Box::into_raw(data) as *mut u8;
+        Ok(0)
+    })
+}
+
+/// Open archive at `path` (UTF-8)
+///
+/// # Safety
+/// `path` must be a valid NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn nipaa_pac_open(path: *const c_char) -> *mut NipaaPac {
+    guard(ptr::null_mut(), || {
+        if path.is_null() {
+            bail!(PacError::InvalidInput("path is NULL".to_string()))
+        }
+        let path = CStr::from_ptr(path).to_str()?;
+        open(PacArc::open(path)?.into_dyn())
+    })
+}
+
+/// Open archive from `len` bytes at `data`, which are copied
+///
+/// # Safety
+/// `data` must point to `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn nipaa_pac_open_memory(data: *const u8, len: usize) -> *mut NipaaPac {
+    guard(ptr::null_mut(), || {
+        if data.is_null() {
+            bail!(PacError::InvalidInput("data is NULL".to_string()))
+        }
+        let data = std::slice::from_raw_parts(data, len).to_vec();
+        open(PacArc::from_source(data)?.into_dyn())
+    })
+}
+
+/// Close archive, `NULL` is ignored
+///
+/// # Safety
+/// `arc` must be returned by `nipaa_pac_open*` and not closed yet
+#[no_mangle]
+pub unsafe extern "C" fn nipaa_pac_close(arc: *mut NipaaPac) {
+    if !arc.is_null() {
+        drop(Box::from_raw(arc));
+    }
+}
+
+/// Number of entries in archive
+///
+/// # Safety
+/// `arc` must be a valid archive handle
+#[no_mangle]
+pub unsafe extern "C" fn nipaa_pac_entry_count(arc: *const NipaaPac) -> usize {
+    guard(0, || Ok(arc_ref(arc)?.arc.entries.len()))
+}
+
+/// UTF-8 name of entry, undecodable bytes are written as `%XX`.
+/// String is owned by archive handle
+///
+/// # Safety
+/// `arc` must be a valid archive handle
+#[no_mangle]
+pub unsafe extern "C" fn nipaa_pac_entry_name(arc: *const NipaaPac, index: usize) -> *const c_char {
+    guard(ptr::null(), || match arc_ref(arc)?.names.get(index) {
+        Some((name, _)) => Ok(name.as_ptr()),
+        None => bail!(PacError::InvalidInput(format!("no entry at index {index}"))),
+    })
+}
+
+/// Name of entry exactly as stored in archive (SHIFT-JIS).
+/// String is owned by archive handle
+///
+/// # Safety
+/// `arc` must be a valid archive handle
+#[no_mangle]
+pub unsafe extern "C" fn nipaa_pac_entry_raw_name(arc: *const NipaaPac, index: usize) -> *const c_char {
+    guard(ptr::null(), || match arc_ref(arc)?.names.get(index) {
+        Some((_, raw)) => Ok(raw.as_ptr()),
+        None => bail!(PacError::InvalidInput(format!("no entry at index {index}"))),
+    })
+}
+
+/// Size of entry data as stored in archive, 0 if there is no such entry
+///
+/// # Safety
+/// `arc` must be a valid archive handle
+#[no_mangle]
+pub unsafe extern "C" fn nipaa_pac_entry_size(arc: *const NipaaPac, index: usize) -> u32 {
+    guard(0, || match arc_ref(arc)?.arc.entries.get(index) {
+        Some(entry) => Ok(entry.size),
+        None => bail!(PacError::InvalidInput(format!("no entry at index {index}"))),
+    })
+}
+
+/// Read entry data as stored in archive into `out`
+///
+/// # Safety
+/// `arc` must be a valid archive handle, `out` must point to writable buffer struct
+#[no_mangle]
+pub unsafe extern "C" fn nipaa_pac_read_entry(arc: *const NipaaPac, index: usize, out: *mut NipaaPacBuffer) -> c_int {
+    entry_data(arc, index, out, |arc, entry| Ok(arc.read_raw(entry)?.into_owned()))
+}
+
+/// Read converted entry data (sprites as `image_format`: "bmp", "png" or "webp", animations as json) into `out`
+///
+/// # Safety
+/// `arc` must be a valid archive handle, `image_format` a valid NUL-terminated string,
+/// `out` must point to writable buffer struct
+#[no_mangle]
+pub unsafe extern "C" fn nipaa_pac_read_converted(
+    arc: *const NipaaPac,
+    index: usize,
+    image_format: *const c_char,
+    out: *mut NipaaPacBuffer,
+) -> c_int {
+    entry_data(arc, index, out, |arc, entry| {
+        if image_format.is_null() {
+            bail!(PacError::InvalidInput("image format is NULL".to_string()))
+        }
+        let image_format: ImageFormat = CStr::from_ptr(image_format).to_str()?.parse()?;
+        arc.read_converted(entry, image_format, Converters::builtin())
+    })
+}
+
+/// Release data of buffer filled by library and reset it, empty buffers are ignored
+///
+/// # Safety
+/// `buf` must be filled by `nipaa_pac_read_*` and not released yet
+#[no_mangle]
+pub unsafe extern "C" fn nipaa_pac_buffer_free(buf: *mut NipaaPacBuffer) {
+    if let Some(buf) = buf.as_mut() {
+        if !buf.data.is_null() {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buf.data, buf.len)));
+        }
+        buf.data = ptr::null_mut();
+        buf.len = 0;
+    }
+}
+
+/// Message of last error on current thread, `NULL` if there was none.
+/// String is valid until next failing call on this thread
+#[no_mangle]
+pub extern "C" fn nipaa_pac_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
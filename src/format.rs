@@ -0,0 +1,195 @@
+//! Layouts of `.pac` archive variants.
+//!
+//! Desktop accessories of the same era share the archive structure, differing in details
+//! like header magic, width of entry name field or byte order (ports to big-endian consoles). Layout of archive being opened is detected
+//! by checking which candidate layout its entry table is consistent with.
+
+use std::fmt::Display;
+use std::io::Cursor;
+use std::str::FromStr;
+use anyhow::{Result, bail};
+use binrw::{BinReaderExt, Endian};
+
+use crate::error::PacError;
+use crate::pac::{PacEntryRead, ENTRY_NAME_SIZE};
+use crate::source::PacSource;
+
+/// Archive layout:
+/// ```text
+/// magic: [u8; magic.len()],
+/// entries_count: u32,
+/// entries: [{ offset: u32, size: u32, name: [u8; name_size] }; entries_count],
+/// data of entries
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PacFormat {
+    /// Bytes archive starts with, empty for archives without magic
+    pub magic: Vec<u8>,
+    /// Size of the null-padded entry name field
+    pub name_size: usize,
+    /// Byte order of numbers in entry table and entry headers
+    pub endian: Endian,
+}
+
+impl Default for PacFormat {
+    /// Layout of higurashi no naku koro ni screen buddy archives
+    fn default() -> Self {
+        Self { magic: vec![], name_size: ENTRY_NAME_SIZE, endian: Endian::Little }
+    }
+}
+
+impl PacFormat {
+    /// Name field widths tried by autodetection, most common first
+    pub const CANDIDATE_NAME_SIZES: &[usize] = &[ENTRY_NAME_SIZE, 32, 48, 64, 128, 256];
+    /// Length of magic tried by autodetection
+    pub const CANDIDATE_MAGIC_SIZE: usize = 4;
+    /// Size of serialized entries count
+    pub const COUNT_SIZE: usize = 4;
+    /// Size of serialized offset and size fields of entry table record, preceding name field
+    pub const RECORD_FIELDS_SIZE: usize = 8;
+
+    /// Offset of entries count
+    pub fn count_offset(&self) -> u64 {
+        self.magic.len() as u64
+    }
+
+    /// Offset of first entry table record
+    pub fn table_offset(&self) -> u64 {
+        (self.magic.len() + Self::COUNT_SIZE) as u64
+    }
+
+    /// Size of serialized entry table record
+    pub fn record_size(&self) -> usize {
+        Self::RECORD_FIELDS_SIZE + self.name_size
+    }
+
+    /// Offset right after table of `count` entries
+    pub fn table_end(&self, count: u64) -> u64 {
+        self.table_offset() + count * self.record_size() as u64
+    }
+
+    /// Find layout `source` is consistent with, trying default one first.
+    /// `None` if no candidate layout fits
+    pub fn detect<S: PacSource + ?Sized>(source: &S) -> Result<Option<Self>> {
+        Self::detect_with(source, None, None)
+    }
+
+    /// Same as `detect`, trying only layouts with byte order `endian`
+    pub fn detect_endian<S: PacSource + ?Sized>(source: &S, endian: Endian) -> Result<Option<Self>> {
+        Self::detect_with(source, Some(endian), None)
+    }
+
+    /// Same as `detect`, trying only layouts with byte order `endian` and name field width `name_size`, if given
+    pub fn detect_with<S: PacSource + ?Sized>(source: &S, endian: Option<Endian>, name_size: Option<usize>) -> Result<Option<Self>> {
+        let endians = match endian {
+            Some(endian) => vec![endian],
+            None => vec![Endian::Little, Endian::Big],
+        };
+        let name_sizes = match name_size {
+            Some(name_size) => vec![name_size],
+            None => Self::CANDIDATE_NAME_SIZES.to_vec(),
+        };
+        for endian in endians {
+            if let Some(format) = Self::detect_among(source, endian, &name_sizes)? {
+                return Ok(Some(format))
+            }
+        }
+        Ok(None)
+    }
+
+    fn detect_among<S: PacSource + ?Sized>(source: &S, endian: Endian, name_sizes: &[usize]) -> Result<Option<Self>> {
+        let len = source.size()?;
+        let mut candidates = vec![vec![]];
+        if len >= Self::CANDIDATE_MAGIC_SIZE as u64 {
+            let head = source.read_at(0, Self::CANDIDATE_MAGIC_SIZE)?;
+            if head.iter().all(|b| b.is_ascii_graphic()) {
+                candidates.push(head.to_vec());
+            }
+        }
+        for magic in candidates {
+            for &name_size in name_sizes {
+                let format = Self { magic: magic.clone(), name_size, endian };
+                if format.fits(source, len)? {
+                    return Ok(Some(format))
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Parse width of name field, positive number of bytes
+    pub fn parse_name_size(s: &str) -> Result<usize> {
+        match s.parse() {
+            Ok(size) if size > 0 => Ok(size),
+            _ => bail!(PacError::InvalidInput(format!("invalid name size: {s} (expected positive number of bytes)"))),
+        }
+    }
+
+    /// Check that entry table read with this layout is consistent: it fits in source,
+    /// every name is null-terminated and non-empty, and data of every entry lies
+    /// between the end of table and the end of source
+    pub fn fits<S: PacSource + ?Sized>(&self, source: &S, len: u64) -> Result<bool> {
+        if len < self.table_offset() || source.read_at(0, self.magic.len())?[..] != self.magic[..] {
+            return Ok(false)
+        }
+        let count: u32 = Cursor::new(source.read_at(self.count_offset(), 4)?).read_type(self.endian)?;
+        let count = count as u64;
+        let table_end = self.table_end(count);
+        if table_end > len {
+            return Ok(false)
+        }
+        let table = source.read_at(self.table_offset(), (table_end - self.table_offset()) as usize)?;
+        for record in table.chunks_exact(self.record_size()) {
+            let name = &record[Self::RECORD_FIELDS_SIZE..];
+            if name.first().is_none_or(|b| *b == 0) || !name.contains(&0) {
+                return Ok(false)
+            }
+            let e: PacEntryRead = Cursor::new(record).read_type_args(self.endian, (self.name_size,))?;
+            let end = e.offset as u64 + e.size as u64;
+            if end > len || (e.size > 0 && (e.offset as u64) < table_end) {
+                return Ok(false)
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl FromStr for PacFormat {
+    type Err = anyhow::Error;
+
+    /// `nipaa` (default layout), or comma-separated overrides of it:
+    /// `magic=<ascii>`, `name-size=<bytes>` and `endian=<le|be>` (e.g. `magic=PAC1,name-size=32`)
+    fn from_str(s: &str) -> Result<Self> {
+        let mut format = Self::default();
+        for part in s.split(',').filter(|p| *p != "nipaa") {
+            match part.split_once('=') {
+                Some(("magic", magic)) => format.magic = magic.as_bytes().to_vec(),
+                Some(("name-size", size)) => format.name_size = Self::parse_name_size(size)?,
+                Some(("endian", "le")) => format.endian = Endian::Little,
+                Some(("endian", "be")) => format.endian = Endian::Big,
+                Some(("endian", endian)) => bail!(PacError::InvalidInput(format!("unknown byte order: {endian} (expected le, be)"))),
+                _ => bail!(PacError::InvalidInput(format!("unknown archive format: {part} (expected nipaa, magic=<ascii>, name-size=<bytes> or endian=<le|be>)"))),
+            }
+        }
+        Ok(format)
+    }
+}
+
+impl Display for PacFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+        if !self.magic.is_empty() {
+            parts.push(format!("magic={}", String::from_utf8_lossy(&self.magic)));
+        }
+        if self.name_size != ENTRY_NAME_SIZE {
+            parts.push(format!("name-size={}", self.name_size));
+        }
+        if self.endian == Endian::Big {
+            parts.push("endian=be".to_string());
+        }
+        match parts.is_empty() {
+            true => f.write_str("nipaa"),
+            false => f.write_str(&parts.join(",")),
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! Conversion of decompressed BMZ sprites to and from common image formats
+
+use std::fmt::Display;
+use std::io::Cursor;
+use std::str::FromStr;
+use anyhow::{Result, bail};
+
+use crate::error::PacError;
+
+/// Format of extracted sprites
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ImageFormat {
+    /// Bitmap exactly as stored in archive (no transcoding)
+    #[default]
+    Bmp,
+    Png,
+    /// Lossless WebP
+    Webp,
+}
+
+impl ImageFormat {
+    /// File extension for this format
+    pub fn ext(self) -> &'static str {
+        match self {
+            ImageFormat::Bmp => "bmp",
+            ImageFormat::Png => "png",
+            ImageFormat::Webp => "webp",
+        }
+    }
+
+    /// Detect format by file extension
+    pub fn from_ext(ext: &str) -> Option<Self> {
+        match ext.to_ascii_lowercase().as_str() {
+            "bmp" => Some(ImageFormat::Bmp),
+            "png" => Some(ImageFormat::Png),
+            "webp" => Some(ImageFormat::Webp),
+            _ => None,
+        }
+    }
+
+    fn as_image_format(self) -> image::ImageFormat {
+        match self {
+            ImageFormat::Bmp => image::ImageFormat::Bmp,
+            ImageFormat::Png => image::ImageFormat::Png,
+            ImageFormat::Webp => image::ImageFormat::WebP,
+        }
+    }
+
+    /// Transcode BMP data into this format
+    pub fn from_bmp(self, bmp: Vec<u8>) -> Result<Vec<u8>> {
+        if self == ImageFormat::Bmp {
+            return Ok(bmp)
+        }
+
+        let img = image::load_from_memory_with_format(&bmp, image::ImageFormat::Bmp).map_err(PacError::from)?;
+        let mut out = Cursor::new(vec![]);
+        img.write_to(&mut out, self.as_image_format())?;
+        Ok(out.into_inner())
+    }
+}
+
+/// Basic properties of BMP image, read from its headers
+pub struct BmpInfo {
+    pub width: i32,
+    /// Negative for top-down bitmaps
+    pub height: i32,
+    pub bit_depth: u16,
+}
+
+impl BmpInfo {
+    /// Read info from BMP file headers
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 30 || !data.starts_with(b"BM") {
+            bail!(PacError::Decode("not a bmp file".into()))
+        }
+        let i32_at = |pos: usize| i32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]);
+        Ok(BmpInfo {
+            width: i32_at(18),
+            height: i32_at(22),
+            bit_depth: u16::from_le_bytes([data[28], data[29]]),
+        })
+    }
+}
+
+/// Decode PNG/WebP `data` and encode it as BMP, ready to be compressed to BMZ.
+///
+/// Sprites are written as bottom-up 32-bit `BI_RGB` bitmaps, same as the ones shipped with the game.
+/// Alpha channel (if source has one) is kept in the 4th byte, otherwise it is zeroed like in originals.
+/// Header fields and trailing padding also mimic originals, so unmodified sprites round-trip
+/// with identical pixel data (only resolution fields may differ, they are not carried by the source).
+pub fn to_bmp(data: &[u8], format: ImageFormat) -> Result<Vec<u8>> {
+    if format == ImageFormat::Bmp {
+        return Ok(data.to_vec())
+    }
+
+    let img = image::load_from_memory_with_format(data, format.as_image_format()).map_err(PacError::from)?;
+    let has_alpha = img.color().has_alpha();
+    let img = img.to_rgba8();
+    let (w, h) = img.dimensions();
+
+    const HEADERS_SIZE: u32 = 14 + 40;
+    const TRAILING_PAD: u32 = 2;
+    let pixels_size = w * h * 4;
+    let file_size = HEADERS_SIZE + pixels_size + TRAILING_PAD;
+    let mut out = Vec::with_capacity(file_size as usize);
+
+    // BITMAPFILEHEADER
+    out.extend_from_slice(b"BM");
+    out.extend_from_slice(&file_size.to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&HEADERS_SIZE.to_le_bytes());
+    // BITMAPINFOHEADER
+    out.extend_from_slice(&40u32.to_le_bytes());
+    out.extend_from_slice(&(w as i32).to_le_bytes());
+    out.extend_from_slice(&(h as i32).to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&32u16.to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes()); // BI_RGB
+    out.extend_from_slice(&0u32.to_le_bytes()); // image size, may be 0 for BI_RGB
+    out.extend_from_slice(&2834i32.to_le_bytes()); // 72 dpi
+    out.extend_from_slice(&2834i32.to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes());
+
+    for row in img.rows().rev() {
+        for px in row {
+            let [r, g, b, a] = px.0;
+            out.extend_from_slice(&[b, g, r, if has_alpha { a } else { 0 }]);
+        }
+    }
+    out.resize(file_size as usize, 0);
+
+    Ok(out)
+}
+
+impl FromStr for ImageFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match Self::from_ext(s) {
+            Some(f) => Ok(f),
+            None => bail!(PacError::InvalidInput(format!("unknown image format: {s} (expected bmp, png or webp)"))),
+        }
+    }
+}
+
+impl Display for ImageFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.ext())
+    }
+}
@@ -0,0 +1,60 @@
+//! Library for working with `.pac` archives of
+//! `ひぐらしのなく頃に礼　デスクトップアクセサリー` (higurashi no naku koro ni screen buddy)
+//!
+//! Reading an archive:
+//! ```no_run
+//! use nipaa_pac::{Converters, ImageFormat, PacArc};
+//!
+//! let arc = PacArc::open("Rika.pac").unwrap();
+//! for entry in arc.entries() {
+//!     println!("{} ({} bytes)", entry.name().unwrap(), entry.size());
+//!     let data = entry.read_converted(ImageFormat::Bmp, Converters::builtin()).unwrap();
+//! }
+//! ```
+//!
+//! Building an archive:
+//! ```no_run
+//! use nipaa_pac::{PacArcBuilder, PacFile};
+//!
+//! let mut builder = PacArcBuilder::new();
+//! let file = PacFile::convert_back(std::fs::read("sprite.bmp").unwrap(), "bmp").unwrap();
+//! builder.add_entry(file, "sprite.bmz").unwrap();
+//! builder.pack("out.pac").unwrap();
+//! ```
+
+pub mod convert;
+pub mod converter;
+pub mod deflate;
+pub mod diff;
+pub mod error;
+mod font;
+pub mod format;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod img;
+pub mod manifest;
+#[cfg(feature = "fuse")]
+pub mod mount;
+pub mod pac;
+pub mod patch;
+pub mod render;
+pub mod sniff;
+pub mod source;
+pub mod ttp;
+pub mod verify;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use converter::{Converters, ExtMapping, FileConverter};
+pub use deflate::DeflateBackend;
+pub use error::{ErrorKind, PacError};
+pub use format::PacFormat;
+pub use img::{BmpInfo, ImageFormat};
+pub use manifest::{ExtractManifest, ManifestEntry, NameMap};
+pub use source::{DynSource, PacSource, ReaderSource};
+pub use pac::{
+    Collision, CollisionFn, CollisionPolicy, ConflictPolicy, EntryRef, ExtractOptions, IndexSet, LongNamePolicy, NameDecoding, PackOptions, PackStats, PathMode, PacArc, PacArcBuilder, PacEntryRead,
+    PacFile, Progress, ProgressFn, Recompression, ShortenedName, SizeFix, StreamConversion, ENTRY_NAME_SIZE,
+    ENTRY_RECORD_SIZE,
+};
+pub use ttp::{ResName, TtpFile, TtpFrame};
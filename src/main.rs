@@ -1,334 +1,2596 @@
-use binrw::{
-    BinRead, NullString, FilePtr32, BinWrite, binwrite, BinWriterExt
-};
-use clap::Parser;
-use std::io::Cursor;
-use std::path::Path;
-use std::{io::SeekFrom, fs::DirBuilder};
-use std::fs::{File, remove_dir_all, read_dir};
+use binrw::{BinRead, BinWriterExt, Endian};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use std::fs::{DirBuilder, File, remove_dir_all, read_dir};
 use anyhow::{Result, bail, Context};
-use miniz_oxide::inflate::decompress_to_vec_zlib;
-use miniz_oxide::deflate::compress_to_vec_zlib;
 use encoding_rs::SHIFT_JIS;
+use glob::Pattern;
+use regex::Regex;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
 
-mod ttp;
-use ttp::TtpFile;
-
-const ENTRY_NAME_SIZE: usize = 56;
-
-/// Struct for reading archive entries
-///
-/// Real layout:
-/// ```
-/// ptr: u32,
-/// size: u32,
-/// name: [u8; 56]
-/// ```
-#[derive(BinRead)]
-struct PacEntryRead {
-    #[br(seek_before = SeekFrom::Current(4))]
-    pub size: u32,
-    #[br(seek_before = SeekFrom::Current(-8), args(size), err_context("size = {size}"))]
-    pub file: FilePtr32<PacFile>,
-    #[br(seek_before = SeekFrom::Current(4), pad_size_to = ENTRY_NAME_SIZE)]
-    pub name: NullString,
-}
-
-impl PacEntryRead {
-    /// Try to get file name
-    pub fn name(&self) -> Result<String> {
-        match SHIFT_JIS.decode(&self.name) {
-            (cow, _, false) => Ok(cow.to_string()),
-            (cow, _, true) => bail!("failed to normally decode string: {cow}")
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use nipaa_pac::convert::{pac_to_tar, pac_to_zip, zip_to_pac};
+use nipaa_pac::deflate::{self, DeflateBackend};
+use nipaa_pac::diff::{diff, diff_extracted, Change, Drift};
+use nipaa_pac::manifest::{file_mtime, set_file_mtime, PackManifest, PackSource};
+use nipaa_pac::patch::Patch;
+#[cfg(feature = "mmap")]
+use nipaa_pac::source::map_file;
+use nipaa_pac::render::{contact_sheet, render_frames, sprite_sheet, AnimFormat, SheetOptions};
+use nipaa_pac::sniff::{sniff, FileKind};
+use nipaa_pac::ttp::{check_resources, ResKind};
+use nipaa_pac::verify::{verify, verify_as};
+use nipaa_pac::{BmpInfo, Converters, ErrorKind, ExtMapping, PacError, Collision, CollisionPolicy, ConflictPolicy, DynSource, ExtractManifest, ExtractOptions, IndexSet, LongNamePolicy, NameDecoding, NameMap, PackOptions, PackStats, PathMode, ImageFormat, PacArc, PacArcBuilder, PacEntryRead, PacFile, PacFormat, PacSource, ReaderSource, TtpFile, TtpFrame};
+
+/// Set with `--output ndjson`: stdout then carries only events
+static EVENT_STREAM: AtomicBool = AtomicBool::new(false);
+
+/// `println!` for human-readable messages, which go to stderr when stdout carries event stream
+macro_rules! say {
+    ($($arg:tt)*) => {
+        match EVENT_STREAM.load(Ordering::Relaxed) {
+            true => eprintln!($($arg)*),
+            false => println!($($arg)*),
+        }
+    };
+}
+
+/// Utility for extracting and packing pac archives of ひぐらしのなく頃に礼　デスクトップアクセサリー
+/// (higurashi no naku koro ni screen buddy)
+#[derive(Parser)]
+#[clap(after_help = EXIT_CODES)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Commands,
+    /// Output format: text, or ndjson (one json event per line on stdout, human-readable messages go to stderr;
+    /// stream ends with `complete` event, or `error` event if command fails)
+    #[clap(long = "output", id = "output_format", global = true, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+    /// Configuration file with defaults for `level`, `image-format`, `jobs` and `lossy-names`,
+    /// and extension mappings (`~/.config/nipaa-pac/config.toml` is used if present)
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
+    /// Deflate implementation for bmz streams: miniz, or libdeflate and zlib-ng if compiled in
+    /// (packing with miniz keeps output identical to previous versions)
+    #[clap(long, global = true, default_value_t = DeflateBackend::Miniz, hide = DeflateBackend::AVAILABLE.len() < 2)]
+    backend: DeflateBackend,
+}
+
+/// User defaults for command line options, applied to every subcommand having them
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Config {
+    level: Option<u8>,
+    image_format: Option<String>,
+    jobs: Option<usize>,
+    lossy_names: Option<String>,
+    /// Extension mappings used by extraction and packing, in addition to built-in ones
+    extensions: Vec<ExtMapping>,
+}
+
+impl Config {
+    /// Load config from `path`, or from default location (if exists) when not given
+    fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match Self::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+        let data = match std::fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read config {}", path.display())),
+        };
+        let config: Self = toml::from_str(&data)
+            .with_context(|| format!("Failed to parse config {}", path.display()))?;
+        config.validate().with_context(|| format!("Invalid config {}", path.display()))?;
+        Ok(config)
+    }
+
+    fn default_path() -> Option<PathBuf> {
+        let config_dir = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))?;
+        Some(config_dir.join("nipaa-pac").join("config.toml"))
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(level) = self.level.filter(|l| *l > PackOptions::MAX_LEVEL) {
+            bail!(PacError::InvalidInput(format!("level {level} is out of range 0..={}", PackOptions::MAX_LEVEL)))
+        }
+        if let Some(format) = &self.image_format {
+            format.parse::<ImageFormat>()?;
         }
+        if let Some(decoding) = &self.lossy_names {
+            decoding.parse::<NameDecoding>()?;
+        }
+        Converters::default().with_mappings(&self.extensions)?;
+        Ok(())
+    }
+
+    /// Use configured values as defaults of matching arguments of all subcommands
+    fn apply(&self, cmd: clap::Command) -> clap::Command {
+        let defaults = [
+            ("level", self.level.map(|l| l.to_string())),
+            ("image_format", self.image_format.clone()),
+            ("jobs", self.jobs.map(|j| j.to_string())),
+            ("lossy_names", self.lossy_names.clone()),
+        ];
+        let names: Vec<String> = cmd.get_subcommands().map(|sub| sub.get_name().to_string()).collect();
+        names.iter().fold(cmd, |cmd, name| cmd.mut_subcommand(name, |mut sub| {
+            for (id, value) in defaults.iter() {
+                let Some(value) = value else {
+                    continue
+                };
+                if sub.get_arguments().any(|arg| arg.get_id() == id) {
+                    sub = sub.mut_arg(id, |arg| arg.default_value(value.clone()));
+                }
+            }
+            sub
+        }))
     }
 }
 
-/// Struct for reading Pac archive
-#[allow(dead_code)]
-#[derive(BinRead)]
-struct PacArc {
-    pub entries_count: u32,
-    #[br(count = entries_count)]
-    pub entries: Vec<PacEntryRead>,
+/// Value of `--config` option, looked up before parsing, so config can provide defaults for parsing
+fn config_arg() -> Option<PathBuf> {
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break
+        }
+        if arg == "--config" {
+            return args.next().map(PathBuf::from)
+        }
+        if let Some(path) = arg.to_str().and_then(|a| a.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path))
+        }
+    }
+    None
 }
 
-/// Entry struct for writing to archive
-#[binwrite]
-#[repr(C)]
-struct PacEntryWrite {
-    pub offset: u32,
-    pub size: u32,
-    #[bw(pad_size_to = ENTRY_NAME_SIZE)]
-    pub name: NullString,
-    #[bw(ignore)]
-    pub data: PacFile,
+const EXIT_CODES: &str = "\
+Exit codes:
+  0  success
+  1  other error
+  2  invalid arguments or request (unknown value, missing or conflicting entry)
+  3  I/O error
+  4  malformed or damaged archive, patch or animation
+  5  entry data can't be decoded (damaged bmz stream, unreadable image)
+  6  name can't be encoded to or decoded from SHIFT-JIS, or is too long
+  7  declared size doesn't fit in file or exceeds safety limit";
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Extract all files from `arc` to `out_dir`, or from several archives into subfolders of `--out-root`
+    #[clap(visible_alias = "x")]
+    Extract {
+        /// .pac archive (`-` reads it from stdin) and out folder (`ARC OUT_DIR`), or, with `--out-root`,
+        /// any number of archives (glob patterns are expanded).
+        /// Out folder will be created if not exists, must be empty unless `--force` or `--merge` given
+        #[clap(required = true, value_name = "ARC")]
+        paths: Vec<String>,
+        /// Extract each archive into folder named after it (without extension) inside this one
+        #[clap(long)]
+        out_root: Option<String>,
+        /// Write converted entries (and manifest) to stdout as tar stream instead of out folder,
+        /// same as giving `-` as out folder
+        #[clap(long, conflicts_with_all = ["out_root", "force", "merge", "skip_existing", "dry_run", "salvage", "keep_raw"])]
+        to_stdout_tar: bool,
+        /// Remove all contents of existing output directory before extraction
+        #[clap(long)]
+        force: bool,
+        /// Extract into existing output directory, overwriting only files with matching names
+        #[clap(long, conflicts_with = "force")]
+        merge: bool,
+        /// Skip entries already extracted to existing output directory (resume interrupted extraction)
+        #[clap(long, conflicts_with = "force")]
+        skip_existing: bool,
+        /// Format of extracted sprites: bmp, png or webp
+        #[clap(long, default_value_t = ImageFormat::Bmp)]
+        image_format: ImageFormat,
+        /// Decode malformed bytes of entry names as replacement characters or, with `hex`, as `%XX`
+        #[clap(long, num_args = 0..=1, default_missing_value = "replace", value_name = "replace|hex")]
+        lossy_names: Option<NameDecoding>,
+        /// Extract only entries with these indices, as shown by `list` (e.g. "0-10,42")
+        #[clap(long)]
+        indices: Option<IndexSet>,
+        /// Extract only entries matching this glob (e.g. "*.bmz"), may be repeated
+        #[clap(long)]
+        include: Vec<Pattern>,
+        /// Skip entries matching this glob, may be repeated
+        #[clap(long)]
+        exclude: Vec<Pattern>,
+        /// Number of parallel workers, 0 means number of CPUs
+        #[clap(short, long, default_value_t = 0)]
+        jobs: usize,
+        /// Don't show progress bar
+        #[clap(short, long)]
+        quiet: bool,
+        /// Memory-map archive instead of reading it (archive must not change meanwhile)
+        #[cfg(feature = "mmap")]
+        #[clap(long)]
+        mmap: bool,
+        /// Read and convert all entries and show what would be written, without touching filesystem
+        #[clap(long)]
+        dry_run: bool,
+        /// Recover as much as possible from damaged archive, reporting damaged entries
+        #[clap(long)]
+        salvage: bool,
+        /// What to do with entries extracted to the same file name (ignoring case):
+        /// suffix with entry index, error or prompt
+        #[clap(long, default_value_t = CollisionPolicy::Suffix, value_name = "suffix|error|prompt")]
+        on_collision: CollisionPolicy,
+        /// Extract entries which names are absolute paths or contain `..` (under sanitized names)
+        /// instead of refusing to extract archive
+        #[clap(long)]
+        trust_names: bool,
+        /// What to do with entry names containing `/` or `\`: preserve (extract into subdirectories)
+        /// or flatten (replace separators with `_`)
+        #[clap(long = "paths", default_value_t = PathMode::Flatten, value_name = "preserve|flatten")]
+        path_mode: PathMode,
+        /// Extract entries of this format as stored in archive: bmz or ttp, may be repeated
+        #[clap(long, value_name = "CONVERTER")]
+        no_convert: Vec<String>,
+        /// Also write converted entries as stored in archive (e.g. `mion01.bmz` next to `mion01.bmp`)
+        #[clap(long)]
+        keep_raw: bool,
+        /// Archive layout: nipaa, or overrides of it like `magic=PAC1,name-size=32` (detected if not given)
+        #[clap(long)]
+        format: Option<PacFormat>,
+        /// Byte order of archive, overrides one of --format (detected if not given)
+        #[clap(long, value_enum, value_name = "be|le")]
+        endian: Option<ByteOrder>,
+        /// Width of entry name field in bytes, overrides one of --format (detected if not given)
+        #[clap(long, value_parser = PacFormat::parse_name_size, value_name = "BYTES")]
+        name_size: Option<usize>,
+    },
+    /// List all files in archive
+    #[clap(visible_alias = "l")]
+    List {
+        /// .pac archive, `-` reads it from stdin
+        arc: String,
+        /// Print entries as json array instead of table
+        #[clap(long)]
+        json: bool,
+        /// Print entries as csv with header row instead of table
+        #[clap(long, conflicts_with = "json")]
+        csv: bool,
+        /// Decode malformed bytes of entry names as replacement characters or, with `hex`, as `%XX`
+        #[clap(long, num_args = 0..=1, default_missing_value = "replace", value_name = "replace|hex")]
+        lossy_names: Option<NameDecoding>,
+        /// Print digest of converted data of each entry: sha256 or crc32
+        #[clap(long, value_enum)]
+        hash: Option<HashKind>,
+        /// Archive layout: nipaa, or overrides of it like `magic=PAC1,name-size=32` (detected if not given)
+        #[clap(long)]
+        format: Option<PacFormat>,
+        /// Byte order of archive, overrides one of --format (detected if not given)
+        #[clap(long, value_enum, value_name = "be|le")]
+        endian: Option<ByteOrder>,
+        /// Width of entry name field in bytes, overrides one of --format (detected if not given)
+        #[clap(long, value_parser = PacFormat::parse_name_size, value_name = "BYTES")]
+        name_size: Option<usize>,
+        /// Order of listed entries; ratio is compressed to uncompressed size of bmz entries
+        #[clap(long, value_enum, default_value_t = ListSort::Index)]
+        sort: ListSort,
+        /// List only entries which names contain this substring
+        #[clap(long)]
+        filter: Option<String>,
+        /// Treat --filter as regular expression
+        #[clap(long, requires = "filter")]
+        regex: bool,
+        /// Print sizes in KiB/MiB instead of bytes
+        #[clap(long, conflicts_with_all = ["json", "csv"])]
+        human: bool,
+        /// Comma-separated columns of table: index, offset, size, magic, ratio, info, hash, name
+        /// (default is index,size,info,name, with hash before name if --hash is given)
+        #[clap(long, value_enum, value_delimiter = ',', conflicts_with_all = ["json", "csv"])]
+        columns: Vec<ListColumn>,
+    },
+    /// Pack directory into archive
+    #[clap(visible_alias = "p")]
+    Pack {
+        /// Result will be saved to this file, `-` writes it to stdout
+        out_arc: String,
+        /// Build archive from this directory
+        #[clap(required_unless_present = "manifest")]
+        src_dir: Option<String>,
+        /// Build archive from files listed in toml manifest instead of directory
+        #[clap(long, conflicts_with_all = ["src_dir", "no_manifest"])]
+        manifest: Option<String>,
+        /// Compression level of bmz files (0 - no compression, 10 - best, max - zopfli, smallest but very slow)
+        #[clap(short, long, default_value_t = PackOptions::DEFAULT_LEVEL,
+            value_parser = PackOptions::parse_level)]
+        level: u8,
+        /// Store images as plain bmp files instead of compressing them to bmz
+        #[clap(long, conflicts_with = "level")]
+        no_compress: bool,
+        /// Ignore extraction manifest, pack all files in name order
+        #[clap(long)]
+        no_manifest: bool,
+        /// Don't show progress bar
+        #[clap(short, long)]
+        quiet: bool,
+        /// Read and convert all sources and show resulting entries, without writing archive
+        #[clap(long)]
+        dry_run: bool,
+        /// What to do with subdirectories: error, flatten (use file names only)
+        /// or join (`dir/file` entry names)
+        #[clap(long, value_enum, default_value_t = Recurse::Error, conflicts_with = "manifest")]
+        recurse: Recurse,
+        /// Shorthand for --recurse: preserve (join, packs directories extracted with `extract --paths preserve`)
+        /// or flatten
+        #[clap(long = "paths", value_name = "preserve|flatten", conflicts_with_all = ["manifest", "recurse"])]
+        path_mode: Option<PathMode>,
+        /// What to do with symbolic links in source directory: follow, skip or error
+        #[clap(long, value_enum, default_value_t = Symlinks::Follow, conflicts_with = "manifest")]
+        symlinks: Symlinks,
+        /// Store identical entry data only once
+        #[clap(long)]
+        dedup: bool,
+        /// Pad entry data to start at offsets that are multiples of N bytes (e.g. 4, 16, 2048)
+        #[clap(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        align: u32,
+        /// Store files this converter would build back (bmz: images, ttp: json) as is, may be repeated
+        #[clap(long, value_name = "CONVERTER")]
+        no_convert: Vec<String>,
+        /// Store all files exactly as given, without any conversion; `.bmz` files must be ZLC3 sprites
+        /// (e.g. extracted with `--no-convert bmz`) and are stored as such
+        #[clap(long, conflicts_with_all = ["no_convert", "level", "no_compress"])]
+        raw: bool,
+        /// Layout of written archive: nipaa, or overrides of it like `magic=PAC1,name-size=32`
+        #[clap(long, default_value_t = PacFormat::default())]
+        format: PacFormat,
+        /// Byte order of written archive, overrides one of --format
+        #[clap(long, value_enum, value_name = "be|le")]
+        endian: Option<ByteOrder>,
+        /// Width of entry name field of written archive in bytes, overrides one of --format
+        #[clap(long, value_parser = PacFormat::parse_name_size, value_name = "BYTES")]
+        name_size: Option<usize>,
+        /// What to do with names that don't fit in name field: error, truncate or hash
+        /// (truncate and append hash of full name). Full names are saved to `<out_arc>.names.json`
+        #[clap(long, default_value_t = LongNamePolicy::Error, value_name = "truncate|hash|error")]
+        long_names: LongNamePolicy,
+        /// Keep running and repack whenever source files change
+        #[cfg(feature = "watch")]
+        #[clap(long, conflicts_with = "dry_run")]
+        watch: bool,
+    },
+    /// Append files to existing archive
+    Add {
+        /// .pac archive, will be rewritten
+        arc: String,
+        /// Files to add, converted same way as when packing
+        #[clap(required = true)]
+        files: Vec<String>,
+    },
+    /// Remove entries from archive
+    #[clap(visible_alias = "rm")]
+    Remove {
+        /// .pac archive
+        arc: String,
+        /// Names of entries to remove
+        #[clap(required = true)]
+        entries: Vec<String>,
+        /// Result will be saved to this file
+        #[clap(short, long, required_unless_present = "in_place", conflicts_with = "in_place")]
+        out: Option<String>,
+        /// Overwrite source archive
+        #[clap(long)]
+        in_place: bool,
+    },
+    /// Replace data of single entry, leaving other entries untouched
+    Replace {
+        /// .pac archive
+        arc: String,
+        /// Name of entry to replace
+        entry: String,
+        /// New file, converted same way as when packing
+        file: String,
+        /// Result will be saved to this file, archive is rewritten in place if not specified
+        #[clap(short, long)]
+        out: Option<String>,
+    },
+    /// Rename entry, leaving its data untouched
+    #[clap(visible_alias = "mv")]
+    Rename {
+        /// .pac archive
+        arc: String,
+        /// Current name of entry
+        old: String,
+        /// New name of entry
+        new: String,
+        /// Result will be saved to this file, archive is rewritten in place if not specified
+        #[clap(short, long)]
+        out: Option<String>,
+    },
+    /// Correct BMZ headers declaring wrong uncompressed size
+    Fix {
+        /// .pac archive
+        arc: String,
+        /// Result will be saved to this file, archive is rewritten in place if not specified
+        #[clap(short, long)]
+        out: Option<String>,
+        /// Only report wrong headers, don't write anything
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Deflate BMZ entries again at maximum level, keeping streams that became smaller
+    Optimize {
+        /// .pac archive
+        in_arc: String,
+        /// Optimized .pac archive, will be created
+        out_arc: String,
+        /// Try several deflate strategies and levels per entry, keeping the smallest stream (slow)
+        #[clap(long)]
+        exhaustive: bool,
+        /// Print every recompressed entry
+        #[clap(short, long)]
+        verbose: bool,
+    },
+    /// Combine several archives into one
+    Merge {
+        /// Output .pac archive
+        out_arc: String,
+        /// Archives to merge, later ones are laid over earlier
+        #[clap(required = true, num_args = 2..)]
+        arcs: Vec<String>,
+        /// What to do with entries present in several archives: last, first or error
+        #[clap(long, default_value_t = ConflictPolicy::Last)]
+        on_conflict: ConflictPolicy,
+    },
+    /// Archive patches
+    Patch {
+        #[clap(subcommand)]
+        cmd: PatchCommands,
+    },
+    /// Convert archive to .zip with converted entries (as on extraction),
+    /// or build archive from .zip (as when packing directory)
+    Convert {
+        /// .pac archive, or .zip to build archive from
+        input: String,
+        /// .zip or .pac archive, will be created
+        output: String,
+        /// Format of sprites written to zip: bmp, png or webp
+        #[clap(long, default_value_t = ImageFormat::Bmp)]
+        image_format: ImageFormat,
+        /// Decode malformed bytes of entry names as replacement characters or, with `hex`, as `%XX`
+        #[clap(long, num_args = 0..=1, default_missing_value = "replace", value_name = "replace|hex")]
+        lossy_names: Option<NameDecoding>,
+        /// Compression level of bmz files, when building archive from zip
+        #[clap(short, long, default_value_t = PackOptions::DEFAULT_LEVEL,
+            value_parser = PackOptions::parse_level)]
+        level: u8,
+    },
+    /// Mount archive as read-only directory of converted files, until unmounted
+    #[cfg(feature = "fuse")]
+    Mount {
+        /// .pac archive
+        arc: String,
+        /// Empty directory to mount at
+        mountpoint: String,
+        /// Format of sprites: bmp, png or webp
+        #[clap(long, default_value_t = ImageFormat::Bmp)]
+        image_format: ImageFormat,
+        /// Decode malformed bytes of entry names as replacement characters or, with `hex`, as `%XX`
+        #[clap(long, num_args = 0..=1, default_missing_value = "replace", value_name = "replace|hex")]
+        lossy_names: Option<NameDecoding>,
+    },
+    /// Pack sample directory at every compression level, reporting time and archive size per level
+    Bench {
+        /// Source directory, as for `pack`
+        src_dir: String,
+        /// What to do with subdirectories, as for `pack`
+        #[clap(long, value_enum, default_value_t = Recurse::Error)]
+        recurse: Recurse,
+    },
+    /// Extract archive to temporary directory, pack it back and compare result with original
+    Roundtrip {
+        /// .pac archive
+        arc: String,
+        /// Format of extracted sprites: bmp, png or webp
+        #[clap(long, default_value_t = ImageFormat::Bmp)]
+        image_format: ImageFormat,
+        /// Compression level used for repacking
+        #[clap(short, long, default_value_t = PackOptions::DEFAULT_LEVEL,
+            value_parser = PackOptions::parse_level)]
+        level: u8,
+    },
+    /// Check archive integrity
+    Verify {
+        /// .pac archives (glob patterns are expanded)
+        #[clap(required = true)]
+        arcs: Vec<String>,
+        /// Archive layout: nipaa, or overrides of it like `magic=PAC1,name-size=32` (detected if not given)
+        #[clap(long)]
+        format: Option<PacFormat>,
+        /// Byte order of archive, overrides one of --format (detected if not given)
+        #[clap(long, value_enum, value_name = "be|le")]
+        endian: Option<ByteOrder>,
+        /// Width of entry name field in bytes, overrides one of --format (detected if not given)
+        #[clap(long, value_parser = PacFormat::parse_name_size, value_name = "BYTES")]
+        name_size: Option<usize>,
+    },
+    /// Compare directory extracted from archive with it, reporting files that were modified, deleted or added
+    VerifyExtract {
+        /// .pac archive
+        arc: String,
+        /// Directory archive was extracted to
+        dir: String,
+        /// Format sprites were extracted in, used to name expected files if directory has no extraction manifest
+        #[clap(long, default_value_t = ImageFormat::Bmp)]
+        image_format: ImageFormat,
+        /// Compare entries of this format as stored in archive: bmz or ttp, may be repeated
+        #[clap(long, value_name = "CONVERTER")]
+        no_convert: Vec<String>,
+        /// How entry names with path separators were extracted, used if directory has no extraction manifest
+        #[clap(long = "paths", default_value_t = PathMode::Flatten, value_name = "preserve|flatten")]
+        path_mode: PathMode,
+    },
+    /// Identify files by content: pac archive (of any supported layout), bmz sprite, ttp animation or unknown
+    Detect {
+        /// Files to identify (glob patterns are expanded)
+        #[clap(required = true)]
+        files: Vec<String>,
+    },
+    /// Show differences between two archives
+    Diff {
+        /// Old .pac archive
+        old: String,
+        /// New .pac archive
+        new: String,
+        /// Compare entry contents too, detects renamed entries and changes that keep size
+        #[clap(long)]
+        hash: bool,
+    },
+    /// Write single entry to stdout
+    Cat {
+        /// .pac archive
+        arc: String,
+        /// Name of entry, as shown by `list`
+        entry: String,
+        /// Write converted data (bmp, json, ...) instead of raw entry bytes
+        #[clap(long)]
+        convert: bool,
+        /// Write entries of this format as stored even with --convert: bmz or ttp, may be repeated
+        #[clap(long, value_name = "CONVERTER")]
+        no_convert: Vec<String>,
+    },
+    /// Show archive-wide totals, compression ratio, sizes per extension and largest entries
+    Stats {
+        /// .pac archive
+        arc: String,
+        /// Number of largest entries to show
+        #[clap(long, default_value_t = 10)]
+        top: usize,
+        /// Print statistics as json object
+        #[clap(long)]
+        json: bool,
+    },
+    /// Show detailed information about single entry
+    Info {
+        /// .pac archive
+        arc: String,
+        /// Name of entry, as shown by `list`
+        entry: String,
+    },
+    /// Print hex dump of single entry
+    Hexdump {
+        /// .pac archive
+        arc: String,
+        /// Name of entry, as shown by `list`
+        entry: String,
+        /// Start dumping at this offset
+        #[clap(long, default_value_t = 0)]
+        offset: usize,
+        /// Dump at most this many bytes
+        #[clap(long)]
+        len: Option<usize>,
+        /// Dump converted data (bmp, json, ...) instead of raw entry bytes
+        #[clap(long)]
+        converted: bool,
+        /// Dump entries of this format as stored even with --converted: bmz or ttp, may be repeated
+        #[clap(long, value_name = "CONVERTER")]
+        no_convert: Vec<String>,
+    },
+    /// Search entry contents (decompressed for bmz) for string or bytes
+    Grep {
+        /// .pac archive
+        arc: String,
+        /// String to search for, both in SHIFT-JIS and UTF-8 encodings
+        pattern: String,
+        /// Treat pattern as hex bytes (e.g. "81 40" or "8140")
+        #[clap(long)]
+        hex: bool,
+    },
+    /// Tile thumbnails of all sprites into labeled contact sheet
+    Thumbs {
+        /// .pac archive, `-` reads it from stdin
+        arc: String,
+        /// Result image, format is chosen by extension (png, webp, bmp)
+        out_image: String,
+        /// Sprites are scaled down to fit into square of this size
+        #[clap(long, default_value_t = SheetOptions::default().thumb_size)]
+        size: u32,
+        /// Number of thumbnails per row
+        #[clap(long, default_value_t = SheetOptions::default().columns)]
+        columns: u32,
+    },
+    /// Work with standalone .ttp animation files
+    Ttp {
+        #[clap(subcommand)]
+        cmd: TtpCommands,
+    },
+}
+
+/// Animation file operations
+#[derive(Subcommand)]
+enum TtpCommands {
+    /// Decode binary `ttp` animation to pretty-printed json, yaml or toml
+    Decode {
+        /// .ttp animation
+        ttp: String,
+        /// Result will be saved to this file
+        out_text: String,
+        /// Format of result, guessed from its extension if not specified (json by default)
+        #[clap(long, value_enum)]
+        format: Option<TextFormat>,
+    },
+    /// Encode json, yaml or toml animation back to binary `ttp`
+    Encode {
+        /// .json, .yaml or .toml animation, as produced by `decode`
+        text: String,
+        /// Result will be saved to this file
+        out_ttp: String,
+        /// Format of source, guessed from its extension if not specified (json by default)
+        #[clap(long, value_enum)]
+        format: Option<TextFormat>,
+    },
+    /// Check that sprites, sounds and textboxes referenced by animations exist in archive,
+    /// and report archive resources no animation uses
+    Check {
+        /// .ttp animations (or .json/.yaml/.toml, as produced by `decode`), all animations from archive if none given
+        ttp: Vec<String>,
+        /// .pac archive with resources
+        #[clap(long)]
+        arc: String,
+    },
+    /// Print overview of animation: header fields, duration, frame delays and used resources
+    Summary {
+        /// .ttp animation (or .json/.yaml/.toml, as produced by `decode`)
+        ttp: String,
+    },
+    /// Change frame delays: scale them, raise to minimum or set to fixed value
+    Retime {
+        /// .ttp animation (or .json/.yaml/.toml, as produced by `decode`)
+        ttp: String,
+        /// Multiply all delays by this factor (e.g. 0.5 plays twice as fast)
+        #[clap(long, conflicts_with = "set_delay")]
+        scale: Option<f64>,
+        /// Raise delays shorter than this, applied after scaling
+        #[clap(long, value_name = "MS")]
+        min_delay: Option<u32>,
+        /// Set all delays to this value
+        #[clap(long, value_name = "MS")]
+        set_delay: Option<u32>,
+        /// Result will be saved to this file, animation is rewritten in place if not specified
+        #[clap(short, long)]
+        out: Option<String>,
+    },
+    /// Insert, delete, duplicate or move frames
+    Frames {
+        #[clap(subcommand)]
+        cmd: FrameCommands,
+    },
+    /// Render animation into animated image, using sprites from archive
+    Render {
+        /// .ttp animation (or .json/.yaml/.toml, as produced by `decode`)
+        ttp: String,
+        /// .pac archive with sprites
+        #[clap(long)]
+        arc: String,
+        /// Result will be saved to this file
+        #[clap(long)]
+        out: String,
+        /// Format of result: gif, apng or webp
+        #[clap(long, default_value_t = AnimFormat::Gif)]
+        format: AnimFormat,
+    },
+    /// Pack rendered frames into single sprite sheet image with json atlas
+    /// (TexturePacker / Aseprite array layout: frame rectangles, offsets in window, delays)
+    Sheet {
+        /// .ttp animation (or .json/.yaml/.toml, as produced by `decode`)
+        ttp: String,
+        /// .pac archive with sprites
+        #[clap(long)]
+        arc: String,
+        /// Sheet image (png)
+        #[clap(long)]
+        out: PathBuf,
+        /// Atlas file, defaults to sheet path with .json extension
+        #[clap(long)]
+        atlas: Option<PathBuf>,
+    },
 }
 
-impl PacEntryWrite {
-    pub const SIZE: usize = 64;
+/// Frame editing operations, `frame_count` is updated accordingly.
+/// Animations are .ttp (or .json/.yaml/.toml, as produced by `ttp decode`) and are rewritten in place unless `-o` is given
+#[derive(Subcommand)]
+enum FrameCommands {
+    /// Insert new frame before frame at index (or at the end if index equals frame count)
+    Insert {
+        ttp: String,
+        index: usize,
+        /// Frame as json object, in the same form as in `ttp decode` output
+        frame: String,
+        #[clap(short, long)]
+        out: Option<String>,
+    },
+    /// Delete frame at index
+    Delete {
+        ttp: String,
+        index: usize,
+        #[clap(short, long)]
+        out: Option<String>,
+    },
+    /// Insert copy of frame right after it
+    Duplicate {
+        ttp: String,
+        index: usize,
+        #[clap(short, long)]
+        out: Option<String>,
+    },
+    /// Move frame to another position
+    Move {
+        ttp: String,
+        from: usize,
+        to: usize,
+        #[clap(short, long)]
+        out: Option<String>,
+    },
 }
 
-/// Builder for Pac archives
-struct PacArcBuilder {
-    pub entries: Vec<PacEntryWrite>,
+/// Text representation of animations
+#[derive(Clone, Copy, ValueEnum)]
+enum TextFormat {
+    Json,
+    Yaml,
+    Toml,
 }
 
-impl PacArcBuilder {
-    /// Create new builder
-    pub fn new() -> Self {
-        Self {
-            entries: vec![],
+impl TextFormat {
+    /// Guess format from file extension
+    fn from_path(path: &str) -> Option<Self> {
+        let ext = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "json" => Some(TextFormat::Json),
+            "yaml" | "yml" => Some(TextFormat::Yaml),
+            "toml" => Some(TextFormat::Toml),
+            _ => None,
         }
     }
 
-    /// Add new entry to archive
-    pub fn add_entry(&mut self, file: PacFile, name: &str) -> Result<()> {
-        let enc_name = match SHIFT_JIS.encode(name) {
-            (cow, _, false) if cow.len() < ENTRY_NAME_SIZE => cow.to_vec(),
-            (_, _, true) => bail!("Failed to encode entry name: {name}"),
-            (cow, _, false) => 
-                bail!("Too long entry name ({}): {name} (must not exceed {ENTRY_NAME_SIZE} bytes)", cow.len())
-        };
-        
-        let e = PacEntryWrite {
-            name: NullString(enc_name),
-            data: file,
-            offset: 0,
-            size: 0,
-        };
+    fn parse(self, data: &[u8]) -> Result<TtpFile> {
+        Ok(match self {
+            TextFormat::Json => serde_json::from_slice(data)?,
+            TextFormat::Yaml => serde_yaml::from_slice(data)?,
+            TextFormat::Toml => toml::from_str(std::str::from_utf8(data)?)?,
+        })
+    }
 
-        self.entries.push(e);
+    fn to_string(self, ttp: &TtpFile) -> Result<String> {
+        Ok(match self {
+            TextFormat::Json => serde_json::to_string_pretty(ttp)?,
+            TextFormat::Yaml => serde_yaml::to_string(ttp)?,
+            TextFormat::Toml => toml::to_string(ttp)?,
+        })
+    }
+}
 
-        Ok(())
+/// Format of command output
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable messages and progress bars
+    Text,
+    /// One json event per line
+    Ndjson,
+}
+
+/// Event of `--output ndjson` stream
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+enum Event<'a> {
+    EntryExtracted { archive: &'a str, name: &'a str, entries_done: usize, entries_total: usize, bytes_written: u64 },
+    /// Damaged entry found by `extract --salvage` or `verify`, `index` is missing for problems of whole archive
+    EntryFailed { archive: &'a str, index: Option<usize>, name: &'a str, message: &'a str },
+    ExtractComplete { archive: &'a str, out_dir: &'a str, entries: usize, problems: usize, dry_run: bool },
+    EntryPacked { name: &'a str, entries_done: usize, entries_total: usize, bytes_written: u64 },
+    PackComplete { archive: &'a str, entries: usize, bytes_written: u64, dedup_saved: u64 },
+    VerifyComplete { archive: &'a str, entries: usize, bmz_checked: usize, problems: usize },
+    EntryListed { archive: &'a str, #[serde(flatten)] row: &'a ListRow },
+    ListComplete { archive: &'a str, entries: usize },
+    EntryInfo {
+        archive: &'a str,
+        name: &'a str,
+        #[serde(rename = "type")]
+        typ: &'a str,
+        offset: u32,
+        size: u32,
+        uncompressed_size: Option<u32>,
+        sha256: &'a str,
+        width: Option<i32>,
+        height: Option<i32>,
+        bit_depth: Option<u16>,
+    },
+    /// Archive written by `command`
+    ArchiveWritten { command: &'a str, archive: &'a str, entries: usize, bytes_written: u64 },
+    /// Last event of successful command
+    Complete { command: &'a str },
+    Error { message: &'a str, exit_code: u8 },
+}
+
+impl Event<'_> {
+    /// Write event as single line to stdout
+    fn emit(&self) {
+        println!("{}", serde_json::to_string(self).expect("events are serializable"));
     }
+}
 
-    /// Pack all entries to archive
-    pub fn pack(self, out_path: &str) -> Result<()> {
-        let mut out = File::create(out_path)?;
+/// Handling of subdirectories when packing directory
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Recurse {
+    /// Refuse to pack directory with subdirectories
+    Error,
+    /// Pack files from all subdirectories under their own names
+    Flatten,
+    /// Pack files from all subdirectories, joining path components into entry name
+    Join,
+}
 
-        out.write_le(&(self.entries.len() as u32))?;
+/// Handling of symbolic links when packing directory
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Symlinks {
+    /// Pack file or directory link points to
+    Follow,
+    /// Leave links out of archive
+    Skip,
+    /// Refuse to pack directory containing links
+    Error,
+}
 
-        let mut header_buff = Cursor::new(vec![]);
-        let mut data_buff = Cursor::new(vec![]);
-        
-        let mut current_offset = 
-            (PacEntryWrite::SIZE * self.entries.len() + 4) as u32;
+/// Byte order of archive numbers
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ByteOrder {
+    /// Big-endian, ports to consoles
+    Be,
+    /// Little-endian, original PC releases
+    Le,
+}
 
-        for mut entry in self.entries {
-            entry.offset = current_offset;
+impl From<ByteOrder> for Endian {
+    fn from(order: ByteOrder) -> Self {
+        match order {
+            ByteOrder::Be => Endian::Big,
+            ByteOrder::Le => Endian::Little,
+        }
+    }
+}
+
+/// Layout given by --format, --endian and --name-size options, `None` if whole layout should be detected.
+/// With only --endian or --name-size, layout is detected among ones of that byte order and name field width
+fn layout<S: PacSource + ?Sized>(
+    source: &S,
+    format: Option<&PacFormat>,
+    endian: Option<ByteOrder>,
+    name_size: Option<usize>,
+) -> Result<Option<PacFormat>> {
+    let endian = endian.map(Endian::from);
+    Ok(match format {
+        Some(format) => Some(override_layout(format, endian, name_size)),
+        None if endian.is_none() && name_size.is_none() => None,
+        None => Some(PacFormat::detect_with(source, endian, name_size)?
+            .unwrap_or_else(|| override_layout(&PacFormat::default(), endian, name_size))),
+    })
+}
+
+/// `format` with byte order and name field width replaced by given ones
+fn override_layout(format: &PacFormat, endian: Option<Endian>, name_size: Option<usize>) -> PacFormat {
+    PacFormat {
+        endian: endian.unwrap_or(format.endian),
+        name_size: name_size.unwrap_or(format.name_size),
+        ..format.clone()
+    }
+}
+
+/// Digest algorithm for entry listings
+#[derive(Clone, Copy, ValueEnum)]
+enum HashKind {
+    Sha256,
+    Crc32,
+}
 
-            // record size of written entry
-            let current = data_buff.position();
-            data_buff.write_le(&entry.data)?;
-            let size = (data_buff.position() - current) as u32;
-            
-            entry.size = size;
-            current_offset += size;
-            header_buff.write_le(&entry)?;
+impl HashKind {
+    /// Hex-encoded digest of `data`
+    fn digest(self, data: &[u8]) -> String {
+        match self {
+            HashKind::Sha256 => Sha256::digest(data).iter().map(|b| format!("{b:02x}")).collect(),
+            HashKind::Crc32 => format!("{:08x}", crc32fast::hash(data)),
         }
+    }
 
-        out.write_le(&header_buff.into_inner())?;
-        out.write_le(&data_buff.into_inner())?;
-                
-        Ok(())
+    /// Length of hex-encoded digest
+    fn width(self) -> usize {
+        match self {
+            HashKind::Sha256 => 64,
+            HashKind::Crc32 => 8,
+        }
     }
 }
 
-impl PacArc {
-    /// Extract and convert all files
-    pub fn extract_all(&self, out_dir: &str) -> Result<()> {
-        for entry in self.entries.iter() {
-            let name = entry.name()?;
-            // Replace file name and extension
-            let path = Path::new(&format!("{out_dir}/x"))
-                .with_file_name(&name)
-                .with_extension(PacFile::converted_ext(
-                    Path::new(&name).extension().and_then(|e| e.to_str()).unwrap_or("") 
-                ));
+/// Order of `list` output
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ListSort {
+    Name,
+    Size,
+    Index,
+    Ratio,
+}
 
-            std::fs::write(path, entry.file.converted_data().context("Failed to extract {path}")?)?;
-        }   
-        Ok(()) 
-    } 
+/// Column of `list` table
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ListColumn {
+    Index,
+    Offset,
+    Size,
+    /// First 4 bytes of stored data
+    Magic,
+    /// Stored size in percents of uncompressed one, for bmz entries
+    Ratio,
+    Info,
+    Hash,
+    Name,
 }
 
-/// Representation of files found in archive
-#[derive(BinRead, BinWrite)]
-#[br(import(size: u32))]
-enum PacFile {
-    /// BMP file compressed with zlib 
-    #[brw(magic = b"ZLC3")]
-    Bmz {
-        uncompressed_size: u32, 
-        #[br(count = size - Self::BMZ_HEADER_SIZE as u32)]
-        compressed_data: Vec<u8>,
+impl ListColumn {
+    /// Width of column including separating spaces
+    fn width(self, hash: HashKind, human: bool) -> usize {
+        match self {
+            ListColumn::Index => 6,
+            ListColumn::Offset => 10,
+            ListColumn::Size if human => 12,
+            ListColumn::Size => 10,
+            ListColumn::Magic => 10,
+            ListColumn::Ratio => 8,
+            ListColumn::Info if human => 60,
+            ListColumn::Info => 56,
+            ListColumn::Hash => hash.width() + 2,
+            ListColumn::Name => 0,
+        }
+    }
+}
+
+/// `len` bytes, or KiB/MiB if `human`
+fn format_size(len: u32, human: bool) -> String {
+    match human {
+        true => HumanBytes(len as u64).to_string(),
+        false => len.to_string(),
+    }
+}
+
+/// Magic bytes as text if printable, hex otherwise
+fn format_magic(head: &[u8]) -> String {
+    match head.iter().all(|b| b.is_ascii_graphic()) {
+        true => String::from_utf8_lossy(head).into_owned(),
+        false => head.iter().map(|b| format!("{b:02x}")).collect(),
+    }
+}
+
+/// Description of entry content for `list` table
+fn list_info(entry: &PacEntryRead, file: &PacFile, human: bool) -> String {
+    match file {
+        PacFile::Bmz { uncompressed_size, .. } => {
+            let ratio = compression_ratio(entry, file);
+            format!(
+                "bmz uncompressed size: {} ({:.1}%{})",
+                format_size(*uncompressed_size, human), 100.0 * ratio, if ratio >= 1.0 { ", incompressible" } else { "" },
+            )
+        },
+        PacFile::Other { .. } =>  "other file".into(),
+        PacFile::Ttp(TtpFile {
+            maybe_ttp_type: typ,
+            frame_count: fcnt,
+            window_width: w,
+            window_height: h,
+            .. 
+        }) => format!("ttp type?: {typ:<3} w: {w:<4} h: {h:<4} frames: {fcnt}"),
+    }
+}
+
+/// Compressed to uncompressed size, 1 for entries stored uncompressed
+fn compression_ratio(entry: &PacEntryRead, file: &PacFile) -> f64 {
+    match file.uncompressed_size() {
+        Some(uncompressed) if uncompressed > 0 => entry.size as f64 / uncompressed as f64,
+        _ => 1.0,
+    }
+}
+
+/// Patch operations
+#[derive(Subcommand)]
+enum PatchCommands {
+    /// Create patch containing only entries changed between two archives
+    Create {
+        /// Original .pac archive
+        base: String,
+        /// Modified .pac archive
+        modified: String,
+        /// Patch will be saved to this file
+        out_patch: String,
     },
-    Ttp(TtpFile),
-    Other {
-        #[br(count = size, err_context("size = {}", size))]
-        data: Vec<u8>
+    /// Rebuild modified archive from original one and patch
+    Apply {
+        /// Original .pac archive
+        base: String,
+        /// .nppatch file, as produced by `patch create`
+        patch: String,
+        /// Result will be saved to this file
+        out_arc: String,
+    },
+}
+
+/// Entry description for machine-readable listings
+#[derive(Serialize)]
+struct ListRow {
+    index: usize,
+    /// `None` if name can't be decoded
+    name: Option<String>,
+    offset: u32,
+    size: u32,
+    /// Only known for bmz entries
+    uncompressed_size: Option<u32>,
+    /// Stored to uncompressed size, for bmz entries
+    ratio: Option<f64>,
+    #[serde(rename = "type")]
+    typ: &'static str,
+    /// Digest of converted data, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+}
+
+impl ListRow {
+    fn new(
+        index: usize,
+        entry: &PacEntryRead,
+        file: &PacFile,
+        name_decoding: NameDecoding,
+        hash: Option<HashKind>,
+    ) -> Result<Self> {
+        Ok(Self {
+            index,
+            name: entry.decode_name(name_decoding).ok(),
+            offset: entry.offset,
+            size: entry.size,
+            uncompressed_size: file.uncompressed_size(),
+            ratio: file.uncompressed_size().map(|_| compression_ratio(entry, file)),
+            typ: file.kind(),
+            hash: hash.map(|h| file.converted_data().map(|d| h.digest(&d))).transpose()?,
+        })
     }
 }
 
-impl PacFile {
-    const BMZ_HEADER_SIZE: usize = 8;
+/// Archive-wide metrics printed by `stats`
+#[derive(Serialize, Default)]
+struct ArchiveStats {
+    entries: usize,
+    stored_size: u64,
+    /// Sizes of bmz data after decompression, of other entries as stored
+    uncompressed_size: u64,
+    /// Stored to uncompressed size
+    ratio: f64,
+    /// Lowercase extension (empty for names without one) to totals of its entries
+    extensions: BTreeMap<String, ExtensionStats>,
+    largest: Vec<LargestEntry>,
+}
 
-    /// Get converted data
-    pub fn converted_data(&self) -> Result<Vec<u8>> {
-        match self {
-            PacFile::Bmz { compressed_data, .. } => {
-                match decompress_to_vec_zlib(compressed_data) {
-                    Ok(data) => Ok(data),
-                    Err(e) => bail!(e),
-                }
-            },
-            PacFile::Other { data } => Ok(data.clone()),
-            PacFile::Ttp(ttp) => Ok(serde_json::to_string_pretty(ttp)?.into_bytes()),
+#[derive(Serialize, Default)]
+struct ExtensionStats {
+    entries: usize,
+    stored_size: u64,
+    uncompressed_size: u64,
+}
+
+#[derive(Serialize)]
+struct LargestEntry {
+    index: usize,
+    name: String,
+    size: u32,
+}
+
+impl ArchiveStats {
+    fn collect<S: PacSource>(arc: &PacArc<S>, top: usize) -> Result<Self> {
+        let mut stats = Self { entries: arc.entries.len(), ..Default::default() };
+        for entry in arc.entries.iter() {
+            let file = arc.read_file(entry)?;
+            let uncompressed = file.uncompressed_size().unwrap_or(entry.size) as u64;
+            stats.stored_size += entry.size as u64;
+            stats.uncompressed_size += uncompressed;
+            let name = entry.decode_name(NameDecoding::Replace)?;
+            let ext = Path::new(&name).extension().map_or(String::new(), |e| e.to_string_lossy().to_lowercase());
+            let ext = stats.extensions.entry(ext).or_default();
+            ext.entries += 1;
+            ext.stored_size += entry.size as u64;
+            ext.uncompressed_size += uncompressed;
+        }
+        stats.ratio = stats.stored_size as f64 / stats.uncompressed_size.max(1) as f64;
+
+        let mut by_size: Vec<_> = arc.entries.iter().enumerate().collect();
+        by_size.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.size));
+        for (index, entry) in by_size.into_iter().take(top) {
+            let name = entry.decode_name(NameDecoding::Replace)?;
+            stats.largest.push(LargestEntry { index, name, size: entry.size });
         }
+        Ok(stats)
     }
 
-    /// Get original (packed) extension
-    pub fn original_ext(conv_ext: &str) -> &str {
-        match conv_ext {
-            "bmp" => "bmz",
-            "json" => "ttp",
-            other => other,
+    fn print(&self) {
+        say!("entries:           {}", self.entries);
+        say!("stored size:       {} ({})", self.stored_size, HumanBytes(self.stored_size));
+        say!("uncompressed size: {} ({})", self.uncompressed_size, HumanBytes(self.uncompressed_size));
+        say!("compression ratio: {:.2}%", 100.0 * self.ratio);
+
+        say!();
+        say!("{:<10}{:<9}{:<13}uncompressed", "extension", "entries", "stored");
+        let max_size = self.extensions.values().map(|e| e.stored_size).max().unwrap_or(0).max(1);
+        for (ext, e) in self.extensions.iter() {
+            let ext = if ext.is_empty() { "(none)" } else { ext };
+            let bar = "#".repeat((30 * e.stored_size).div_ceil(max_size) as usize);
+            say!(
+                "{ext:<10}{:<9}{:<13}{:<15}{bar}",
+                e.entries, HumanBytes(e.stored_size).to_string(), HumanBytes(e.uncompressed_size).to_string(),
+            );
         }
+
+        if !self.largest.is_empty() {
+            say!();
+            say!("{:<6}{:<13}name", "index", "size");
+            for e in self.largest.iter() {
+                say!("{:<6}{:<13}{}", e.index, HumanBytes(e.size as u64).to_string(), e.name);
+            }
+        }
+    }
+}
+
+/// Progress bar for processing of `len` entries, hidden if `quiet`
+fn progress_bar(len: usize, quiet: bool) -> ProgressBar {
+    if quiet {
+        return ProgressBar::hidden()
+    }
+
+    ProgressBar::new(len as u64).with_style(
+        ProgressStyle::with_template("{prefix} {wide_bar} {pos}/{len} entries, {msg}, ETA {eta}")
+            .expect("valid template")
+    )
+}
+
+/// Call `on_change` whenever something changes in `dir` (recursively), until interrupted.
+/// Changes of `ignored` file (archive being written) don't count
+#[cfg(feature = "watch")]
+fn watch_changes(dir: &Path, ignored: &Path, mut on_change: impl FnMut()) -> Result<()> {
+    use notify::{RecursiveMode, Watcher};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let ignored = ignored.canonicalize()?;
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(&dir.canonicalize()?, RecursiveMode::Recursive)?;
+    say!("Watching {} for changes, press Ctrl+C to stop", dir.display());
+
+    let relevant = |event: notify::Result<notify::Event>| match event {
+        Ok(event) => !event.kind.is_access() && event.paths.iter().any(|p| *p != ignored),
+        Err(e) => {
+            eprintln!("Watch error: {e}");
+            false
+        },
+    };
+    loop {
+        if !relevant(rx.recv()?) {
+            continue
+        }
+        // editors tend to produce bursts of events on save
+        while rx.recv_timeout(Duration::from_millis(300)).is_ok() {}
+        on_change();
     }
+}
 
-    /// Get converted (extracted) extension
-    pub fn converted_ext(orig_ext: &str) -> &str {
-        match orig_ext {
-            "bmz" => "bmp",
-            "ttp" => "json",
-            other => other,
+/// Expand glob patterns in `args` (for shells that don't do this), other arguments are kept as is
+fn expand_globs(args: &[String]) -> Result<Vec<String>> {
+    let mut paths = vec![];
+    for arg in args {
+        if !arg.contains(['*', '?', '[']) {
+            paths.push(arg.clone());
+            continue
+        }
+        let matched = glob::glob(arg)?
+            .map(|path| Ok(path?.to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>>>()?;
+        if matched.is_empty() {
+            bail!(PacError::InvalidInput(format!("no files match {arg}")))
         }
+        paths.extend(matched);
     }
+    Ok(paths)
+}
+
+/// Read whole stdin, for archives given as `-`
+fn read_stdin() -> Result<Vec<u8>> {
+    let mut data = vec![];
+    std::io::stdin().lock().read_to_end(&mut data).context("Failed to read stdin")?;
+    Ok(data)
+}
+
+/// Open archive at `path`, or read it from stdin if `path` is `-`
+/// Emit `archive-written` event for archive written by `command`, if stdout carries event stream
+fn written(command: &str, archive: &str, stats: &PackStats) {
+    if EVENT_STREAM.load(Ordering::Relaxed) {
+        Event::ArchiveWritten { command, archive, entries: stats.entries, bytes_written: stats.bytes_written }.emit();
+    }
+}
 
+/// Write archive edited from `arc` to `out`, or back to `arc` in place if `out` is not given or is the same file
+fn write_edited(builder: PacArcBuilder, arc: &str, out: Option<&str>) -> Result<PackStats> {
+    match out {
+        Some(out) if !same_file(arc, out) => builder.pack(out),
+        _ => builder.update(arc),
+    }
+}
 
-    /// Try to build file from raw data.
-    /// Expects extension of converted file
-    pub fn convert_back(data: Vec<u8>, conv_extension: &str) -> Result<Self> {
-        match conv_extension {
-            "bmp" => {
-                let uncompressed_size = data.len() as u32;
-                let compressed_data = compress_to_vec_zlib(&data, 5);
-                Ok(PacFile::Bmz { uncompressed_size, compressed_data })                
+fn same_file(a: impl AsRef<Path>, b: impl AsRef<Path>) -> bool {
+    match (a.as_ref().canonicalize(), b.as_ref().canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn open_arc(path: &str) -> Result<PacArc<DynSource>> {
+    open_arc_as(path, None, None, None)
+}
+
+/// Same as `open_arc`, with layout given by --format and --endian options
+fn open_arc_as(path: &str, format: Option<&PacFormat>, endian: Option<ByteOrder>, name_size: Option<usize>) -> Result<PacArc<DynSource>> {
+    let open = |source: DynSource| match layout(&*source, format, endian, name_size)? {
+        Some(format) => PacArc::from_source_as(source, format),
+        None => PacArc::from_source(source),
+    };
+    if path == "-" {
+        return open(Box::new(read_stdin()?)).context("Failed to read archive from stdin")
+    }
+    File::open(path)
+        .map_err(Into::into)
+        .and_then(|f| open(Box::new(ReaderSource::new(BufReader::new(f)))))
+        .with_context(|| format!("Failed to open archive {path}"))
+}
+
+/// Collect files to pack from `src_dir`.
+/// Files listed in extraction manifest (if `use_manifest`) go first in manifest order
+/// and keep their original entry names, then all other files in name order
+fn collect_sources(
+    src_dir: &Path,
+    use_manifest: bool,
+    recurse: Recurse,
+    symlinks: Symlinks,
+    opts: &PackOptions,
+) -> Result<Vec<PackSource>> {
+    let mut files = vec![];
+    walk_dir(src_dir, "", recurse, symlinks, &mut files)?;
+    files.retain(|(path, _)| *path != src_dir.join(ExtractManifest::FILE_NAME));
+    files.sort();
+    if recurse == Recurse::Flatten {
+        let mut names: Vec<_> = files.iter().map(|(path, _)| path.file_name()).collect();
+        names.sort();
+        if let Some(w) = names.windows(2).find(|w| w[0] == w[1]) {
+            bail!(PacError::InvalidInput(format!("several files named {:?} found, can't flatten them", w[0].unwrap_or_default())))
+        }
+    }
+
+    let mut sources = vec![];
+    let manifest = match use_manifest {
+        true => ExtractManifest::load(src_dir)?,
+        false => None,
+    };
+    if let Some(manifest) = manifest {
+        // entry data kept by `extract --keep-raw` is packed from converted files
+        files.retain(|(path, _)| !manifest.entries.iter().any(|e| e.raw_file.as_ref().is_some_and(|f| *path == src_dir.join(f))));
+        for entry in manifest.entries {
+            let path = src_dir.join(&entry.file);
+            match files.iter().position(|(f, _)| *f == path) {
+                Some(idx) => {
+                    files.remove(idx);
+                    sources.push(PackSource {
+                        path,
+                        raw_name: entry.raw_name_bytes()?,
+                        name: Some(entry.name),
+                        prefix: String::new(),
+                        options: opts.clone(),
+                    });
+                },
+                None => eprintln!("warning: {} is listed in manifest, but missing", entry.file),
             }
-            "json" => {
-                let ttp: TtpFile = serde_json::from_slice(&data)?;
-                Ok(PacFile::Ttp(ttp))
-            } 
-            _ => Ok(PacFile::Other { data })
         }
     }
+    sources.extend(files.into_iter().map(|(path, prefix)| PackSource {
+        path,
+        name: None,
+        prefix,
+        raw_name: None,
+        options: opts.clone(),
+    }));
+
+    Ok(sources)
 }
 
-/// Utility for extracting and packing pac archives of ひぐらしのなく頃に礼　デスクトップアクセサリー
-/// (higurashi no naku koro ni screen buddy)
-#[derive(Parser)]
-enum Commands {
-    /// Extract all files from `arc` to `out_dir`
-    #[clap(visible_alias = "x")]
-    Extract {
-        /// .pac archive
-        arc: String,
-        /// out folder, will be created if not exists, all contents will be REMOVED if exists
-        out_dir: String,
-    },
-    /// List all files in archive
-    #[clap(visible_alias = "l")]
-    List {
-        /// .pac archive
-        arc: String,
-    },
-    /// Pack directory into archive
-    #[clap(visible_alias = "p")]
-    Pack {
-        /// Result will be saved to this file
-        out_arc: String,
-        /// Build archive from this directory
-        src_dir: String,
+/// Collect files of `dir` to `files` along with entry name prefix, descending into subdirectories per `recurse`
+/// and handling symbolic links per `symlinks`
+fn walk_dir(dir: &Path, prefix: &str, recurse: Recurse, symlinks: Symlinks, files: &mut Vec<(PathBuf, String)>) -> Result<()> {
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_symlink() {
+            match symlinks {
+                Symlinks::Follow => {},
+                Symlinks::Skip => continue,
+                Symlinks::Error => bail!(PacError::InvalidInput(format!("{} is a symbolic link (see --symlinks)", path.display()))),
+            }
+        }
+        if entry.metadata()?.is_dir() {
+            let dir_name = entry.file_name();
+            let dir_name = dir_name.to_str()
+                .ok_or_else(|| PacError::InvalidInput(format!("Invalid directory name: {}", path.display())))?;
+            let sub_prefix = match recurse {
+                Recurse::Error => bail!(PacError::InvalidInput("all source directory entries must be files (see --recurse and --paths)".to_string())),
+                Recurse::Flatten => String::new(),
+                Recurse::Join => format!("{prefix}{dir_name}/"),
+            };
+            walk_dir(&path, &sub_prefix, recurse, symlinks, files)?;
+        } else {
+            files.push((path, prefix.to_string()));
+        }
     }
+    Ok(())
 }
 
-fn main() -> Result<()> {
-    let args = Commands::parse();
+/// Read and convert source file, returns converted file and its entry name
+fn load_source(builder: &PacArcBuilder, path: &Path, opts: &PackOptions) -> Result<(PacFile, String)> {
+    let unc_data = std::fs::read(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    let file_name = path.file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| PacError::InvalidInput(format!("Invalid file name: {}", path.display())))?;
+    
+    builder.convert_source_with(unc_data, file_name, opts)
+        .with_context(|| format!("Failed to convert {}", path.display()))
+}
 
-    match args {
-        Commands::Extract { arc, out_dir } => {
-            let mut f = File::open(arc)?;
-            let arc = PacArc::read_le(&mut f)?;
+/// Pack sources from `src_dir` in memory at every compression level and print timings and sizes
+fn bench(src_dir: &Path, recurse: Recurse) -> Result<()> {
+    let sources = collect_sources(src_dir, true, recurse, Symlinks::Follow, &PackOptions::default())?;
+    // read everything up front, so only conversion and packing are timed
+    let files = sources.into_iter()
+        .map(|source| {
+            let data = std::fs::read(&source.path)
+                .with_context(|| format!("Failed to read {}", source.path.display()))?;
+            Ok((source, data))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let source_size: usize = files.iter().map(|(_, data)| data.len()).sum();
+    say!("{} files, {}", files.len(), HumanBytes(source_size as u64));
 
-            let path = Path::new(&out_dir);
-            match (path.exists(), path.is_dir()) {
-                (true, true) => remove_dir_all(path)?,
-                (true, false) => bail!("specified path is not a directory"),
-                _ => (),
+    say!("{:>5} {:>10} {:>12} {:>7}", "level", "time", "size", "ratio");
+    for level in 0..=PackOptions::MAX_LEVEL {
+        let start = Instant::now();
+        let mut builder = PacArcBuilder::new();
+        for (source, data) in files.iter() {
+            let file_name = source.path.file_name()
+                .and_then(|n| n.to_str())
+                .ok_or_else(|| PacError::InvalidInput(format!("Invalid file name: {}", source.path.display())))?;
+            let opts = PackOptions { level, ..source.options.clone() };
+            let (pac_file, name) = builder.convert_source_with(data.clone(), file_name, &opts)
+                .with_context(|| format!("Failed to convert {}", source.path.display()))?;
+            match &source.raw_name {
+                Some(raw_name) => builder.add_entry_raw(pac_file, raw_name.clone())?,
+                None => builder.add_entry(pac_file, &source.entry_name(&name))?,
             }
+        }
+        let stats = builder.pack_to(std::io::sink())?;
+        let elapsed = start.elapsed();
+        say!(
+            "{level:>5} {:>9.2}s {:>12} {:>6.1}%",
+            elapsed.as_secs_f64(), HumanBytes(stats.bytes_written).to_string(),
+            stats.bytes_written as f64 / source_size as f64 * 100.0,
+        );
+    }
+    Ok(())
+}
 
-            DirBuilder::new().create(path)?;
-            arc.extract_all(&out_dir)?;
-            println!("All files extracted successfully");
+/// Extract `arc_path` to `tmp_dir`, pack it back and report entries that differ from original
+fn roundtrip(arc_path: &str, tmp_dir: &Path, image_format: ImageFormat, level: u8) -> Result<()> {
+    let arc = open_arc(arc_path)?;
+    let src_dir = tmp_dir.join("src");
+    DirBuilder::new().recursive(true).create(&src_dir)?;
+    let src_dir_str = src_dir.to_str().context("Invalid temporary directory path")?;
+    let opts = ExtractOptions { image_format, ..Default::default() };
+    arc.extract_all(src_dir_str, &opts)?
+        .save(&src_dir)?;
+
+    let mut builder = PacArcBuilder::new()
+        .with_options(PackOptions { level, ..Default::default() });
+    for source in collect_sources(&src_dir, true, Recurse::Error, Symlinks::Follow, builder.options())? {
+        let (pac_file, name) = load_source(&builder, &source.path, &source.options)?;
+        match source.raw_name {
+            Some(raw_name) => builder.add_entry_raw(pac_file, raw_name)?,
+            None => builder.add_entry(pac_file, &source.entry_name(&name))?,
+        }
+    }
+    let repacked_path = tmp_dir.join("repacked.pac");
+    let repacked_path = repacked_path.to_str().context("Invalid temporary directory path")?;
+    builder.pack(repacked_path)?;
+    let repacked = open_arc(repacked_path)?;
+
+    if arc.entries.len() != repacked.entries.len() {
+        bail!("archive has {} entries, but {} were repacked", arc.entries.len(), repacked.entries.len())
+    }
+    let (mut identical, mut recompressed, mut differ) = (0, 0, 0);
+    for (idx, (orig, new)) in arc.entries.iter().zip(repacked.entries.iter()).enumerate() {
+        let name = orig.name().unwrap_or_else(|e| e.to_string());
+        if orig.name.0 != new.name.0 {
+            say!("entry {idx} ({name}): repacked as {}", new.name()?);
+            differ += 1;
+            continue
+        }
+        let (orig_raw, new_raw) = (arc.read_raw(orig)?, repacked.read_raw(new)?);
+        if orig_raw == new_raw {
+            identical += 1;
+            continue
+        }
+        // compressed streams may differ, while holding same bitmap
+        let orig_conv = arc.read_converted(orig, ImageFormat::Bmp, Converters::builtin())?;
+        let new_conv = repacked.read_converted(new, ImageFormat::Bmp, Converters::builtin())?;
+        if orig_conv == new_conv {
+            recompressed += 1;
+        } else {
+            say!("entry {idx} ({name}): content differs ({} -> {} bytes)", orig.size, new.size);
+            differ += 1;
+        }
+    }
+
+    say!("{identical} entries identical, {recompressed} recompressed with same content, {differ} differ");
+    if differ > 0 {
+        bail!("archive does not survive extract -> pack round trip")
+    }
+    Ok(())
+}
+
+/// Ask user on stderr what to do with colliding entry
+fn prompt_collision(c: &Collision) -> Result<Option<String>> {
+    eprintln!("{} would be extracted to {}, already used by {}", c.name, c.file, c.taken_by);
+    eprint!("file name to use, empty for {}, \"-\" to skip entry: ", c.suggested);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer)? == 0 {
+        bail!(PacError::InvalidInput(format!("no answer for collision of {}", c.name)))
+    }
+    Ok(match answer.trim() {
+        "" => Some(c.suggested.to_string()),
+        "-" => None,
+        file => Some(file.to_string()),
+    })
+}
+
+/// Parse hex string like "81 40" or "8140" into bytes
+fn parse_hex(s: &str) -> Result<Vec<u8>> {
+    let digits: Vec<u8> = s.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+        bail!(PacError::InvalidInput(format!("odd number of hex digits in {s:?}")))
+    }
+    digits.chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).unwrap_or_default();
+            u8::from_str_radix(pair, 16).map_err(|_| PacError::InvalidInput(format!("invalid hex byte: {pair:?}")).into())
+        })
+        .collect()
+}
+
+/// Offsets of all (possibly overlapping) occurrences of `needle` in `haystack`
+fn find_all<'a>(haystack: &'a [u8], needle: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+    haystack.windows(needle.len())
+        .enumerate()
+        .filter(move |(_, w)| *w == needle)
+        .map(|(offset, _)| offset)
+}
+
+fn main() -> ExitCode {
+    let (cli, config, command) = match Config::load(config_arg().as_deref()) {
+        Ok(config) => {
+            let matches = config.apply(Cli::command()).get_matches();
+            let command = matches.subcommand_name().unwrap_or_default().to_string();
+            (Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit()), config, command)
         },
-        Commands::List { arc } => {
-            let mut f = File::open(arc)?;
-            let arc = PacArc::read_le(&mut f)?;
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            return ExitCode::from(exit_code(&e))
+        },
+    };
+    deflate::set_backend(cli.backend);
+    let Err(e) = run(cli.command, cli.output, &config.extensions) else {
+        if cli.output == OutputFormat::Ndjson {
+            Event::Complete { command: &command }.emit();
+        }
+        return ExitCode::SUCCESS
+    };
+    let code = exit_code(&e);
+    if cli.output == OutputFormat::Ndjson {
+        Event::Error { message: &format!("{e:#}"), exit_code: code }.emit();
+    }
+    eprintln!("Error: {e:?}");
+    ExitCode::from(code)
+}
+
+/// Process exit code for `e`, as listed in `EXIT_CODES`
+fn exit_code(e: &anyhow::Error) -> u8 {
+    match ErrorKind::of(e) {
+        None => 1,
+        Some(ErrorKind::InvalidInput) => 2,
+        Some(ErrorKind::Io) => 3,
+        Some(ErrorKind::Format) => 4,
+        Some(ErrorKind::Decode) => 5,
+        Some(ErrorKind::NameEncoding) => 6,
+        Some(ErrorKind::SizeLimit) => 7,
+    }
+}
 
-            println!("{:<6}{:<10}{:<48}{}", "index", "size", "info", "name");
+fn run(command: Commands, output: OutputFormat, extensions: &[ExtMapping]) -> Result<()> {
+    let ndjson = output == OutputFormat::Ndjson;
+    EVENT_STREAM.store(ndjson, Ordering::Relaxed);
+    // these write data to stdout, which would break event stream
+    match &command {
+        Commands::Cat { .. } | Commands::Hexdump { .. } if ndjson => {
+            bail!(PacError::InvalidInput("--output ndjson can't be used with commands writing entry data to stdout".to_string()))
+        },
+        Commands::List { json, csv, .. } if ndjson && (*json || *csv) => {
+            bail!(PacError::InvalidInput("--output ndjson can't be used with --json or --csv".to_string()))
+        },
+        _ => {},
+    }
+    match command {
+        Commands::Extract { 
+            paths, out_root, to_stdout_tar, force, merge, skip_existing, image_format, lossy_names, indices, include, exclude, jobs, quiet,
+            #[cfg(feature = "mmap")] mmap,
+            dry_run, salvage, on_collision, trust_names, path_mode, no_convert, keep_raw, format, endian, name_size,
+        } => {
+            let converters = converters(extensions, &no_convert)?;
+            let targets = match out_root {
+                Some(out_root) => {
+                    let mut targets: Vec<(String, String)> = vec![];
+                    for arc in expand_globs(&paths)? {
+                        if arc == "-" {
+                            bail!(PacError::InvalidInput("archive can't be read from stdin with --out-root".to_string()))
+                        }
+                        let stem = Path::new(&arc).file_stem()
+                            .ok_or_else(|| PacError::InvalidInput(format!("Invalid archive path {arc}")))?;
+                        let out_dir = Path::new(&out_root).join(stem).to_string_lossy().into_owned();
+                        if let Some((other, _)) = targets.iter().find(|(_, dir)| *dir == out_dir) {
+                            bail!(PacError::InvalidInput(format!("archives {other} and {arc} would be extracted to the same folder {out_dir}")))
+                        }
+                        targets.push((arc, out_dir));
+                    }
+                    targets
+                },
+                None => match <[String; 2]>::try_from(paths) {
+                    Ok([arc, out_dir]) => vec![(arc, out_dir)],
+                    Err(paths) if to_stdout_tar && paths.len() == 1 => vec![(paths[0].clone(), "-".to_string())],
+                    Err(_) => bail!(PacError::InvalidInput("expected archive and output directory, use --out-root to extract several archives".to_string())),
+                },
+            };
+
+            if ndjson && targets.iter().any(|(_, out_dir)| out_dir == "-") {
+                bail!(PacError::InvalidInput("--output ndjson can't be used with tar output".to_string()))
+            }
+            let batch = targets.len() > 1;
+            for (arc, out_dir) in targets {
+                if batch && !ndjson {
+                    say!("{arc} -> {out_dir}");
+                }
+                let archive = arc.clone();
+                let full_names = match arc.as_str() {
+                    "-" => Default::default(),
+                    _ => NameMap::load(&arc)?.unwrap_or_default().entries.into_iter()
+                        .map(|s| (s.name, s.full_name))
+                        .collect(),
+                };
+                #[cfg(feature = "mmap")]
+                let source: DynSource = if arc == "-" {
+                    Box::new(read_stdin()?)
+                } else if mmap {
+                    Box::new(map_file(&arc).with_context(|| format!("Failed to open archive {arc}"))?)
+                } else {
+                    Box::new(ReaderSource::new(BufReader::new(File::open(&arc)?)))
+                };
+                #[cfg(not(feature = "mmap"))]
+                let source: DynSource = if arc == "-" {
+                    Box::new(read_stdin()?)
+                } else {
+                    Box::new(ReaderSource::new(BufReader::new(File::open(&arc)?)))
+                };
+                let (arc, mut problems) = match (salvage, layout(&*source, format.as_ref(), endian, name_size)?) {
+                    (true, Some(format)) => PacArc::from_source_salvage_as(source, format),
+                    (true, None) => PacArc::from_source_salvage(source),
+                    (false, Some(format)) => PacArc::from_source_as(source, format).map(|arc| (arc, vec![])),
+                    (false, None) => PacArc::from_source(source).map(|arc| (arc, vec![])),
+                }
+                .with_context(|| format!("Failed to open archive {arc}"))?;
+
+                if out_dir == "-" {
+                    if force || merge || skip_existing || dry_run || salvage || keep_raw {
+                        bail!(PacError::InvalidInput("--force, --merge, --skip-existing, --dry-run, --salvage and --keep-raw can't be used with tar output".to_string()))
+                    }
+                    let opts = ExtractOptions {
+                        image_format, on_collision, trust_names, paths: path_mode,
+                        indices: indices.clone(),
+                        include: include.clone(),
+                        exclude: exclude.clone(),
+                        name_decoding: lossy_names.unwrap_or_default(),
+                        collision_prompt: Some(Box::new(prompt_collision)),
+                        converters: converters.clone(),
+                        full_names,
+                        ..Default::default()
+                    };
+                    let manifest = pac_to_tar(&arc, BufWriter::new(std::io::stdout().lock()), &opts)?;
+                    eprintln!("{} files written to stdout", manifest.entries.len());
+                    continue
+                }
+
+                let path = Path::new(&out_dir);
+                match (path.exists(), path.is_dir()) {
+                    (true, false) => bail!(PacError::InvalidInput("specified path is not a directory".to_string())),
+                    (true, true) if merge || skip_existing || read_dir(path)?.next().is_none() => (),
+                    (true, true) if force && !dry_run => remove_dir_all(path)?,
+                    (true, true) if force => (),
+                    (true, true) => bail!(
+                        "output directory {out_dir} is not empty, \
+                        use --force to wipe it or --merge to overwrite matching files"
+                    ),
+                    _ => (),
+                }
+
+                if !dry_run {
+                    DirBuilder::new().recursive(true).create(path)?;
+                }
+                let pb = progress_bar(arc.entries.len(), quiet || dry_run || ndjson);
+                let pb_cb = pb.clone();
+                let pb_prompt = pb.clone();
+                let archive_cb = archive.clone();
+                let opts = ExtractOptions { 
+                    image_format, jobs, dry_run, skip_existing, on_collision, trust_names, keep_raw, paths: path_mode,
+                    indices: indices.clone(),
+                    include: include.clone(),
+                    exclude: exclude.clone(),
+                    name_decoding: lossy_names.unwrap_or_default(),
+                    progress: Some(Box::new(move |p| {
+                        if ndjson {
+                            Event::EntryExtracted {
+                                archive: &archive_cb,
+                                name: p.name,
+                                entries_done: p.entries_done,
+                                entries_total: p.entries_total,
+                                bytes_written: p.bytes_written,
+                            }.emit();
+                        }
+                        pb_cb.set_position(p.entries_done as u64);
+                        pb_cb.set_message(HumanBytes(p.bytes_written).to_string());
+                    })),
+                    converters: converters.clone(),
+                    collision_prompt: Some(Box::new(move |c| pb_prompt.suspend(|| prompt_collision(c)))),
+                    full_names,
+                };
+                let mut manifest = if salvage {
+                    let (manifest, damaged) = arc.salvage_all(&out_dir, &opts)?;
+                    problems.extend(damaged);
+                    manifest
+                } else {
+                    arc.extract_all(&out_dir, &opts)?
+                };
+                if archive != "-" {
+                    manifest.archive_mtime = file_mtime(&archive);
+                }
+                pb.finish_and_clear();
+                if ndjson {
+                    for p in problems.iter() {
+                        Event::EntryFailed { archive: &archive, index: p.index, name: &p.name, message: &p.message }.emit();
+                    }
+                    if !dry_run {
+                        manifest.save(&out_dir)?;
+                    }
+                    Event::ExtractComplete {
+                        archive: &archive,
+                        out_dir: &out_dir,
+                        entries: manifest.entries.len(),
+                        problems: problems.len(),
+                        dry_run,
+                    }.emit();
+                    continue
+                }
+                for p in problems.iter() {
+                    match p.index {
+                        Some(idx) => say!("damaged entry {idx} ({}): {}", p.name, p.message),
+                        None => say!("damaged archive: {}", p.message),
+                    }
+                }
+                if dry_run {
+                    for entry in manifest.entries.iter() {
+                        say!("{} -> {}", entry.name, path.join(&entry.file).display());
+                    }
+                    say!("{} files would be extracted", manifest.entries.len());
+                    continue
+                }
+                manifest.save(&out_dir)?;
+                if problems.is_empty() {
+                    say!("All files extracted successfully");
+                } else {
+                    say!("{} files extracted, {} problems found", manifest.entries.len(), problems.len());
+                }
+            }
+        },
+        Commands::List { arc: arc_path, json, csv, lossy_names, hash, format, endian, name_size, sort, filter, regex, human, columns } => {
+            let name_decoding = lossy_names.unwrap_or_default();
+            let arc = open_arc_as(&arc_path, format.as_ref(), endian, name_size)?;
+            if arc.format != PacFormat::default() {
+                eprintln!("archive format: {}", arc.format);
+            }
+
+            let filter = match (filter, regex) {
+                (Some(filter), true) => Some(Regex::new(&filter).map_err(|e| PacError::InvalidInput(e.to_string())).context("Invalid --filter regex")?),
+                (Some(filter), false) => Some(Regex::new(&regex::escape(&filter))?),
+                (None, _) => None,
+            };
+            let mut listed = vec![];
             for (idx, entry) in arc.entries.iter().enumerate() {
-                let info = match &*entry.file {
-                    PacFile::Bmz { uncompressed_size, .. } =>
-                        format!("bmz uncompressed size: {uncompressed_size}"),
-                    PacFile::Other { .. } =>  "other file".into(),
-                    PacFile::Ttp(TtpFile {
-                        maybe_ttp_type: typ,
-                        frame_count: fcnt,
-                        window_width: w,
-                        window_height: h,
-                        .. 
-                    }) => format!("ttp type?: {typ:<3} w: {w:<4} h: {h:<4} frames: {fcnt}"),
+                let lossy_name = entry.decode_name(NameDecoding::Replace)?;
+                if filter.as_ref().is_some_and(|f| !f.is_match(&lossy_name)) {
+                    continue
+                }
+                listed.push((idx, entry, arc.read_file(entry)?, lossy_name));
+            }
+            match sort {
+                ListSort::Index => {},
+                ListSort::Name => listed.sort_by(|a, b| a.3.cmp(&b.3)),
+                ListSort::Size => listed.sort_by_key(|(_, entry, ..)| entry.size),
+                ListSort::Ratio => listed.sort_by(|a, b| compression_ratio(a.1, &a.2).total_cmp(&compression_ratio(b.1, &b.2))),
+            }
+
+            let rows = listed.iter()
+                .map(|(index, entry, file, _)| ListRow::new(*index, entry, file, name_decoding, hash));
+
+            if json {
+                let rows = rows.collect::<Result<Vec<_>>>()?;
+                say!("{}", serde_json::to_string_pretty(&rows)?);
+                return Ok(())
+            }
+
+            if ndjson {
+                let rows = rows.collect::<Result<Vec<_>>>()?;
+                for row in rows.iter() {
+                    Event::EntryListed { archive: &arc_path, row }.emit();
+                }
+                Event::ListComplete { archive: &arc_path, entries: rows.len() }.emit();
+                return Ok(())
+            }
+
+            if csv {
+                let mut w = csv::WriterBuilder::new()
+                    .quote_style(csv::QuoteStyle::NonNumeric)
+                    .from_writer(std::io::stdout().lock());
+                for row in rows {
+                    w.serialize(row?)?;
+                }
+                w.flush()?;
+                return Ok(())
+            }
+
+            let columns = match (columns.is_empty(), hash.is_some()) {
+                (false, _) => columns,
+                (true, false) => vec![ListColumn::Index, ListColumn::Size, ListColumn::Info, ListColumn::Name],
+                (true, true) => vec![ListColumn::Index, ListColumn::Size, ListColumn::Info, ListColumn::Hash, ListColumn::Name],
+            };
+            let hash = hash.unwrap_or(HashKind::Sha256);
+            let print_row = |cells: Vec<String>| {
+                let mut line = String::new();
+                for (i, (cell, column)) in cells.iter().zip(columns.iter()).enumerate() {
+                    match i + 1 == columns.len() {
+                        true => line.push_str(cell),
+                        false => line.push_str(&format!("{cell:<width$}", width = column.width(hash, human))),
+                    }
+                }
+                say!("{}", line.trim_end());
+            };
+            print_row(columns.iter()
+                .map(|c| c.to_possible_value().expect("no skipped variants").get_name().to_string())
+                .collect());
+            for (idx, entry, file, _) in listed {
+                let mut cells = vec![];
+                for column in columns.iter() {
+                    cells.push(match column {
+                        ListColumn::Index => idx.to_string(),
+                        ListColumn::Offset => entry.offset.to_string(),
+                        ListColumn::Size => format_size(entry.size, human),
+                        ListColumn::Magic => format_magic(&arc.read_raw(entry)?[..entry.size.min(4) as usize]),
+                        ListColumn::Ratio => match file.uncompressed_size() {
+                            Some(_) => format!("{:.1}%", compression_ratio(entry, &file) * 100.0),
+                            None => "-".into(),
+                        },
+                        ListColumn::Info => list_info(entry, &file, human),
+                        ListColumn::Hash => hash.digest(&file.converted_data()?),
+                        ListColumn::Name => match entry.decode_name(name_decoding) {
+                            Ok(n) => n,
+                            Err(e) => e.to_string(),
+                        },
+                    });
+                }
+                print_row(cells);
+            }
+        },
+        Commands::Pack {
+            out_arc, src_dir, manifest, level, no_compress, no_manifest, quiet, dry_run, recurse, path_mode, symlinks, dedup, align, no_convert, raw, format, endian, name_size,
+            long_names, #[cfg(feature = "watch")] watch,
+        } => {
+            let converters = match raw {
+                true => Converters::empty(),
+                false => converters(extensions, &no_convert)?,
+            };
+            let recurse = match path_mode {
+                Some(PathMode::Preserve) => Recurse::Join,
+                Some(PathMode::Flatten) => Recurse::Flatten,
+                None => recurse,
+            };
+            let pack = || -> Result<()> {
+                let mut builder = PacArcBuilder::new()
+                    .with_options(PackOptions { level, compress: !no_compress, converters: converters.clone() })
+                    .with_dedup(dedup)
+                    .with_align(align)
+                    .with_format(override_layout(&format, endian.map(Endian::from), name_size))?
+                    .with_long_names(long_names);
+                let sources = match (&manifest, &src_dir) {
+                    (Some(manifest), _) => {
+                        let base_dir = Path::new(manifest).parent().unwrap_or(Path::new(""));
+                        PackManifest::load(manifest)?.resolve(base_dir, builder.options())?
+                    },
+                    (None, Some(src_dir)) => collect_sources(Path::new(src_dir), !no_manifest, recurse, symlinks, builder.options())?,
+                    (None, None) => unreachable!("enforced by clap"),
                 };
+                // archive keeps its modification time if it's repacked from unchanged extracted files
+                let mut unchanged_since = None;
+                if let (None, Some(src_dir), false) = (&manifest, &src_dir, no_manifest) {
+                    if let Some(extracted) = ExtractManifest::load(src_dir)?.filter(|m| m.entries.iter().any(|e| e.mtime.is_some())) {
+                        let changed = extracted.changed_entries(Path::new(src_dir)).count();
+                        if !quiet && !ndjson {
+                            say!("{changed} of {} extracted files changed since extraction", extracted.entries.len());
+                        }
+                        if changed == 0 && sources.len() == extracted.entries.len() {
+                            unchanged_since = extracted.archive_mtime;
+                        }
+                    }
+                }
+
+                let pb = progress_bar(sources.len(), quiet || dry_run || ndjson);
+                pb.set_prefix("converting");
+                let mut bytes_read = 0;
+                for source in sources {
+                    bytes_read += source.path.metadata()
+                        .with_context(|| format!("Failed to read {}", source.path.display()))?
+                        .len();
+                    let (pac_file, name) = load_source(&builder, &source.path, &source.options)?;
+                    let pac_file = match pac_file {
+                        PacFile::Other { data } if raw && Path::new(&name).extension().is_some_and(|e| e.eq_ignore_ascii_case("bmz")) =>
+                            PacFile::from_bmz_as(&data, builder.format().endian)
+                                .with_context(|| format!("Failed to pack {} as is", source.path.display()))?,
+                        pac_file => pac_file,
+                    };
+                    let name = source.entry_name(&name);
+                    if dry_run && !ndjson {
+                        say!("{} -> {name} ({})", source.path.display(), pac_file.kind());
+                    }
+                    match source.raw_name {
+                        Some(raw_name) => builder.add_entry_raw(pac_file, raw_name)?,
+                        None => builder.add_entry(pac_file, &name)?,
+                    }                    
+                    pb.inc(1);
+                    pb.set_message(HumanBytes(bytes_read).to_string());
+                }
 
-                let name = match entry.name() {
-                    Ok(n) => n,
-                    Err(e) => e.to_string(),
+                if dry_run {
+                    pb.finish_and_clear();
+                    let size = builder.estimated_size()?;
+                    if size > PacArcBuilder::MAX_ARCHIVE_SIZE {
+                        bail!(PacError::SizeLimit(format!(
+                            "archive would be {size} bytes, larger than {} bytes entry offsets can address", PacArcBuilder::MAX_ARCHIVE_SIZE,
+                        )))
+                    }
+                    if !ndjson {
+                        let at_most = if dedup { "at most " } else { "" };
+                        say!(
+                            "{} entries would be packed to {out_arc} ({at_most}{})", builder.entry_count(), HumanBytes(size),
+                        );
+                    }
+                    return Ok(())
+                }
+
+                pb.reset();
+                pb.set_prefix("writing");
+                let pb_cb = pb.clone();
+                builder.on_progress(move |p| {
+                    if ndjson {
+                        Event::EntryPacked {
+                            name: p.name,
+                            entries_done: p.entries_done,
+                            entries_total: p.entries_total,
+                            bytes_written: p.bytes_written,
+                        }.emit();
+                    }
+                    pb_cb.set_position(p.entries_done as u64);
+                    pb_cb.set_message(HumanBytes(p.bytes_written).to_string());
+                });
+                if out_arc == "-" {
+                    let stats = builder.pack_to(BufWriter::new(std::io::stdout().lock()))?;
+                    pb.finish_and_clear();
+                    for s in stats.shortened.iter() {
+                        eprintln!("{} stored as {}", s.full_name, s.name);
+                    }
+                    eprintln!("All files packed");
+                    if dedup {
+                        eprintln!("Deduplication saved {}", HumanBytes(stats.dedup_saved));
+                    }
+                    return Ok(())
+                }
+                let stats = builder.pack(&out_arc)?;
+                pb.finish_and_clear();
+                if let Some(mtime) = unchanged_since {
+                    set_file_mtime(&out_arc, mtime)?;
+                }
+                let name_map = NameMap::path_for(&out_arc);
+                if !stats.shortened.is_empty() {
+                    NameMap { entries: stats.shortened.clone() }.save(&out_arc)?;
+                } else if name_map.exists() {
+                    // left from previous pack
+                    std::fs::remove_file(&name_map)?;
+                }
+                if ndjson {
+                    Event::PackComplete {
+                        archive: &out_arc,
+                        entries: stats.entries,
+                        bytes_written: stats.bytes_written,
+                        dedup_saved: stats.dedup_saved,
+                    }.emit();
+                    return Ok(())
+                }
+                say!("All files packed");
+                if unchanged_since.is_some() {
+                    say!("Sources unchanged since extraction, archive modification time restored");
+                }
+                if dedup {
+                    say!("Deduplication saved {}", HumanBytes(stats.dedup_saved));
+                }
+                if !stats.shortened.is_empty() {
+                    say!("{} long names shortened, full names saved to {}", stats.shortened.len(), name_map.display());
+                }
+                Ok(())
+            };
+
+            #[cfg(feature = "watch")]
+            if watch && out_arc == "-" {
+                bail!(PacError::InvalidInput("--watch can't be used when writing archive to stdout".to_string()))
+            }
+            if ndjson && out_arc == "-" {
+                bail!(PacError::InvalidInput("--output ndjson can't be used when writing archive to stdout".to_string()))
+            }
+            pack()?;
+            #[cfg(feature = "watch")]
+            if watch {
+                let watched = match (&manifest, &src_dir) {
+                    (Some(manifest), _) => Path::new(manifest).parent().unwrap_or(Path::new(".")).to_path_buf(),
+                    (None, Some(src_dir)) => PathBuf::from(src_dir),
+                    (None, None) => unreachable!("enforced by clap"),
                 };
+                watch_changes(&watched, Path::new(&out_arc), || {
+                    if let Err(e) = pack() {
+                        eprintln!("Error: {e:#}");
+                    }
+                })?;
+            }
+        },
+        Commands::Add { arc, files } => {
+            let mut builder = PacArcBuilder::from_existing(open_arc(&arc)?)?;
 
-                println!("{idx:<6}{:<10}{info:<48}{name}", entry.size);
+            for file in files {
+                let (pac_file, name) = load_source(&builder, Path::new(&file), builder.options())?;
+                if builder.has_entry(&name) {
+                    bail!(PacError::InvalidInput(format!("entry {name} already exists in archive")))
+                }
+                builder.add_entry(pac_file, &name)?;
             }
+
+            written("add", &arc, &builder.update(&arc)?);
+            say!("All files added")
         },
-        Commands::Pack { out_arc, src_dir } => {
+        Commands::Remove { arc, entries, out, in_place: _ } => {
+            let mut builder = PacArcBuilder::from_existing(open_arc(&arc)?)?;
+
+            for name in entries {
+                if !builder.remove_entry(&name) {
+                    bail!(PacError::InvalidInput(format!("no such entry: {name}")))
+                }
+            }
+
+            written("remove", out.as_deref().unwrap_or(&arc), &write_edited(builder, &arc, out.as_deref())?);
+            say!("Entries removed")
+        },
+        Commands::Replace { arc, entry, file, out } => {
+            let mut builder = PacArcBuilder::from_existing(open_arc(&arc)?)?;
+
+            let (pac_file, _) = load_source(&builder, Path::new(&file), builder.options())?;
+            if !builder.replace_entry(&entry, pac_file) {
+                bail!(PacError::InvalidInput(format!("no such entry: {entry}")))
+            }
+
+            written("replace", out.as_deref().unwrap_or(&arc), &write_edited(builder, &arc, out.as_deref())?);
+            say!("Entry replaced")
+        },
+        Commands::Rename { arc, old, new, out } => {
+            let mut builder = PacArcBuilder::from_existing(open_arc(&arc)?)?;
+
+            if builder.has_entry(&new) {
+                bail!(PacError::InvalidInput(format!("entry {new} already exists in archive")))
+            }
+            if !builder.rename_entry(&old, &new)? {
+                bail!(PacError::InvalidInput(format!("no such entry: {old}")))
+            }
+
+            written("rename", out.as_deref().unwrap_or(&arc), &write_edited(builder, &arc, out.as_deref())?);
+            say!("Entry renamed")
+        },
+        Commands::Fix { arc, out, dry_run } => {
+            let mut builder = open_arc(&arc)?.into_builder()?;
+            let fixes = builder.fix_bmz_sizes()?;
+            for fix in fixes.iter() {
+                say!("{}: declared size {}, actual {}", fix.name, fix.declared, fix.actual);
+            }
+
+            if fixes.is_empty() {
+                say!("All bmz headers are correct");
+            } else if dry_run {
+                say!("{} bmz headers would be fixed", fixes.len());
+            } else {
+                let out = out.as_ref().unwrap_or(&arc);
+                written("fix", out, &builder.pack(out)?);
+                say!("{} bmz headers fixed", fixes.len());
+            }
+        },
+        Commands::Optimize { in_arc, out_arc, exhaustive, verbose } => {
+            let arc = open_arc(&in_arc)?;
+            let mut builder = arc.into_builder()?;
+            let recompressed = builder.recompress_bmz(exhaustive)?;
+            if verbose {
+                for r in recompressed.iter() {
+                    say!("{}: {} -> {} bytes", r.name, r.before, r.after);
+                }
+            }
+            let saved: u64 = recompressed.iter().map(|r| (r.before - r.after) as u64).sum();
+            let before = std::fs::metadata(&in_arc)?.len();
+            let stats = builder.pack(&out_arc)?;
+            written("optimize", &out_arc, &stats);
+            say!(
+                "{} bmz entries recompressed, saved {} ({} -> {})",
+                recompressed.len(), HumanBytes(saved), HumanBytes(before), HumanBytes(stats.bytes_written),
+            );
+        },
+        Commands::Merge { out_arc, arcs, on_conflict } => {
             let mut builder = PacArcBuilder::new();
-            
-            for entry in read_dir(src_dir)? {
-                let entry = entry?;
-                if entry.metadata()?.is_file() {
-                    let unc_data = std::fs::read(entry.path())?;
-                    let path = entry.path();
-
-                    let unc_ext = path
-                        .extension()
-                        .and_then(|e| e.to_str())
-                        .unwrap_or_default();
-                    
-                    let pac_file = PacFile::convert_back(unc_data, unc_ext)?;
-
-                    let path = path.with_extension(PacFile::original_ext(unc_ext));
-                    let name = path.file_name()
-                        .and_then(|n| n.to_str())
-                        .unwrap();
-
-                    builder.add_entry(pac_file, name)?;                    
-                }    
-                else {
-                    bail!("all source directory entries must be files")
-                }
-            }
-
-            builder.pack(&out_arc)?;
-            println!("All files packed")
+            let mut conflicts = 0;
+            for arc in arcs.iter() {
+                conflicts += builder.merge(&open_arc(arc)?, on_conflict)
+                    .with_context(|| format!("Failed to merge {arc}"))?;
+            }
+
+            written("merge", &out_arc, &builder.pack(&out_arc)?);
+            say!("{} archives merged, {conflicts} conflicting entries", arcs.len())
+        },
+        Commands::Convert { input, output, image_format, lossy_names, level } => {
+            if Path::new(&input).extension().is_some_and(|e| e.eq_ignore_ascii_case("zip")) {
+                let mut builder = PacArcBuilder::new()
+                    .with_options(PackOptions { level, converters: converters(extensions, &[])?, ..Default::default() });
+                let zip = BufReader::new(File::open(&input).with_context(|| format!("Failed to open {input}"))?);
+                let added = zip_to_pac(zip, &mut builder)?;
+                written("convert", &output, &builder.pack(&output)?);
+                say!("{added} entries packed to {output}");
+                return Ok(())
+            }
+            let arc = open_arc(&input)?;
+            let opts = ExtractOptions {
+                image_format,
+                name_decoding: lossy_names.unwrap_or_default(),
+                converters: converters(extensions, &[])?,
+                ..Default::default()
+            };
+            let mut out = BufWriter::new(File::create(&output)?);
+            let manifest = pac_to_zip(&arc, &mut out, &opts)?;
+            out.flush()?;
+            if ndjson {
+                let bytes_written = std::fs::metadata(&output)?.len();
+                Event::ArchiveWritten { command: "convert", archive: &output, entries: manifest.entries.len(), bytes_written }.emit();
+            }
+            say!("{} entries written to {output}", manifest.entries.len());
+        },
+        #[cfg(feature = "fuse")]
+        Commands::Mount { arc, mountpoint, image_format, lossy_names } => {
+            let opts = ExtractOptions {
+                image_format,
+                name_decoding: lossy_names.unwrap_or_default(),
+                converters: converters(extensions, &[])?,
+                ..Default::default()
+            };
+            nipaa_pac::mount::mount(open_arc(&arc)?, &mountpoint, &opts)?;
+        },
+        Commands::Bench { src_dir, recurse } => bench(Path::new(&src_dir), recurse)?,
+        Commands::Roundtrip { arc, image_format, level } => {
+            let tmp_dir = std::env::temp_dir().join(format!("nipaa-roundtrip-{}", std::process::id()));
+            let res = roundtrip(&arc, &tmp_dir, image_format, level);
+            if tmp_dir.exists() {
+                remove_dir_all(&tmp_dir)?;
+            }
+            res?
+        },
+        Commands::Verify { arcs, format, endian, name_size } => {
+            let arcs = expand_globs(&arcs)?;
+            let mut damaged = 0;
+            for arc in arcs.iter() {
+                let mut f = File::open(arc)
+                    .with_context(|| format!("Failed to open {arc}"))?;
+                let report = match layout(&ReaderSource::new(&mut f), format.as_ref(), endian, name_size)? {
+                    Some(format) => verify_as(&mut f, &format)?,
+                    None => verify(&mut f)?,
+                };
+                if !report.is_ok() {
+                    damaged += 1;
+                }
+
+                if ndjson {
+                    for p in report.problems.iter() {
+                        Event::EntryFailed { archive: arc, index: p.index, name: &p.name, message: &p.message }.emit();
+                    }
+                    Event::VerifyComplete {
+                        archive: arc,
+                        entries: report.entries_count,
+                        bmz_checked: report.bmz_checked,
+                        problems: report.problems.len(),
+                    }.emit();
+                    continue
+                }
+                if arcs.len() > 1 {
+                    say!("{arc}:");
+                }
+                for p in report.problems.iter() {
+                    match p.index {
+                        Some(idx) => say!("entry {idx} ({}): {}", p.name, p.message),
+                        None => say!("archive: {}", p.message),
+                    }
+                }
+                say!(
+                    "{} entries, {} bmz streams checked, {} problems found",
+                    report.entries_count, report.bmz_checked, report.problems.len(),
+                );
+            }
+            match (damaged, arcs.len()) {
+                (0, _) => (),
+                (_, 1) => bail!(PacError::Format("archive is damaged".into())),
+                _ => bail!(PacError::Format(format!("{damaged} of {} archives are damaged", arcs.len()))),
+            }
+        },
+        Commands::VerifyExtract { arc, dir, image_format, no_convert, path_mode } => {
+            let arc = open_arc(&arc)?;
+            let opts = ExtractOptions {
+                image_format,
+                paths: path_mode,
+                converters: converters(extensions, &no_convert)?,
+                ..Default::default()
+            };
+            let result = diff_extracted(&arc, Path::new(&dir), &opts)?;
+            for drift in result.drift.iter() {
+                match drift {
+                    Drift::Modified { name, file } if name == file => say!("M {file}"),
+                    Drift::Modified { name, file } => say!("M {file} ({name})"),
+                    Drift::Missing { name, file } if name == file => say!("- {file}"),
+                    Drift::Missing { name, file } => say!("- {file} ({name})"),
+                    Drift::Added { file } => say!("+ {file}"),
+                }
+            }
+            let count = |f: fn(&Drift) -> bool| result.drift.iter().filter(|d| f(d)).count();
+            say!(
+                "{} unchanged, {} modified, {} missing, {} added",
+                result.unchanged,
+                count(|d| matches!(d, Drift::Modified { .. })),
+                count(|d| matches!(d, Drift::Missing { .. })),
+                count(|d| matches!(d, Drift::Added { .. })),
+            );
+        },
+        Commands::Detect { files } => {
+            let files = expand_globs(&files)?;
+            for path in files.iter() {
+                let f = File::open(path).with_context(|| format!("Failed to open {path}"))?;
+                let sniffed = sniff(&ReaderSource::new(BufReader::new(f)))
+                    .with_context(|| format!("Failed to read {path}"))?;
+                match sniffed.kind {
+                    FileKind::Unknown => say!("{path}: unknown"),
+                    kind => say!("{path}: {kind} ({} confidence)", sniffed.confidence),
+                }
+                for detail in sniffed.details.iter() {
+                    say!("    {detail}");
+                }
+            }
+        },
+        Commands::Diff { old, new, hash } => {
+            let changes = diff(&open_arc(&old)?, &open_arc(&new)?, hash)?;
+            for change in changes.iter() {
+                match change {
+                    Change::Added { name, size } => say!("+ {name} ({size} bytes)"),
+                    Change::Removed { name, size } => say!("- {name} ({size} bytes)"),
+                    Change::Renamed { from, to, .. } => say!("R {from} -> {to}"),
+                    Change::Changed { name, old_size, new_size } => say!(
+                        "M {name} ({old_size} -> {new_size} bytes, {:+})", change.size_delta(),
+                    ),
+                }
+            }
+            let total: i64 = changes.iter().map(Change::size_delta).sum();
+            say!("{} changes, {total:+} bytes", changes.len());
+        },
+        Commands::Cat { arc, entry, convert, no_convert } => {
+            let arc = open_arc(&arc)?;
+            let Some(entry) = arc.find_entry(&entry) else {
+                bail!(PacError::InvalidInput(format!("no such entry: {entry}")))
+            };
+
+            let data = if convert {
+                arc.read_converted(entry, ImageFormat::Bmp, &converters(extensions, &no_convert)?)?
+            } else {
+                arc.read_raw(entry)?.into_owned()
+            };
+            std::io::stdout().lock().write_all(&data)?;
+        },
+        Commands::Stats { arc, top, json } => {
+            let arc = open_arc(&arc)?;
+            let stats = ArchiveStats::collect(&arc, top)?;
+            match json {
+                true => say!("{}", serde_json::to_string_pretty(&stats)?),
+                false => stats.print(),
+            }
+        },
+        Commands::Info { arc: arc_path, entry: entry_name } => {
+            let arc = open_arc(&arc_path)?;
+            let Some(entry) = arc.find_entry(&entry_name) else {
+                bail!(PacError::InvalidInput(format!("no such entry: {entry_name}")))
+            };
+            let raw = arc.read_raw(entry)?;
+            let file = PacFile::from_raw_as(&raw, arc.format.endian)?;
+
+            if ndjson {
+                let bmp = match file {
+                    PacFile::Bmz { .. } => Some(BmpInfo::parse(&file.converted_data()?)?),
+                    _ => None,
+                };
+                Event::EntryInfo {
+                    archive: &arc_path,
+                    name: &entry_name,
+                    typ: file.kind(),
+                    offset: entry.offset,
+                    size: entry.size,
+                    uncompressed_size: file.uncompressed_size(),
+                    sha256: &Sha256::digest(&raw).iter().map(|b| format!("{b:02x}")).collect::<String>(),
+                    width: bmp.as_ref().map(|b| b.width),
+                    height: bmp.as_ref().map(|b| b.height.abs()),
+                    bit_depth: bmp.as_ref().map(|b| b.bit_depth),
+                }.emit();
+                return Ok(())
+            }
+
+            say!("name:              {entry_name}");
+            say!("type:              {}", file.kind());
+            say!("offset:            {}", entry.offset);
+            say!("stored size:       {}", entry.size);
+            let magic: String = raw.iter().take(4)
+                .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+                .collect();
+            say!("magic:             {magic}");
+            if let Some(uncompressed_size) = file.uncompressed_size() {
+                say!("uncompressed size: {uncompressed_size}");
+                say!("compression ratio: {:.2}%", 100.0 * entry.size as f64 / uncompressed_size.max(1) as f64);
+            }
+            let hash: String = Sha256::digest(&raw).iter().map(|b| format!("{b:02x}")).collect();
+            say!("sha-256:           {hash}");
+            if let PacFile::Bmz { .. } = file {
+                let bmp = BmpInfo::parse(&file.converted_data()?)?;
+                say!("dimensions:        {}x{}", bmp.width, bmp.height.abs());
+                say!("bit depth:         {}", bmp.bit_depth);
+            }
+        },
+        Commands::Hexdump { arc, entry, offset, len, converted, no_convert } => {
+            let arc = open_arc(&arc)?;
+            let Some(entry) = arc.find_entry(&entry) else {
+                bail!(PacError::InvalidInput(format!("no such entry: {entry}")))
+            };
+
+            let data = if converted {
+                Cow::Owned(arc.read_converted(entry, ImageFormat::Bmp, &converters(extensions, &no_convert)?)?)
+            } else {
+                arc.read_raw(entry)?
+            };
+            if offset > data.len() {
+                bail!(PacError::InvalidInput(format!("offset {offset} is past end of entry ({} bytes)", data.len())))
+            }
+            let end = len.map_or(data.len(), |len| data.len().min(offset.saturating_add(len)));
+
+            let mut out = std::io::stdout().lock();
+            for (line, chunk) in data[offset..end].chunks(16).enumerate() {
+                let hex: Vec<_> = chunk.iter().map(|b| format!("{b:02x}")).collect();
+                let (left, right) = hex.split_at(hex.len().min(8));
+                let ascii: String = chunk.iter()
+                    .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+                    .collect();
+                writeln!(
+                    out, "{:08x}  {:<23}  {:<23}  |{ascii}|",
+                    offset + line * 16, left.join(" "), right.join(" "),
+                )?;
+            }
+        },
+        Commands::Grep { arc, pattern, hex } => {
+            let needles = if hex {
+                vec![("bytes", parse_hex(&pattern)?)]
+            } else {
+                let (sjis, _, unmappable) = SHIFT_JIS.encode(&pattern);
+                let mut needles = vec![("utf-8", pattern.as_bytes().to_vec())];
+                if !unmappable && sjis != pattern.as_bytes() {
+                    needles.push(("shift-jis", sjis.into_owned()));
+                }
+                needles
+            };
+            if needles.iter().any(|(_, n)| n.is_empty()) {
+                bail!(PacError::InvalidInput("empty pattern".to_string()))
+            }
+
+            let arc = open_arc(&arc)?;
+            let mut matches = 0;
+            for (idx, entry) in arc.entries.iter().enumerate() {
+                let raw = arc.read_raw(entry)?;
+                let data = match PacFile::from_raw_as(&raw, arc.format.endian)? {
+                    file @ PacFile::Bmz { .. } => Cow::Owned(file.converted_data()?),
+                    _ => raw,
+                };
+                let name = entry.name().unwrap_or_else(|_| format!("entry{idx}"));
+                for (encoding, needle) in needles.iter() {
+                    for offset in find_all(&data, needle) {
+                        say!("{name}: {offset:#x} ({encoding})");
+                        matches += 1;
+                    }
+                }
+            }
+            if matches == 0 {
+                bail!("no matches found")
+            }
+        },
+        Commands::Thumbs { arc, out_image, size, columns } => {
+            let (sheet, count) = contact_sheet(&open_arc(&arc)?, &SheetOptions { thumb_size: size, columns })?;
+            sheet.save(&out_image)
+                .with_context(|| format!("Failed to write {out_image}"))?;
+            say!("{count} sprites written to {out_image} ({}x{})", sheet.width(), sheet.height());
+        },
+        Commands::Ttp { cmd } => ttp_command(cmd)?,
+        Commands::Patch { cmd } => patch_command(cmd)?,
+    }
+
+    Ok(())
+}
+
+fn patch_command(cmd: PatchCommands) -> Result<()> {
+    match cmd {
+        PatchCommands::Create { base, modified, out_patch } => {
+            let patch = Patch::create(&open_arc(&base)?, &open_arc(&modified)?)?;
+            patch.save(&out_patch)?;
+            say!(
+                "Patch created: {} entries, {} stored ({})",
+                patch.entries.len(), patch.blobs.len(), HumanBytes(patch.blobs_size()),
+            )
+        },
+        PatchCommands::Apply { base, patch, out_arc } => {
+            let builder = Patch::load(&patch)?.apply(&open_arc(&base)?)?;
+            written("patch apply", &out_arc, &builder.pack(&out_arc)?);
+            say!("Patch applied")
+        },
+    }
+
+    Ok(())
+}
+
+/// Built-in converters with configured extension mappings, except for `disabled` ones
+fn converters(extensions: &[ExtMapping], disabled: &[String]) -> Result<Converters> {
+    let mut converters = Converters::default().with_mappings(extensions)?;
+    for name in disabled {
+        converters.disable(name)?;
+    }
+    Ok(converters)
+}
+
+/// Read binary animation or, if `path` has `.json`, `.yaml` or `.toml` extension, decoded one
+fn load_ttp(path: &str) -> Result<TtpFile> {
+    let ttp = match TextFormat::from_path(path) {
+        Some(format) => format.parse(&std::fs::read(path)?),
+        None => TtpFile::read_le(&mut BufReader::new(File::open(path)?)).map_err(Into::into),
+    };
+    ttp.with_context(|| format!("Failed to parse animation {path}"))
+}
+
+/// Write animation in the same form `load_ttp` reads it from `path`
+fn save_ttp(path: &str, ttp: &TtpFile) -> Result<()> {
+    match TextFormat::from_path(path) {
+        Some(format) => std::fs::write(path, format.to_string(ttp)?)?,
+        None => {
+            let mut out = BufWriter::new(File::create(path)?);
+            out.write_le(ttp)?;
+            out.flush()?;
+        },
+    }
+    Ok(())
+}
+
+fn ttp_command(cmd: TtpCommands) -> Result<()> {
+    match cmd {
+        TtpCommands::Decode { ttp, out_text, format } => {
+            let mut f = File::open(&ttp)?;
+            let ttp = TtpFile::read_le(&mut f)
+                .with_context(|| format!("Failed to parse animation {ttp}"))?;
+
+            let format = format.or(TextFormat::from_path(&out_text)).unwrap_or(TextFormat::Json);
+            std::fs::write(out_text, format.to_string(&ttp)?)?;
+            say!("Animation decoded");
+        },
+        TtpCommands::Encode { text, out_ttp, format } => {
+            let data = std::fs::read(&text)?;
+            let format = format.or(TextFormat::from_path(&text)).unwrap_or(TextFormat::Json);
+            let ttp = format.parse(&data)
+                .with_context(|| format!("Failed to parse animation {text}"))?;
+            ttp.validate()?;
+
+            let mut out = File::create(out_ttp)?;
+            out.write_le(&ttp)?;
+            say!("Animation encoded");
+        },
+        TtpCommands::Check { ttp, arc } => {
+            let pac = open_arc(&arc)?;
+            let anims = match ttp.is_empty() {
+                true => pac.entries.iter()
+                    .filter_map(|entry| match pac.read_file(entry) {
+                        Ok(PacFile::Ttp(ttp)) => Some(entry.name().map(|name| (name, ttp))),
+                        Ok(_) => None,
+                        Err(e) => Some(Err(e)),
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+                false => ttp.iter()
+                    .map(|path| Ok((path.clone(), load_ttp(path)?)))
+                    .collect::<Result<Vec<_>>>()?,
+            };
+
+            let check = check_resources(&anims, &pac)?;
+            for m in check.missing.iter() {
+                say!("missing: {} frame {}: {} {}.{}", m.anim, m.frame, m.kind.field(), m.name, m.kind.ext());
+            }
+            for name in check.unused.iter() {
+                say!("unused: {name}");
+            }
+            say!(
+                "{} animations checked, {} missing and {} unused resources",
+                anims.len(), check.missing.len(), check.unused.len(),
+            );
+            if !check.missing.is_empty() {
+                bail!("animations reference missing resources")
+            }
+        },
+        TtpCommands::Summary { ttp } => {
+            let anim = load_ttp(&ttp)?;
+            let total_ms: u64 = anim.frames.iter().map(|f| f.delay_ms as u64).sum();
+            let delays: Vec<_> = anim.frames.iter().map(|f| f.delay_ms.to_string()).collect();
+
+            say!("type:           {}", anim.maybe_ttp_type);
+            say!("frames:         {}", anim.frames.len());
+            say!("window:         {}x{}", anim.window_width, anim.window_height);
+            say!("total duration: {}.{:03}s", total_ms / 1000, total_ms % 1000);
+            say!("delays (ms):    {}", delays.join(", "));
+            if let Some(dont_play_sound) = anim.onetime_wakeup_dont_play_sound {
+                say!("onetime wakeup dont play sound: {dont_play_sound}");
+            }
+            for (kind, label) in [(ResKind::Sprite, "sprites:"), (ResKind::Se, "sounds:"), (ResKind::Textbox, "textboxes:")] {
+                let mut names = vec![];
+                for (_, res) in anim.frames.iter().flat_map(|f| f.resources()).filter(|(k, _)| *k == kind) {
+                    let name = res.decode()?;
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+                say!("{label:<16}{}", names.join(", "));
+            }
+        },
+        TtpCommands::Retime { ttp, scale, min_delay, set_delay, out } => {
+            if scale.is_none() && min_delay.is_none() && set_delay.is_none() {
+                bail!(PacError::InvalidInput("nothing to do, specify --scale, --min-delay or --set-delay".to_string()))
+            }
+            if scale.is_some_and(|scale| !scale.is_finite() || scale < 0.0) {
+                bail!(PacError::InvalidInput("scale must be non-negative number".to_string()))
+            }
+            let mut anim = load_ttp(&ttp)?;
+            anim.map_delays(|delay| {
+                let delay = match (set_delay, scale) {
+                    (Some(set), _) => set,
+                    (None, Some(scale)) => (delay as f64 * scale).round().min(u32::MAX as f64) as u32,
+                    (None, None) => delay,
+                };
+                delay.max(min_delay.unwrap_or(0))
+            });
+            save_ttp(out.as_ref().unwrap_or(&ttp), &anim)?;
+            say!("{} frames retimed", anim.frames.len());
+        },
+        TtpCommands::Frames { cmd } => {
+            let (ttp, out) = match &cmd {
+                FrameCommands::Insert { ttp, out, .. }
+                | FrameCommands::Delete { ttp, out, .. }
+                | FrameCommands::Duplicate { ttp, out, .. }
+                | FrameCommands::Move { ttp, out, .. } => (ttp, out.as_ref().unwrap_or(ttp)),
+            };
+            let mut anim = load_ttp(ttp)?;
+            match &cmd {
+                FrameCommands::Insert { index, frame, .. } => {
+                    let frame: TtpFrame = serde_json::from_str(frame).context("Failed to parse frame")?;
+                    anim.insert_frame(*index, frame)?;
+                },
+                FrameCommands::Delete { index, .. } => {
+                    anim.remove_frame(*index)?;
+                },
+                FrameCommands::Duplicate { index, .. } => anim.duplicate_frame(*index)?,
+                FrameCommands::Move { from, to, .. } => anim.move_frame(*from, *to)?,
+            }
+            save_ttp(out, &anim)?;
+            say!("Animation now has {} frames", anim.frames.len());
+        },
+        TtpCommands::Render { ttp, arc, out, format } => {
+            let frames = render_frames(&load_ttp(&ttp)?, &open_arc(&arc)?)?;
+            let frames_count = frames.len();
+            format.write(frames, BufWriter::new(File::create(&out)?))?;
+            say!("{frames_count} frames rendered");
+        },
+        TtpCommands::Sheet { ttp, arc, out, atlas } => {
+            let atlas_path = atlas.unwrap_or_else(|| out.with_extension("json"));
+            let image = out.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            let (sheet, atlas) = sprite_sheet(&load_ttp(&ttp)?, &open_arc(&arc)?, &image)?;
+            sheet.save_with_format(&out, image::ImageFormat::Png)
+                .with_context(|| format!("Failed to save {}", out.display()))?;
+            std::fs::write(&atlas_path, serde_json::to_string_pretty(&atlas)?)?;
+            say!(
+                "{} frames packed to {} ({}x{}), atlas written to {}",
+                atlas.frames.len(), out.display(), sheet.width(), sheet.height(), atlas_path.display(),
+            );
         },
     }
 
@@ -0,0 +1,243 @@
+//! Manifests describing archive contents: written on extraction to restore archive on packing,
+//! or written by hand to control packing
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use anyhow::{Result, Context, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::error::PacError;
+use crate::pac::{PackOptions, ShortenedName};
+
+/// Extraction manifest, stored in output directory
+#[derive(Serialize, Deserialize, Default)]
+pub struct ExtractManifest {
+    /// Extracted entries in archive order
+    pub entries: Vec<ManifestEntry>,
+    /// Modification time of extracted archive, see `file_mtime`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_mtime: Option<u64>,
+}
+
+/// Extracted entry
+#[derive(Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Name of entry in archive
+    pub name: String,
+    /// Path of extracted file, relative to output directory
+    pub file: String,
+    /// Hex-encoded name bytes, present if `name` doesn't represent them exactly
+    /// (e.g. name is not valid SHIFT-JIS)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_name: Option<String>,
+    /// Path of entry data as stored in archive, written next to converted `file` with `ExtractOptions::keep_raw`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_file: Option<String>,
+    /// Modification time of extracted file right after extraction, see `file_mtime`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<u64>,
+}
+
+/// Modification time of file at `path` in nanoseconds since Unix epoch, `None` if it's not available
+pub fn file_mtime(path: impl AsRef<Path>) -> Option<u64> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    modified.duration_since(UNIX_EPOCH).ok()?.as_nanos().try_into().ok()
+}
+
+/// Set modification time of file at `path` to `mtime` nanoseconds since Unix epoch
+pub fn set_file_mtime(path: impl AsRef<Path>, mtime: u64) -> Result<()> {
+    let path = path.as_ref();
+    File::options().write(true).open(path)
+        .and_then(|f| f.set_modified(UNIX_EPOCH + Duration::from_nanos(mtime)))
+        .with_context(|| format!("Failed to set modification time of {}", path.display()))
+}
+
+impl ManifestEntry {
+    /// Hex-encode entry name bytes
+    pub fn encode_raw_name(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// Decode `raw_name`, if present
+    pub fn raw_name_bytes(&self) -> Result<Option<Vec<u8>>> {
+        let Some(hex) = &self.raw_name else {
+            return Ok(None)
+        };
+        if hex.len() % 2 != 0 || !hex.is_ascii() {
+            bail!(PacError::Format(format!("invalid raw name of {}: {hex}", self.name)))
+        }
+        (0..hex.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| PacError::Format(format!("invalid raw name of {}: {hex}", self.name)).into()))
+            .collect::<Result<_>>()
+            .map(Some)
+    }
+}
+
+impl ExtractManifest {
+    /// Name of manifest file in extraction directory
+    pub const FILE_NAME: &'static str = ".nipaa-manifest.json";
+
+    /// Load manifest from `dir`, `None` if there is no manifest
+    pub fn load(dir: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = dir.as_ref().join(Self::FILE_NAME);
+        if !path.exists() {
+            return Ok(None)
+        }
+
+        let data = std::fs::read(&path)?;
+        let manifest = serde_json::from_slice(&data)
+            .map_err(|e| PacError::Format(e.to_string()))
+            .with_context(|| format!("Failed to parse manifest {}", path.display()))?;
+        Ok(Some(manifest))
+    }
+
+    /// Save manifest to `dir`
+    pub fn save(&self, dir: impl AsRef<Path>) -> Result<()> {
+        let path = dir.as_ref().join(Self::FILE_NAME);
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Entries whose files in `dir` were modified or removed since extraction,
+    /// or have no recorded modification time
+    pub fn changed_entries<'a>(&'a self, dir: &'a Path) -> impl Iterator<Item = &'a ManifestEntry> + 'a {
+        self.entries.iter().filter(move |e| e.mtime.is_none() || file_mtime(dir.join(&e.file)) != e.mtime)
+    }
+}
+
+/// Full names of entries packed under shortened names, stored next to archive
+/// as `<archive>.names.json`, so extraction can restore them
+#[derive(Serialize, Deserialize, Default)]
+pub struct NameMap {
+    pub entries: Vec<ShortenedName>,
+}
+
+impl NameMap {
+    /// Path of name map of archive at `arc`
+    pub fn path_for(arc: impl AsRef<Path>) -> PathBuf {
+        let mut path = arc.as_ref().as_os_str().to_owned();
+        path.push(".names.json");
+        PathBuf::from(path)
+    }
+
+    /// Load name map of archive at `arc`, `None` if there is no name map
+    pub fn load(arc: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = Self::path_for(arc);
+        if !path.exists() {
+            return Ok(None)
+        }
+
+        let data = std::fs::read(&path)?;
+        let map = serde_json::from_slice(&data)
+            .map_err(|e| PacError::Format(e.to_string()))
+            .with_context(|| format!("Failed to parse name map {}", path.display()))?;
+        Ok(Some(map))
+    }
+
+    /// Save name map of archive at `arc`
+    pub fn save(&self, arc: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(Self::path_for(arc), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Hand-written packing manifest (toml).
+///
+/// ```toml
+/// # defaults for all entries
+/// level = 9
+///
+/// [[entries]]
+/// source = "sprites/mion01.png"   # relative to manifest
+/// name = "mion01.bmz"             # derived from source if omitted
+///
+/// [[entries]]
+/// source = "raw/mion02.bmp"
+/// compress = false                # per-entry conversion override
+/// ```
+///
+/// Entries are packed in listed order
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackManifest {
+    /// Default compression level
+    pub level: Option<u8>,
+    /// Default for image compression
+    pub compress: Option<bool>,
+    pub entries: Vec<PackManifestEntry>,
+}
+
+/// Entry of packing manifest
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackManifestEntry {
+    /// Source file path, relative to manifest
+    pub source: PathBuf,
+    /// Entry name in archive
+    pub name: Option<String>,
+    pub level: Option<u8>,
+    pub compress: Option<bool>,
+}
+
+/// Resolved packing manifest entry
+pub struct PackSource {
+    pub path: PathBuf,
+    /// Entry name, derived from file name if `None`
+    pub name: Option<String>,
+    /// Prepended to derived entry name (e.g. `chara/`)
+    pub prefix: String,
+    /// Exact entry name bytes, take precedence over `name`
+    pub raw_name: Option<Vec<u8>>,
+    pub options: PackOptions,
+}
+
+impl PackSource {
+    /// Name of entry packed from this source, `derived` being name derived from converted file name
+    pub fn entry_name(&self, derived: &str) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("{}{derived}", self.prefix),
+        }
+    }
+}
+
+impl PackManifest {
+    /// Load manifest from toml file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read_to_string(path)?;
+        toml::from_str(&data)
+            .map_err(|e| PacError::Format(e.to_string()))
+            .with_context(|| format!("Failed to parse manifest {}", path.display()))
+    }
+
+    /// Resolve source paths relative to `base_dir`, and conversion options on top of `defaults`
+    pub fn resolve(&self, base_dir: &Path, defaults: &PackOptions) -> Result<Vec<PackSource>> {
+        let defaults = Self::apply(defaults, self.level, self.compress)?;
+        self.entries.iter()
+            .map(|e| Ok(PackSource {
+                path: base_dir.join(&e.source),
+                name: e.name.clone(),
+                prefix: String::new(),
+                raw_name: None,
+                options: Self::apply(&defaults, e.level, e.compress)?,
+            }))
+            .collect()
+    }
+
+    fn apply(base: &PackOptions, level: Option<u8>, compress: Option<bool>) -> Result<PackOptions> {
+        let mut opts = base.clone();
+        if let Some(level) = level {
+            if level > PackOptions::MAX_LEVEL {
+                bail!(PacError::InvalidInput(format!("compression level must be in 0..={}, got {level}", PackOptions::MAX_LEVEL)))
+            }
+            opts.level = level;
+        }
+        if let Some(compress) = compress {
+            opts.compress = compress;
+        }
+        Ok(opts)
+    }
+}
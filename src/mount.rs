@@ -0,0 +1,196 @@
+//! Read-only FUSE filesystem exposing archive entries as converted files
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use anyhow::{Result, Context};
+use fuser::{FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, Request};
+
+use crate::converter::Converters;
+use crate::img::ImageFormat;
+use crate::pac::{ExtractOptions, NameDecoding, PacArc};
+use crate::source::PacSource;
+
+const ROOT_INO: u64 = 1;
+/// Archive doesn't change while mounted, so attributes may be cached for long
+const TTL: Duration = Duration::from_secs(3600);
+
+/// Mount `arc` at `mountpoint` and serve it until unmounted.
+/// Files are named and converted the same way as on extraction with `opts`
+pub fn mount<S: PacSource>(arc: PacArc<S>, mountpoint: impl AsRef<Path>, opts: &ExtractOptions) -> Result<()> {
+    let files = arc.plan_extraction(opts, false)?
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, target)| target.map(|t| (t.file, index)))
+        .collect();
+    let fs = PacFs {
+        arc,
+        image_format: opts.image_format,
+        converters: opts.converters.clone(),
+        files,
+        sizes: HashMap::new(),
+        cache: HashMap::new(),
+        mounted_at: SystemTime::now(),
+        // SAFETY: getuid and getgid have no preconditions and always succeed
+        uid: unsafe { libc::getuid() },
+        gid: unsafe { libc::getgid() },
+    };
+    let options = [MountOption::RO, MountOption::FSName("nipaa-pac".to_string()), MountOption::DefaultPermissions];
+    fuser::mount2(fs, mountpoint.as_ref(), &options)
+        .with_context(|| format!("Failed to mount at {}", mountpoint.as_ref().display()))
+}
+
+struct PacFs<S: PacSource> {
+    arc: PacArc<S>,
+    image_format: ImageFormat,
+    converters: Converters,
+    /// File name and entry index, file inode is `position + 2`
+    files: Vec<(String, usize)>,
+    /// Converted sizes, by entry index
+    sizes: HashMap<usize, u64>,
+    /// Converted data of open files, by entry index
+    cache: HashMap<usize, Vec<u8>>,
+    mounted_at: SystemTime,
+    uid: u32,
+    gid: u32,
+}
+
+impl<S: PacSource> PacFs<S> {
+    fn file_index(&self, ino: u64) -> Option<usize> {
+        let pos = ino.checked_sub(ROOT_INO + 1)? as usize;
+        self.files.get(pos).map(|(_, index)| *index)
+    }
+
+    fn converted(&mut self, index: usize) -> Result<&[u8]> {
+        if !self.cache.contains_key(&index) {
+            let entry = &self.arc.entries[index];
+            let name = entry.decode_name(NameDecoding::Replace)?;
+            let data = self.converters.convert_raw(&name, &self.arc.read_raw(entry)?, self.image_format, self.arc.format.endian)
+                .with_context(|| format!("Failed to convert {}", self.arc.describe_entry(entry)))?;
+            self.sizes.insert(index, data.len() as u64);
+            self.cache.insert(index, data);
+        }
+        Ok(&self.cache[&index])
+    }
+
+    fn size(&mut self, index: usize) -> Result<u64> {
+        if let Some(size) = self.sizes.get(&index) {
+            return Ok(*size)
+        }
+        let entry = &self.arc.entries[index];
+        let (name, raw) = (entry.decode_name(NameDecoding::Replace)?, self.arc.read_raw(entry)?);
+        let size = match self.converters.converted_size_raw(&name, &raw, self.image_format, self.arc.format.endian) {
+            Some(size) => size,
+            None => {
+                let size = self.converted(index)?.len() as u64;
+                // don't keep data of files that are only listed
+                self.cache.remove(&index);
+                size
+            },
+        };
+        self.sizes.insert(index, size);
+        Ok(size)
+    }
+
+    fn attr(&mut self, ino: u64) -> Option<FileAttr> {
+        let (kind, size, perm, nlink) = match ino {
+            ROOT_INO => (FileType::Directory, 0, 0o555, 2),
+            _ => {
+                let index = self.file_index(ino)?;
+                (FileType::RegularFile, self.size(index).ok()?, 0o444, 1)
+            },
+        };
+        Some(FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: self.mounted_at,
+            mtime: self.mounted_at,
+            ctime: self.mounted_at,
+            crtime: self.mounted_at,
+            kind,
+            perm,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        })
+    }
+}
+
+impl<S: PacSource> Filesystem for PacFs<S> {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let pos = self.files.iter().position(|(file, _)| OsStr::new(file) == name);
+        match pos.filter(|_| parent == ROOT_INO).and_then(|pos| self.attr(pos as u64 + ROOT_INO + 1)) {
+            Some(attr) => reply.entry(&TTL, &attr, 0),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.attr(ino) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let Some(index) = self.file_index(ino) else {
+            return reply.error(libc::ENOENT)
+        };
+        match self.converted(index) {
+            Ok(data) => {
+                let start = (offset.max(0) as usize).min(data.len());
+                let end = start.saturating_add(size as usize).min(data.len());
+                reply.data(&data[start..end])
+            },
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        if let Some(index) = self.file_index(ino) {
+            self.cache.remove(&index);
+        }
+        reply.ok()
+    }
+
+    fn readdir(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        if ino != ROOT_INO {
+            return reply.error(libc::ENOTDIR)
+        }
+        let dots = [(ROOT_INO, FileType::Directory, "."), (ROOT_INO, FileType::Directory, "..")];
+        let files = self.files.iter()
+            .enumerate()
+            .map(|(pos, (file, _))| (pos as u64 + ROOT_INO + 1, FileType::RegularFile, file.as_str()));
+        for (i, (ino, kind, name)) in dots.into_iter().chain(files).enumerate().skip(offset.max(0) as usize) {
+            // offset of the next entry
+            if reply.add(ino, i as i64 + 1, kind, name) {
+                break
+            }
+        }
+        reply.ok()
+    }
+}
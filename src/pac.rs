@@ -0,0 +1,2086 @@
+//! `.pac` archive reading and writing
+
+use binrw::{
+    BinRead, BinReaderExt, NullString, BinWrite, binwrite, BinWriterExt, Endian
+};
+use std::borrow::Cow;
+use std::collections::{hash_map, HashMap};
+use std::fmt::Display;
+use std::io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+use std::fs::File;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use anyhow::{Result, bail, Context};
+use miniz_oxide::deflate::core::{compress, create_comp_flags_from_zip_params, CompressionStrategy, CompressorOxide, TDEFLFlush, TDEFLStatus};
+use miniz_oxide::inflate::{DecompressError, TINFLStatus};
+use encoding_rs::{DecoderResult, SHIFT_JIS};
+use glob::Pattern;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::converter::Converters;
+use crate::deflate::{self, DeflateBackend};
+use crate::error::PacError;
+use crate::format::PacFormat;
+use crate::img::ImageFormat;
+use crate::manifest::{file_mtime, ExtractManifest, ManifestEntry};
+use crate::source::{DynSource, PacSource, ReaderSource};
+use crate::ttp::TtpFile;
+use crate::verify::Problem;
+
+/// Size of the null-padded entry name field of default archive format
+pub const ENTRY_NAME_SIZE: usize = 56;
+
+/// Size of serialized entry table record of default layout: offset, size and name field
+pub const ENTRY_RECORD_SIZE: usize = PacFormat::RECORD_FIELDS_SIZE + ENTRY_NAME_SIZE;
+
+/// Magic of BMZ files
+pub(crate) const BMZ_MAGIC: &[u8; 4] = b"ZLC3";
+
+/// Largest size BMZ stream is allowed to inflate to, so crafted streams can't exhaust memory
+pub const MAX_INFLATED_SIZE: usize = 256 << 20;
+
+/// Inflate BMZ stream, producing at most `MAX_INFLATED_SIZE` bytes
+pub(crate) fn inflate(data: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    deflate::backend().inflate(data, MAX_INFLATED_SIZE)
+}
+
+/// Inflate BMZ stream which header declares bitmap of `declared` bytes. Declared size caps inflation
+/// (as does `MAX_INFLATED_SIZE`), so streams inflating to more than it are rejected without allocating more
+pub(crate) fn inflate_bmz(data: &[u8], declared: u32) -> Result<Vec<u8>, PacError> {
+    deflate::backend().inflate(data, bmz_limit(declared)).map_err(|e| match e.status {
+        TINFLStatus::HasMoreOutput => bmz_size_error(declared),
+        _ => PacError::from(e),
+    })
+}
+
+/// Same as `inflate_bmz`, writing bitmap to `out` in chunks. Returns its size
+pub(crate) fn inflate_bmz_to(data: &[u8], declared: u32, out: &mut dyn Write) -> Result<u64> {
+    deflate::backend().inflate_to(data, bmz_limit(declared), out).map_err(|e| match e.downcast_ref::<PacError>() {
+        Some(PacError::SizeLimit(_)) => bmz_size_error(declared).into(),
+        _ => e,
+    })
+}
+
+fn bmz_limit(declared: u32) -> usize {
+    (declared as usize).min(MAX_INFLATED_SIZE)
+}
+
+fn bmz_size_error(declared: u32) -> PacError {
+    match declared as usize > MAX_INFLATED_SIZE {
+        true => PacError::SizeLimit(format!("bmz stream inflates to more than {MAX_INFLATED_SIZE} bytes")),
+        false => PacError::SizeLimit(format!(
+            "bmz stream inflates to more than {declared} bytes declared in its header (headers can be corrected with `fix`)"
+        )),
+    }
+}
+
+/// Deflate `data` to zlib stream with compression `level` (0-10) and `strategy`
+fn deflate_with(data: &[u8], level: u8, strategy: CompressionStrategy) -> Vec<u8> {
+    let flags = create_comp_flags_from_zip_params(level.into(), 1, strategy as i32);
+    let mut compressor = CompressorOxide::new(flags);
+    let mut output = vec![0; (data.len() / 2).max(64)];
+    let (mut in_pos, mut out_pos) = (0, 0);
+    loop {
+        let (status, bytes_in, bytes_out) = compress(&mut compressor, &data[in_pos..], &mut output[out_pos..], TDEFLFlush::Finish);
+        in_pos += bytes_in;
+        out_pos += bytes_out;
+        match status {
+            TDEFLStatus::Done => break,
+            TDEFLStatus::Okay if output.len() - out_pos < 64 => output.resize(output.len() * 2, 0),
+            TDEFLStatus::Okay => {},
+            status => unreachable!("deflate of in-memory buffer failed: {status:?}"),
+        }
+    }
+    output.truncate(out_pos);
+    output
+}
+
+/// Struct for reading archive entries.
+/// Only entry table record is kept, data is read on demand with [`PacArc::read_file`]
+///
+/// Real layout:
+/// ```text
+/// ptr: u32,
+/// size: u32,
+/// name: [u8; name_size]
+/// ```
+#[derive(BinRead)]
+#[br(import(name_size: usize))]
+pub struct PacEntryRead {
+    /// Offset of entry data from start of archive
+    pub offset: u32,
+    pub size: u32,
+    #[br(pad_size_to = name_size)]
+    pub name: NullString,
+}
+
+impl PacEntryRead {
+    /// Try to get file name
+    pub fn name(&self) -> Result<String> {
+        match SHIFT_JIS.decode(&self.name) {
+            (cow, _, false) => Ok(cow.to_string()),
+            (cow, _, true) => bail!(PacError::NameEncoding(format!("failed to normally decode string: {cow}")))
+        }
+    }
+
+    /// Get file name, handling undecodable bytes according to `decoding`
+    pub fn decode_name(&self, decoding: NameDecoding) -> Result<String> {
+        match decoding {
+            NameDecoding::Strict => self.name(),
+            NameDecoding::Replace => Ok(SHIFT_JIS.decode(&self.name).0.into_owned()),
+            NameDecoding::Hex => Ok(decode_hex_escaped(&self.name)),
+        }
+    }
+}
+
+/// How to treat bytes of entry names that are not valid SHIFT-JIS
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum NameDecoding {
+    /// Fail to decode name
+    #[default]
+    Strict,
+    /// Substitute replacement character `U+FFFD`
+    Replace,
+    /// Substitute `%XX` escape of each malformed byte
+    Hex,
+}
+
+impl FromStr for NameDecoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "strict" => Ok(NameDecoding::Strict),
+            "replace" => Ok(NameDecoding::Replace),
+            "hex" => Ok(NameDecoding::Hex),
+            _ => bail!(PacError::InvalidInput(format!("unknown name decoding: {s} (expected strict, replace or hex)"))),
+        }
+    }
+}
+
+impl Display for NameDecoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            NameDecoding::Strict => "strict",
+            NameDecoding::Replace => "replace",
+            NameDecoding::Hex => "hex",
+        })
+    }
+}
+
+/// Decode SHIFT-JIS, writing malformed bytes as `%XX`
+fn decode_hex_escaped(bytes: &[u8]) -> String {
+    let mut decoder = SHIFT_JIS.new_decoder_without_bom_handling();
+    let mut out = String::with_capacity(bytes.len() * 3);
+    let mut rest = bytes;
+    loop {
+        // every input byte produces at most 3 bytes of UTF-8
+        out.reserve(rest.len() * 3 + 4);
+        let (result, read) = decoder.decode_to_string_without_replacement(rest, &mut out, true);
+        match result {
+            DecoderResult::InputEmpty => return out,
+            DecoderResult::OutputFull => rest = &rest[read..],
+            DecoderResult::Malformed(bad, after) => {
+                // malformed sequence ends `after` bytes before current position,
+                // bytes consumed after it are decoded again by fresh decoder
+                let end = read - after as usize;
+                for b in &rest[end - bad as usize..end] {
+                    out.push_str(&format!("%{b:02X}"));
+                }
+                rest = &rest[end..];
+                decoder = SHIFT_JIS.new_decoder_without_bom_handling();
+            },
+        }
+    }
+}
+
+/// Struct for reading Pac archive.
+///
+/// Only entry table is read on opening, entries data is read from underlying source on demand
+pub struct PacArc<S = ReaderSource<BufReader<File>>> {
+    pub entries: Vec<PacEntryRead>,
+    /// Layout archive was read with
+    pub format: PacFormat,
+    source: S,
+    /// Built on first lookup by name
+    names: OnceLock<NameIndex>,
+}
+
+/// Positions of entries by raw and by decoded name, first entry wins on duplicates
+struct NameIndex {
+    /// Number of entries index was built for
+    len: usize,
+    by_raw: HashMap<Vec<u8>, usize>,
+    by_name: HashMap<String, usize>,
+}
+
+impl NameIndex {
+    fn build(entries: &[PacEntryRead]) -> Self {
+        let (mut by_raw, mut by_name) = (HashMap::new(), HashMap::new());
+        for (index, entry) in entries.iter().enumerate() {
+            by_raw.entry(entry.name.0.clone()).or_insert(index);
+            if let Ok(name) = entry.name() {
+                by_name.entry(name).or_insert(index);
+            }
+        }
+        Self { len: entries.len(), by_raw, by_name }
+    }
+}
+
+impl PacArc {
+    /// Open archive file
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl PacArc<memmap2::Mmap> {
+    /// Open archive file by memory-mapping it.
+    ///
+    /// File must not be modified while archive is open
+    pub fn open_mmap(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_source(crate::source::map_file(path)?)
+    }
+}
+
+impl<R: Read + Seek + Send> PacArc<ReaderSource<R>> {
+    /// Read entry table from `reader`
+    pub fn read(reader: R) -> Result<Self> {
+        Self::from_source(ReaderSource::new(reader))
+    }
+}
+
+impl<S: PacSource> PacArc<S> {
+    /// Read entry table from `source`, detecting its layout (default one is assumed if none fits).
+    ///
+    /// Entry table and data of all entries must fit in source, so sizes declared by damaged
+    /// or crafted archives are never trusted for allocations
+    pub fn from_source(source: S) -> Result<Self> {
+        let format = PacFormat::detect(&source)?.unwrap_or_default();
+        Self::from_source_as(source, format)
+    }
+
+    /// Read entry table from `source` with layout `format`
+    pub fn from_source_as(source: S, format: PacFormat) -> Result<Self> {
+        let source_len = source.size()?;
+        if source_len < format.table_offset() {
+            bail!(PacError::Format(format!("file is too short to be archive ({source_len} bytes)")))
+        }
+        if source.read_at(0, format.magic.len())?[..] != format.magic[..] {
+            bail!(PacError::Format(format!("archive doesn't start with magic {}", String::from_utf8_lossy(&format.magic))))
+        }
+        let entries_count: u32 = Cursor::new(source.read_at(format.count_offset(), 4)?).read_type(format.endian)?;
+        let table_start = format.table_offset();
+        let table_end = format.table_end(entries_count as u64);
+        if table_end > source_len {
+            bail!(PacError::SizeLimit(format!(
+                "entry table of {entries_count} entries ends at {table_end}, past end of file ({source_len})"
+            )))
+        }
+        let record_size = format.record_size();
+        let table = source.read_at(table_start, entries_count as usize * record_size)?;
+        let mut table = Cursor::new(table);
+        let entries: Vec<PacEntryRead> = (0..entries_count as usize)
+            .map(|index| table.read_type_args::<PacEntryRead>(format.endian, (format.name_size,))
+                .map_err(|e| parse_error(e, table_start))
+                .with_context(|| format!(
+                    "Failed to read entry table record {index} at offset {}", table_start as usize + index * record_size
+                )))
+            .collect::<Result<_>>()?;
+
+        for (index, e) in entries.iter().enumerate() {
+            let end = e.offset as u64 + e.size as u64;
+            if end > source_len {
+                bail!(PacError::SizeLimit(format!(
+                    "entry {index} ({}): data range {}..{end} is past end of file ({source_len})",
+                    SHIFT_JIS.decode(&e.name).0, e.offset,
+                )))
+            }
+        }
+
+        Ok(Self { entries, format, source, names: OnceLock::new() })
+    }
+
+    /// Read as much of entry table from `source` as it's present, detecting its layout.
+    /// Entries with data past end of source are dropped, truncated ones are clamped to the end of source.
+    /// Returns archive and list of found problems
+    pub fn from_source_salvage(source: S) -> Result<(Self, Vec<Problem>)> {
+        let format = PacFormat::detect(&source)?.unwrap_or_default();
+        Self::from_source_salvage_as(source, format)
+    }
+
+    /// Same as `from_source_salvage`, with layout `format`
+    pub fn from_source_salvage_as(source: S, format: PacFormat) -> Result<(Self, Vec<Problem>)> {
+        let source_len = source.size()?;
+        if source_len < format.table_offset() {
+            bail!(PacError::Format(format!("file is too short to be archive ({source_len} bytes)")))
+        }
+        let mut problems = vec![];
+        let declared: u32 = Cursor::new(source.read_at(format.count_offset(), 4)?).read_type(format.endian)?;
+        let declared = declared as u64;
+        let record_size = format.record_size();
+        let present = declared.min((source_len - format.table_offset()) / record_size as u64);
+        if present < declared {
+            problems.push(Problem {
+                index: None,
+                name: String::new(),
+                message: format!("entry table truncated, {declared} entries declared, only {present} present"),
+            });
+        }
+
+        let table = source.read_at(format.table_offset(), present as usize * record_size)?;
+        let mut table = Cursor::new(table);
+        let mut entries = Vec::with_capacity(present as usize);
+        for index in 0..present as usize {
+            let mut e: PacEntryRead = table.read_type_args(format.endian, (format.name_size,))?;
+            let end = e.offset as u64 + e.size as u64;
+            if end > source_len {
+                let name = SHIFT_JIS.decode(&e.name).0.into_owned();
+                if e.offset as u64 >= source_len {
+                    let message = format!("data starts past end of file ({}), entry dropped", e.offset);
+                    problems.push(Problem { index: Some(index), name, message });
+                    continue
+                }
+                let clamped = (source_len - e.offset as u64) as u32;
+                let message = format!("data truncated, {} bytes declared, only {clamped} present", e.size);
+                problems.push(Problem { index: Some(index), name, message });
+                e.size = clamped;
+            }
+            entries.push(e);
+        }
+
+        Ok((Self { entries, format, source, names: OnceLock::new() }, problems))
+    }
+
+    /// Iterate over entries in archive order, as handles reading data of their entry on demand
+    pub fn entries(&self) -> impl ExactSizeIterator<Item = EntryRef<'_, S>> {
+        self.entries.iter().enumerate().map(|(index, entry)| EntryRef { arc: self, index, entry })
+    }
+
+    /// Erase type of underlying source
+    pub fn into_dyn(self) -> PacArc<DynSource> where S: 'static {
+        PacArc { entries: self.entries, format: self.format, source: Box::new(self.source), names: self.names }
+    }
+
+    /// Describe entry for error messages: index, name (with `%XX` escapes of undecodable bytes)
+    /// and location of its data
+    pub fn describe_entry(&self, entry: &PacEntryRead) -> String {
+        let name = entry.decode_name(NameDecoding::Hex).unwrap_or_default();
+        let location = format!("{} bytes at offset {}", entry.size, entry.offset);
+        match self.entries.iter().position(|e| std::ptr::eq(e, entry)) {
+            Some(index) => format!("entry {index} ({name}, {location})"),
+            None => format!("entry {name} ({location})"),
+        }
+    }
+
+    /// Read entry data as stored in archive
+    pub fn read_raw(&self, entry: &PacEntryRead) -> Result<Cow<'_, [u8]>> {
+        self.source.read_at(entry.offset as u64, entry.size as usize)
+            .with_context(|| format!("Failed to read {}", self.describe_entry(entry)))
+    }
+
+    /// Read and parse entry data
+    pub fn read_file(&self, entry: &PacEntryRead) -> Result<PacFile> {
+        let raw = self.read_raw(entry)?;
+        Cursor::new(&raw[..]).read_type_args::<PacFile>(self.format.endian, (raw.len() as u32,))
+            .map_err(|e| parse_error(e, entry.offset as u64))
+            .with_context(|| format!("Failed to parse {}", self.describe_entry(entry)))
+    }
+
+    /// Read entry data converted with `converters` the same way as on extraction
+    /// (`Converters::builtin()` for default conversion)
+    pub fn read_converted(&self, entry: &PacEntryRead, image_format: ImageFormat, converters: &Converters) -> Result<Vec<u8>> {
+        let name = entry.decode_name(NameDecoding::Replace)?;
+        converters.convert_raw(&name, &self.read_raw(entry)?, image_format, self.format.endian)
+            .with_context(|| format!("Failed to convert {}", self.describe_entry(entry)))
+    }
+
+    /// Same as `read_converted`, but converted data is written to `out`. BMZ entries extracted as BMP
+    /// are inflated in chunks, so whole bitmap isn't kept in memory. Returns number of written bytes
+    pub fn extract_entry_to(
+        &self,
+        entry: &PacEntryRead,
+        image_format: ImageFormat,
+        converters: &Converters,
+        out: &mut impl Write,
+    ) -> Result<u64> {
+        let name = entry.decode_name(NameDecoding::Replace)?;
+        converters.convert_raw_to(&name, &self.read_raw(entry)?, image_format, self.format.endian, out)
+            .with_context(|| format!("Failed to convert {}", self.describe_entry(entry)))
+    }
+}
+
+/// Entry struct for writing to archive
+#[binwrite]
+#[bw(import(name_size: usize))]
+struct PacEntryWrite {
+    pub offset: u32,
+    pub size: u32,
+    #[bw(pad_size_to = name_size)]
+    pub name: NullString,
+    #[bw(ignore)]
+    pub data: EntryData,
+    /// Offset and size of data in archive builder was seeded from, if data wasn't changed since
+    #[bw(ignore)]
+    pub origin: Option<(u32, u32)>,
+}
+
+/// Data of entry to write
+enum EntryData {
+    File(PacFile),
+    /// Data exactly as it's stored, spooled to temporary file by `PacArcBuilder::add_entry_from_reader`
+    Spooled { file: File, size: u32 },
+    /// Data left in archive builder was seeded from by `PacArcBuilder::from_existing`,
+    /// read only if it has to be written
+    Seeded { offset: u32, size: u32 },
+}
+
+impl EntryData {
+    /// Size of data as it's stored in archive
+    fn stored_size(&self, endian: Endian) -> Result<u64> {
+        Ok(match self {
+            EntryData::File(PacFile::Bmz { compressed_data, .. }) => (PacFile::BMZ_HEADER_SIZE + compressed_data.len()) as u64,
+            EntryData::File(PacFile::Other { data }) => data.len() as u64,
+            EntryData::File(file) => file.raw_data_as(endian)?.len() as u64,
+            EntryData::Spooled { size, .. } | EntryData::Seeded { size, .. } => *size as u64,
+        })
+    }
+}
+
+/// Conversion of data added with `PacArcBuilder::add_entry_from_reader`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StreamConversion {
+    /// Data is stored as is
+    Store,
+    /// BMP bitmap is compressed to BMZ at level of builder options (zopfli level is treated as maximum one)
+    Bmz,
+}
+
+/// Serialized entry data: data kept in memory, with spooled data copied in between
+struct SerializedData {
+    buff: Vec<u8>,
+    /// Spooled data and position in `buff` it's copied at
+    spooled: Vec<(usize, File)>,
+}
+
+impl SerializedData {
+    fn write_to(self, out: &mut impl Write) -> Result<()> {
+        let mut pos = 0;
+        for (at, mut file) in self.spooled {
+            out.write_all(&self.buff[pos..at])?;
+            file.seek(SeekFrom::Start(0))?;
+            std::io::copy(&mut file, out)?;
+            pos = at;
+        }
+        out.write_all(&self.buff[pos..])?;
+        Ok(())
+    }
+}
+
+/// Options controlling conversion of source files when packing
+#[derive(Clone)]
+pub struct PackOptions {
+    /// zlib compression level of BMZ files, `0..=10` or `ZOPFLI_LEVEL`
+    pub level: u8,
+    /// Compress images to BMZ. If disabled, images are stored as plain BMP files
+    pub compress: bool,
+    /// Converters building entries back from source files, files none of them accept are stored as is
+    pub converters: Converters,
+}
+
+impl PackOptions {
+    pub const DEFAULT_LEVEL: u8 = 5;
+    pub const MAX_LEVEL: u8 = 10;
+    /// Level compressing with zopfli instead of deflate backend (`max` on command line):
+    /// smallest streams, but orders of magnitude slower
+    pub const ZOPFLI_LEVEL: u8 = 11;
+
+    /// Parse level given as `0..=10` or `max` (`ZOPFLI_LEVEL`)
+    pub fn parse_level(s: &str) -> Result<u8> {
+        match s {
+            "max" => Ok(Self::ZOPFLI_LEVEL),
+            _ => match s.parse() {
+                Ok(level) if level <= Self::MAX_LEVEL => Ok(level),
+                _ => bail!(PacError::InvalidInput(format!("invalid compression level: {s} (expected 0..={} or max)", Self::MAX_LEVEL))),
+            },
+        }
+    }
+}
+
+impl Default for PackOptions {
+    fn default() -> Self {
+        Self { level: Self::DEFAULT_LEVEL, compress: true, converters: Converters::default() }
+    }
+}
+
+/// What to do when merged archive contains entry that is already present
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ConflictPolicy {
+    /// Entry from merged archive replaces existing one (keeping its position)
+    #[default]
+    Last,
+    /// Existing entry is kept
+    First,
+    /// Merge fails
+    Error,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "last" => Ok(ConflictPolicy::Last),
+            "first" => Ok(ConflictPolicy::First),
+            "error" => Ok(ConflictPolicy::Error),
+            _ => bail!(PacError::InvalidInput(format!("unknown conflict policy: {s} (expected last, first or error)"))),
+        }
+    }
+}
+
+impl Display for ConflictPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ConflictPolicy::Last => "last",
+            ConflictPolicy::First => "first",
+            ConflictPolicy::Error => "error",
+        })
+    }
+}
+
+/// What to do when extracted entry would get file name already taken by another one
+/// (compared case-insensitively, as on Windows and macOS filesystems)
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum CollisionPolicy {
+    /// Entry index is appended to file name (`name~<index>.ext`)
+    #[default]
+    Suffix,
+    /// Extraction fails
+    Error,
+    /// `ExtractOptions::collision_prompt` decides, extraction fails if it's not set
+    Prompt,
+}
+
+impl FromStr for CollisionPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "suffix" => Ok(CollisionPolicy::Suffix),
+            "error" => Ok(CollisionPolicy::Error),
+            "prompt" => Ok(CollisionPolicy::Prompt),
+            _ => bail!(PacError::InvalidInput(format!("unknown collision policy: {s} (expected suffix, error or prompt)"))),
+        }
+    }
+}
+
+impl Display for CollisionPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CollisionPolicy::Suffix => "suffix",
+            CollisionPolicy::Error => "error",
+            CollisionPolicy::Prompt => "prompt",
+        })
+    }
+}
+
+/// How entry names containing path separators (`chara/mion01.bmz`, `/` or `\`) are extracted
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum PathMode {
+    /// Separators are replaced like other characters not allowed in file names, all files are extracted
+    /// right into output directory
+    #[default]
+    Flatten,
+    /// Name components become subdirectories of output directory
+    Preserve,
+}
+
+impl FromStr for PathMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "flatten" => Ok(PathMode::Flatten),
+            "preserve" => Ok(PathMode::Preserve),
+            _ => bail!(PacError::InvalidInput(format!("unknown path mode: {s} (expected preserve or flatten)"))),
+        }
+    }
+}
+
+impl Display for PathMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PathMode::Flatten => "flatten",
+            PathMode::Preserve => "preserve",
+        })
+    }
+}
+
+/// What to do with entry names that don't fit in name field of archive
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum LongNamePolicy {
+    /// Adding entry fails
+    #[default]
+    Error,
+    /// Name is cut at SHIFT-JIS character boundary, keeping extension
+    Truncate,
+    /// Name is cut and suffixed with hash of full name (`name~<hash>.ext`), so it stays unique
+    Hash,
+}
+
+impl FromStr for LongNamePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "error" => Ok(LongNamePolicy::Error),
+            "truncate" => Ok(LongNamePolicy::Truncate),
+            "hash" => Ok(LongNamePolicy::Hash),
+            _ => bail!(PacError::InvalidInput(format!("unknown long name policy: {s} (expected truncate, hash or error)"))),
+        }
+    }
+}
+
+impl Display for LongNamePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            LongNamePolicy::Error => "error",
+            LongNamePolicy::Truncate => "truncate",
+            LongNamePolicy::Hash => "hash",
+        })
+    }
+}
+
+/// File name collision on extraction
+pub struct Collision<'a> {
+    /// Name of colliding entry
+    pub name: &'a str,
+    /// File name it would be extracted to
+    pub file: &'a str,
+    /// Name of entry already extracted to this (or differing only by case) file name
+    pub taken_by: &'a str,
+    /// File name with index suffix, used by `CollisionPolicy::Suffix`
+    pub suggested: &'a str,
+}
+
+/// Decides file name for colliding entry, `None` skips it
+pub type CollisionFn = dyn Fn(&Collision) -> Result<Option<String>> + Send + Sync;
+
+/// Progress of extraction or packing, reported after each processed entry
+pub struct Progress<'a> {
+    /// Name of just processed entry
+    pub name: &'a str,
+    pub entries_done: usize,
+    pub entries_total: usize,
+    /// Total bytes written so far
+    pub bytes_written: u64,
+}
+
+/// Progress callback
+pub type ProgressFn = dyn Fn(Progress) + Send + Sync;
+
+/// Result of packing
+pub struct PackStats {
+    pub entries: usize,
+    /// Size of written archive
+    pub bytes_written: u64,
+    /// Size of entry data that was not written thanks to deduplication
+    pub dedup_saved: u64,
+    /// Entries stored under shortened names, see `PacArcBuilder::with_long_names`
+    pub shortened: Vec<ShortenedName>,
+    /// Size of data left in place by `PacArcBuilder::update`
+    pub kept_in_place: u64,
+}
+
+/// Entry name shortened to fit in name field
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ShortenedName {
+    /// Name entry is stored under
+    pub name: String,
+    /// Requested name
+    pub full_name: String,
+}
+
+/// BMZ header size correction, made by `PacArcBuilder::fix_bmz_sizes`
+pub struct SizeFix {
+    pub name: String,
+    /// Size declared in BMZ header
+    pub declared: u32,
+    /// Actual size of inflated stream
+    pub actual: u32,
+}
+
+/// BMZ entry recompressed by `PacArcBuilder::recompress_bmz`
+pub struct Recompression {
+    pub name: String,
+    /// Size of original zlib stream
+    pub before: u32,
+    /// Size of new zlib stream
+    pub after: u32,
+}
+
+/// Entry of archive, as yielded by [`PacArc::entries`]: table record and archive to read its data from
+pub struct EntryRef<'a, S: PacSource> {
+    arc: &'a PacArc<S>,
+    index: usize,
+    entry: &'a PacEntryRead,
+}
+
+impl<'a, S: PacSource> EntryRef<'a, S> {
+    /// Position in entry table
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Decoded name, error if it's not valid SHIFT-JIS
+    pub fn name(&self) -> Result<String> {
+        self.entry.name()
+    }
+
+    /// Size of data as stored in archive
+    pub fn size(&self) -> u32 {
+        self.entry.size
+    }
+
+    /// Offset of data from start of archive
+    pub fn offset(&self) -> u32 {
+        self.entry.offset
+    }
+
+    /// Entry table record
+    pub fn record(&self) -> &'a PacEntryRead {
+        self.entry
+    }
+
+    /// Same as `PacArc::read_raw`
+    pub fn read_raw(&self) -> Result<Cow<'a, [u8]>> {
+        self.arc.read_raw(self.entry)
+    }
+
+    /// Same as `PacArc::read_file`
+    pub fn read_file(&self) -> Result<PacFile> {
+        self.arc.read_file(self.entry)
+    }
+
+    /// Same as `PacArc::read_converted`
+    pub fn read_converted(&self, image_format: ImageFormat, converters: &Converters) -> Result<Vec<u8>> {
+        self.arc.read_converted(self.entry, image_format, converters)
+    }
+
+    /// Same as `PacArc::extract_entry_to`
+    pub fn extract_to(&self, image_format: ImageFormat, converters: &Converters, out: &mut impl Write) -> Result<u64> {
+        self.arc.extract_entry_to(self.entry, image_format, converters, out)
+    }
+}
+
+/// Builder for Pac archives
+pub struct PacArcBuilder {
+    entries: Vec<PacEntryWrite>,
+    options: PackOptions,
+    progress: Option<Box<ProgressFn>>,
+    dedup: bool,
+    format: PacFormat,
+    long_names: LongNamePolicy,
+    shortened: Vec<ShortenedName>,
+    align: u32,
+    /// Source of archive builder was seeded from, data of `EntryData::Seeded` entries is read from it
+    seed: Option<DynSource>,
+}
+
+impl Default for PacArcBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PacArcBuilder {
+    /// Largest possible archive, offsets of entry data are 32-bit
+    pub const MAX_ARCHIVE_SIZE: u64 = u32::MAX as u64;
+
+    /// Create new builder
+    pub fn new() -> Self {
+        Self {
+            entries: vec![],
+            options: PackOptions::default(),
+            progress: None,
+            dedup: false,
+            format: PacFormat::default(),
+            long_names: LongNamePolicy::default(),
+            shortened: vec![],
+            align: 1,
+            seed: None,
+        }
+    }
+
+    /// Builder seeded with all entries of `arc`, for appending to or editing it.
+    /// Entry data is left in `arc` and copied verbatim as stored only when it's written,
+    /// without decompression or parsing, so untouched entries are written back byte for byte
+    /// (and `update` doesn't read data it leaves in place at all). Layout of `arc` is kept
+    pub fn from_existing<S: PacSource + 'static>(arc: PacArc<S>) -> Result<Self> {
+        let entries = arc.entries.iter()
+            .map(|e| PacEntryWrite {
+                offset: 0,
+                size: 0,
+                data: EntryData::Seeded { offset: e.offset, size: e.size },
+                name: NullString(e.name.0.clone()),
+                origin: Some((e.offset, e.size)),
+            })
+            .collect();
+
+        Ok(Self { entries, format: arc.format, seed: Some(Box::new(arc.source)), ..Self::new() })
+    }
+
+    /// Write archive with layout `format`. Names of already added entries must fit in its name field
+    pub fn with_format(mut self, format: PacFormat) -> Result<Self> {
+        if let Some(e) = self.entries.iter().find(|e| e.name.len() >= format.name_size) {
+            bail!(PacError::NameEncoding(format!(
+                "entry name {} doesn't fit in {} bytes name field", SHIFT_JIS.decode(&e.name).0, format.name_size
+            )))
+        }
+        self.format = format;
+        Ok(self)
+    }
+
+    pub fn format(&self) -> &PacFormat {
+        &self.format
+    }
+
+    /// Store entries with identical data only once, pointing all of them to same data region
+    pub fn with_dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Shorten names that don't fit in name field instead of failing, according to `policy`.
+    /// Shortened names are reported in `PackStats::shortened`
+    pub fn with_long_names(mut self, policy: LongNamePolicy) -> Self {
+        self.long_names = policy;
+        self
+    }
+
+    /// Pad data of every entry with zeros to start at offset that is multiple of `align`
+    /// (0 and 1 mean no padding)
+    pub fn with_align(mut self, align: u32) -> Self {
+        self.align = align.max(1);
+        self
+    }
+
+    /// Set options used to convert sources added with `add_source`
+    pub fn with_options(mut self, options: PackOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn options(&self) -> &PackOptions {
+        &self.options
+    }
+
+    /// Convert source file `data` according to builder options.
+    /// Returns converted file and entry name derived from `file_name` (e.g. `a.bmp` -> `a.bmz`)
+    pub fn convert_source(&self, data: Vec<u8>, file_name: &str) -> Result<(PacFile, String)> {
+        self.convert_source_with(data, file_name, &self.options)
+    }
+
+    /// Same as `convert_source`, but with explicit conversion options
+    pub fn convert_source_with(&self, data: Vec<u8>, file_name: &str, opts: &PackOptions) -> Result<(PacFile, String)> {
+        let path = Path::new(file_name);
+        let conv_ext = path.extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+
+        let file = PacFile::convert_back_with(data, conv_ext, opts)?;
+        let orig_ext = match file {
+            // uncompressed images are stored as bmp
+            PacFile::Other { .. } if ImageFormat::from_ext(conv_ext).is_some() && opts.converters.accepting(conv_ext).is_some() => "bmp",
+            _ => opts.converters.original_ext(conv_ext),
+        };
+        let name = path.with_extension(orig_ext);
+        let name = name.to_str()
+            .ok_or_else(|| PacError::InvalidInput(format!("Invalid file name: {file_name}")))?;
+
+        Ok((file, name.to_string()))
+    }
+
+    /// Convert source file and add it to archive, returns name of added entry
+    pub fn add_source(&mut self, data: Vec<u8>, file_name: &str) -> Result<String> {
+        let (file, name) = self.convert_source(data, file_name)?;
+        self.add_entry(file, &name)?;
+        Ok(name)
+    }
+
+    /// Set callback, that will be called after each entry is written by `pack`
+    pub fn on_progress(&mut self, f: impl Fn(Progress) + Send + Sync + 'static) {
+        self.progress = Some(Box::new(f));
+    }
+
+    /// Encode entry name to SHIFT-JIS, checking its length (or shortening it, depending on long name policy)
+    fn encode_name(&self, name: &str) -> Result<Vec<u8>> {
+        let max = self.format.name_size;
+        match SHIFT_JIS.encode(name) {
+            (cow, _, false) if cow.len() < max => Ok(cow.to_vec()),
+            (_, _, true) => bail!(PacError::NameEncoding(format!("Failed to encode entry name: {name}"))),
+            (cow, _, false) if self.long_names == LongNamePolicy::Error => bail!(PacError::NameEncoding(format!(
+                "Too long entry name ({}): {name} (must not exceed {max} bytes)", cow.len()
+            ))),
+            (cow, _, false) => Ok(self.shorten_name(name, &cow)),
+        }
+    }
+
+    /// Cut name encoded as `enc` to fit in name field, keeping its extension
+    fn shorten_name(&self, name: &str, enc: &[u8]) -> Vec<u8> {
+        let max = self.format.name_size - 1;
+        let (stem, ext) = match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() && ext.len() < max / 2 => (stem, format!(".{ext}")),
+            _ => (name, String::new()),
+        };
+        let suffix = match self.long_names {
+            LongNamePolicy::Hash => {
+                let hash: String = Sha256::digest(enc)[..4].iter().map(|b| format!("{b:02x}")).collect();
+                format!("~{hash}{ext}")
+            },
+            _ => ext,
+        };
+        let suffix = SHIFT_JIS.encode(&suffix).0;
+        let mut out = vec![];
+        let mut buf = [0; 4];
+        for c in stem.chars() {
+            let c = SHIFT_JIS.encode(c.encode_utf8(&mut buf)).0;
+            if out.len() + c.len() + suffix.len() > max {
+                break
+            }
+            out.extend_from_slice(&c);
+        }
+        out.extend_from_slice(&suffix);
+        out
+    }
+
+    /// Remember name of entry stored under shortened name `enc`.
+    /// Fails if shortened name collides with existing entry
+    fn record_shortened(&mut self, name: &str, enc: &[u8]) -> Result<()> {
+        if SHIFT_JIS.encode(name).0[..] == enc[..] {
+            return Ok(())
+        }
+        let short = SHIFT_JIS.decode(enc).0.into_owned();
+        if self.entries.iter().any(|e| e.name.0 == enc) {
+            bail!(PacError::NameEncoding(format!(
+                "Too long entry name {name} is shortened to {short}, which is already taken"
+            )))
+        }
+        self.shortened.push(ShortenedName { name: short, full_name: name.to_string() });
+        Ok(())
+    }
+
+    fn forget_shortened(&mut self, enc: &[u8]) {
+        let short = SHIFT_JIS.decode(enc).0;
+        self.shortened.retain(|s| s.name != short);
+    }
+
+    /// Number of added entries
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// No entries were added
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of entries archive will have, same as `len`
+    pub fn entry_count(&self) -> usize {
+        self.len()
+    }
+
+    /// Size of archive `pack` would write: header, entry table and data of all entries, with alignment.
+    /// With dedup enabled, identical data is counted for each entry, so archive may be smaller.
+    /// Packing fails if archive would be larger than `MAX_ARCHIVE_SIZE`
+    pub fn estimated_size(&self) -> Result<u64> {
+        let mut size = self.format.table_end(self.entries.len() as u64);
+        for e in self.entries.iter() {
+            size = size.next_multiple_of(self.align as u64) + e.data.stored_size(self.format.endian)?;
+        }
+        Ok(size)
+    }
+
+    /// Check whether entry with `name` was already added
+    pub fn has_entry(&self, name: &str) -> bool {
+        match self.encode_name(name) {
+            Ok(enc) => self.entries.iter().any(|e| e.name.0 == enc),
+            Err(_) => false,
+        }
+    }
+
+    /// Remove entry with `name`, returns `false` if there is no such entry
+    pub fn remove_entry(&mut self, name: &str) -> bool {
+        let Ok(enc) = self.encode_name(name) else {
+            return false
+        };
+        let len = self.entries.len();
+        self.entries.retain(|e| e.name.0 != enc);
+        self.forget_shortened(&enc);
+        self.entries.len() != len
+    }
+
+    /// Replace data of entry with `name`, returns `false` if there is no such entry
+    pub fn replace_entry(&mut self, name: &str, file: PacFile) -> bool {
+        let Ok(enc) = self.encode_name(name) else {
+            return false
+        };
+        match self.entries.iter_mut().find(|e| e.name.0 == enc) {
+            Some(e) => {
+                e.data = EntryData::File(file);
+                e.origin = None;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Rename entry `old` to `new`, returns `false` if there is no such entry.
+    /// Fails if new name can't be encoded or is too long
+    pub fn rename_entry(&mut self, old: &str, new: &str) -> Result<bool> {
+        let new_enc = self.encode_name(new)?;
+        let Ok(old) = self.encode_name(old) else {
+            return Ok(false)
+        };
+        let Some(index) = self.entries.iter().position(|e| e.name.0 == old) else {
+            return Ok(false)
+        };
+        self.forget_shortened(&old);
+        self.record_shortened(new, &new_enc)?;
+        self.entries[index].name = NullString(new_enc);
+        Ok(true)
+    }
+
+    /// Add new entry to archive
+    pub fn add_entry(&mut self, file: PacFile, name: &str) -> Result<()> {
+        let enc_name = self.encode_name(name)?;
+        self.record_shortened(name, &enc_name)?;
+        self.add_entry_raw(file, enc_name)
+    }
+
+    /// Add new entry with already encoded name
+    pub fn add_entry_raw(&mut self, file: PacFile, enc_name: Vec<u8>) -> Result<()> {
+        self.push_entry(EntryData::File(file), enc_name)
+    }
+
+    /// Add new entry with data read from `reader`, which must yield exactly `len` bytes.
+    /// Data is converted according to `conversion` as it's read, and spooled to temporary file until
+    /// archive is packed, so files larger than available memory can be packed
+    pub fn add_entry_from_reader(&mut self, name: &str, reader: impl Read, len: u64, conversion: StreamConversion) -> Result<()> {
+        let too_large = || PacError::SizeLimit(format!("{name} is too large to be stored in archive ({len} bytes)"));
+        let uncompressed_size = u32::try_from(len).map_err(|_| too_large())?;
+        let enc_name = self.encode_name(name)?;
+
+        let mut reader = reader.take(len + 1);
+        let mut spool = BufWriter::new(tempfile::tempfile()?);
+        let (read, size) = match conversion {
+            StreamConversion::Store => {
+                let read = std::io::copy(&mut reader, &mut spool)?;
+                (read, read)
+            },
+            StreamConversion::Bmz => {
+                spool.write_all(BMZ_MAGIC)?;
+                spool.write_all(&match self.format.endian {
+                    Endian::Little => uncompressed_size.to_le_bytes(),
+                    Endian::Big => uncompressed_size.to_be_bytes(),
+                })?;
+                let (read, written) = deflate::compress_to(&mut reader, self.options.level, &mut spool)?;
+                (read, PacFile::BMZ_HEADER_SIZE as u64 + written)
+            },
+        };
+        if read > len {
+            bail!(PacError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{name}: reader yielded more than {len} bytes"))))
+        }
+        if read < len {
+            bail!(PacError::Io(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, format!("{name}: reader yielded only {read} of {len} bytes"))))
+        }
+        let size = u32::try_from(size).map_err(|_| too_large())?;
+        let file = spool.into_inner().map_err(|e| e.into_error())?;
+
+        self.record_shortened(name, &enc_name)?;
+        self.push_entry(EntryData::Spooled { file, size }, enc_name)
+    }
+
+    fn push_entry(&mut self, data: EntryData, enc_name: Vec<u8>) -> Result<()> {
+        if enc_name.len() >= self.format.name_size {
+            bail!(PacError::NameEncoding(format!(
+                "Too long entry name ({} bytes, must not exceed {} bytes)", enc_name.len(), self.format.name_size
+            )))
+        }
+
+        let e = PacEntryWrite {
+            name: NullString(enc_name),
+            data,
+            offset: 0,
+            size: 0,
+            origin: None,
+        };
+
+        self.entries.push(e);
+
+        Ok(())
+    }
+
+    /// Add all entries of `arc`, resolving entries with same name according to `policy`.
+    /// New entries are appended in `arc` order.
+    /// Returns number of conflicting entries
+    pub fn merge<S: PacSource>(&mut self, arc: &PacArc<S>, policy: ConflictPolicy) -> Result<usize> {
+        let mut conflicts = 0;
+        for e in arc.entries.iter() {
+            match self.entries.iter_mut().find(|w| w.name.0 == e.name.0) {
+                Some(existing) => {
+                    conflicts += 1;
+                    match policy {
+                        ConflictPolicy::Last => {
+                            existing.data = EntryData::File(arc.read_file(e)?);
+                            existing.origin = None;
+                        },
+                        ConflictPolicy::First => {},
+                        ConflictPolicy::Error => bail!(PacError::InvalidInput(format!("conflicting entry: {}", e.name()?))),
+                    }
+                },
+                None => self.entries.push(PacEntryWrite {
+                    offset: 0,
+                    size: 0,
+                    data: EntryData::File(arc.read_file(e)?),
+                    name: NullString(e.name.0.clone()),
+                    origin: None,
+                }),
+            }
+        }
+
+        Ok(conflicts)
+    }
+
+    /// Inflate all BMZ entries and correct `uncompressed_size` in headers that don't match real size.
+    /// Returns list of made corrections
+    pub fn fix_bmz_sizes(&mut self) -> Result<Vec<SizeFix>> {
+        let mut fixes = vec![];
+        for e in self.entries.iter_mut() {
+            let EntryData::File(PacFile::Bmz { uncompressed_size, compressed_data }) = &mut e.data else {
+                continue
+            };
+            let name = SHIFT_JIS.decode(&e.name).0.into_owned();
+            let actual = match inflate(compressed_data) {
+                Ok(data) => data.len() as u32,
+                Err(err) => return Err(anyhow::Error::new(PacError::from(err)).context(format!("Failed to inflate {name}"))),
+            };
+            if actual != *uncompressed_size {
+                fixes.push(SizeFix { name, declared: *uncompressed_size, actual });
+                *uncompressed_size = actual;
+            }
+        }
+
+        Ok(fixes)
+    }
+
+    /// Deflate all BMZ entries again at maximum level (trying several deflate strategies and levels
+    /// if `exhaustive`), keeping new stream only if it's smaller and inflates to the same bytes.
+    /// Returns list of recompressed entries
+    pub fn recompress_bmz(&mut self, exhaustive: bool) -> Result<Vec<Recompression>> {
+        let backend = deflate::backend();
+        let attempts: Vec<(u8, CompressionStrategy)> = match exhaustive {
+            true => (6..=PackOptions::MAX_LEVEL)
+                .flat_map(|level| [
+                    CompressionStrategy::Default, CompressionStrategy::Filtered, CompressionStrategy::RLE, CompressionStrategy::HuffmanOnly,
+                ].map(|strategy| (level, strategy)))
+                .collect(),
+            false => vec![(PackOptions::MAX_LEVEL, CompressionStrategy::Default)],
+        };
+        let recompressed = self.entries.par_iter_mut()
+            .map(|e| {
+                let EntryData::File(PacFile::Bmz { compressed_data, .. }) = &mut e.data else {
+                    return Ok(None)
+                };
+                let name = SHIFT_JIS.decode(&e.name).0.into_owned();
+                let data = inflate(compressed_data)
+                    .map_err(|err| anyhow::Error::new(PacError::from(err)).context(format!("Failed to inflate {name}")))?;
+                // backend other than miniz is tried too, at its own maximum level
+                let other_backend = (backend != DeflateBackend::Miniz).then(|| backend.compress(&data, PackOptions::MAX_LEVEL));
+                let best = attempts.iter()
+                    .map(|&(level, strategy)| deflate_with(&data, level, strategy))
+                    .chain(other_backend)
+                    .min_by_key(|stream| stream.len())
+                    .expect("at least one attempt");
+                if best.len() >= compressed_data.len() {
+                    return Ok(None)
+                }
+                if inflate(&best).ok().as_ref() != Some(&data) {
+                    bail!(PacError::Decode(format!("recompressed stream of {name} doesn't inflate to original data")))
+                }
+                let before = compressed_data.len() as u32;
+                *compressed_data = best;
+                e.origin = None;
+                Ok(Some(Recompression { name, before, after: compressed_data.len() as u32 }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(recompressed.into_iter().flatten().collect())
+    }
+
+    /// Pack all entries to archive. Data of seeded entries is read before `out_path` is created,
+    /// so it may be the archive builder was seeded from
+    pub fn pack(self, out_path: &str) -> Result<PackStats> {
+        let data_start = self.format.table_end(self.entries.len() as u64) as u32;
+        let (head, data, stats) = self.serialize(0, data_start)?;
+        let mut out = BufWriter::new(File::create(out_path)?);
+        out.write_all(&head)?;
+        data.write_to(&mut out)?;
+        out.flush()?;
+        Ok(stats)
+    }
+
+    /// Pack all entries to `out`, which doesn't need to be seekable (e.g. stdout)
+    pub fn pack_to(self, mut out: impl Write) -> Result<PackStats> {
+        let data_start = self.format.table_end(self.entries.len() as u64) as u32;
+        let (head, data, stats) = self.serialize(0, data_start)?;
+        out.write_all(&head)?;
+        data.write_to(&mut out)?;
+        Ok(stats)
+    }
+
+    /// Update archive at `path` this builder was seeded from with `from_existing`, rewriting only
+    /// entry table and data after the leading entries which data is untouched (entries may have been
+    /// appended, replaced, removed or renamed since). Unchanged leading data is not rewritten,
+    /// which for large archives saves most of I/O. Falls back to full rewrite when entry table
+    /// would grow into kept data (so appending is done in place only if there is free space after the table,
+    /// e.g. left by removed entries or alignment), or archive at `path` doesn't match the one builder was seeded from.
+    /// Update is not atomic: archive is damaged if it's interrupted
+    pub fn update(self, path: &str) -> Result<PackStats> {
+        let Some((kept, data_start)) = self.kept_prefix(path)? else {
+            return self.pack(path)
+        };
+        let (head, data, mut stats) = self.serialize(kept, data_start)?;
+        let mut out = File::options().write(true).open(path)?;
+        out.write_all(&head)?;
+        out.seek(SeekFrom::Start(data_start as u64))?;
+        let mut writer = BufWriter::new(&mut out);
+        data.write_to(&mut writer)?;
+        writer.flush()?;
+        drop(writer);
+        out.set_len(stats.bytes_written)?;
+        stats.kept_in_place = data_start as u64 - head.len() as u64;
+        Ok(stats)
+    }
+
+    /// Number of leading entries which data can be left in place in archive at `path`,
+    /// and offset data of following entries is written from. `None` if nothing can be kept
+    fn kept_prefix(&self, path: &str) -> Result<Option<(usize, u32)>> {
+        let kept = self.entries.iter().take_while(|e| e.origin.is_some()).count();
+        if kept == 0 {
+            return Ok(None)
+        }
+        let arc = match PacArc::open(path) {
+            Ok(arc) if arc.format == self.format => arc,
+            _ => return Ok(None),
+        };
+        let table_end = self.format.table_end(self.entries.len() as u64);
+        let mut kept_end = table_end;
+        for e in self.entries[..kept].iter() {
+            let (offset, size) = e.origin.unwrap();
+            if !arc.entries.iter().any(|a| (a.offset, a.size) == (offset, size)) {
+                return Ok(None)
+            }
+            if size > 0 && (offset as u64) < table_end {
+                return Ok(None)
+            }
+            kept_end = kept_end.max(offset as u64 + size as u64);
+        }
+        Ok(Some((kept, kept_end as u32)))
+    }
+
+    /// Serialize archive header and entry table, and data of entries after first `kept` ones
+    /// (which keep their original data location) starting from offset `data_start`
+    fn serialize(self, kept: usize, data_start: u32) -> Result<(Vec<u8>, SerializedData, PackStats)> {
+        let mut header_buff = Cursor::new(self.format.magic.clone());
+        header_buff.set_position(self.format.magic.len() as u64);
+        header_buff.write_type(&(self.entries.len() as u32), self.format.endian)?;
+        let mut data_buff = Cursor::new(vec![]);
+        let mut spooled = vec![];
+        
+        let entries_total = self.entries.len();
+        let mut current_offset = data_start;
+
+        // data hash -> (offset, size) of already written data
+        let mut written = HashMap::new();
+        let mut dedup_saved = 0;
+        for (idx, mut entry) in self.entries.into_iter().enumerate() {
+            if idx < kept {
+                (entry.offset, entry.size) = entry.origin.expect("kept entries have origin");
+                header_buff.write_type_args(&entry, self.format.endian, (self.format.name_size,))?;
+                continue
+            }
+            let too_large = || PacError::SizeLimit(format!(
+                "archive would be larger than {} bytes, offset of entry {idx} doesn't fit in 32 bits", Self::MAX_ARCHIVE_SIZE,
+            ));
+            let pad_start = data_buff.position();
+            entry.offset = current_offset.checked_next_multiple_of(self.align).ok_or_else(too_large)?;
+            let padding = entry.offset - current_offset;
+            data_buff.write_all(&vec![0; padding as usize])?;
+
+            // record size of written entry, spooled data is copied to its place when written out
+            let current = data_buff.position();
+            let (size, hash) = match &entry.data {
+                EntryData::File(file) => {
+                    data_buff.write_type(file, self.format.endian)?;
+                    let data = &data_buff.get_ref()[current as usize..];
+                    ((data_buff.position() - current) as u32, self.dedup.then(|| Sha256::digest(data)))
+                },
+                EntryData::Seeded { offset, size } => {
+                    let seed = self.seed.as_ref().expect("seeded entries have seed source");
+                    let data = seed.read_at(*offset as u64, *size as usize)
+                        .with_context(|| format!("Failed to read data of {} from seed archive", SHIFT_JIS.decode(&entry.name).0))?;
+                    data_buff.write_all(&data)?;
+                    (*size, self.dedup.then(|| Sha256::digest(&data)))
+                },
+                EntryData::Spooled { file, size } => {
+                    let hash = match self.dedup {
+                        true => {
+                            let (mut file, mut hasher) = (file, Sha256::new());
+                            file.seek(SeekFrom::Start(0))?;
+                            std::io::copy(&mut file, &mut hasher)?;
+                            Some(hasher.finalize())
+                        },
+                        false => None,
+                    };
+                    (*size, hash)
+                },
+            };
+            entry.size = size;
+
+            let unique = match hash.map(|hash| written.entry(hash)) {
+                Some(hash_map::Entry::Occupied(e)) => {
+                    // drop just written copy along with its padding
+                    data_buff.get_mut().truncate(pad_start as usize);
+                    data_buff.set_position(pad_start);
+                    (entry.offset, entry.size) = *e.get();
+                    dedup_saved += size as u64;
+                    false
+                },
+                Some(hash_map::Entry::Vacant(e)) => {
+                    e.insert((entry.offset, size));
+                    true
+                },
+                None => true,
+            };
+            if unique {
+                current_offset = entry.offset.checked_add(size).ok_or_else(too_large)?;
+                if let EntryData::Spooled { file, .. } = &entry.data {
+                    spooled.push((current as usize, file.try_clone()?));
+                }
+            }
+            header_buff.write_type_args(&entry, self.format.endian, (self.format.name_size,))?;
+
+            if let Some(progress) = &self.progress {
+                progress(Progress {
+                    name: &SHIFT_JIS.decode(&entry.name).0,
+                    entries_done: idx + 1,
+                    entries_total,
+                    bytes_written: current_offset as u64,
+                });
+            }
+        }
+
+        // offsets of entry data were computed from table size, table of other size would shift them
+        let table_end = self.format.table_end(entries_total as u64);
+        if header_buff.position() != table_end {
+            bail!(PacError::Format(format!("serialized entry table ends at {}, entry offsets were computed for table ending at {table_end}", header_buff.position())))
+        }
+
+        let stats = PackStats {
+            entries: entries_total,
+            bytes_written: current_offset as u64,
+            dedup_saved,
+            shortened: self.shortened,
+            kept_in_place: 0,
+        };
+        Ok((header_buff.into_inner(), SerializedData { buff: data_buff.into_inner(), spooled }, stats))
+    }
+}
+
+/// Options controlling extraction
+#[derive(Default)]
+pub struct ExtractOptions {
+    /// Format BMZ sprites will be converted to
+    pub image_format: ImageFormat,
+    /// Handling of undecodable entry names
+    pub name_decoding: NameDecoding,
+    /// Only entries with these indices are extracted (all if `None`).
+    /// Selected entries with undecodable names are extracted as `entry<index>`
+    pub indices: Option<IndexSet>,
+    /// Only entries matching any of these patterns are extracted (all if empty)
+    pub include: Vec<Pattern>,
+    /// Entries matching any of these patterns are skipped
+    pub exclude: Vec<Pattern>,
+    /// Number of worker threads, 0 means number of CPUs
+    pub jobs: usize,
+    /// Called after each extracted entry (possibly from worker threads)
+    pub progress: Option<Box<ProgressFn>>,
+    /// Read and convert entries, but don't write anything
+    pub dry_run: bool,
+    /// Don't rewrite output files that already exist with expected size (or content, if size
+    /// can't be told without conversion), so interrupted extraction can be resumed
+    pub skip_existing: bool,
+    /// Handling of entries that would be extracted to the same file
+    pub on_collision: CollisionPolicy,
+    /// Used with `CollisionPolicy::Prompt`
+    pub collision_prompt: Option<Box<CollisionFn>>,
+    /// Converters of entry data, entries none of them detect are extracted as is
+    pub converters: Converters,
+    /// Full names of entries stored under shortened names (see `NameMap`), used to name
+    /// extracted files. Manifest keeps stored names, so packing restores archive as is
+    pub full_names: HashMap<String, String>,
+    /// Extract entries which names look like paths escaping output directory (absolute ones, `..` components)
+    /// instead of failing. Their file names are sanitized like any other
+    pub trust_names: bool,
+    /// Whether path separators in entry names create subdirectories
+    pub paths: PathMode,
+    /// Also write converted entries as stored in archive, next to converted files
+    /// under original extension (e.g. `mion01.bmz` along with `mion01.bmp`)
+    pub keep_raw: bool,
+}
+
+impl ExtractOptions {
+    /// Check whether entry with (in-archive) `name` should be extracted
+    pub fn selects(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(name)))
+            && !self.exclude.iter().any(|p| p.matches(name))
+    }
+
+    /// Check whether entry at `index` is selected by `indices`
+    pub fn selects_index(&self, index: usize) -> bool {
+        self.indices.as_ref().is_none_or(|i| i.contains(index))
+    }
+}
+
+/// Set of entry indices, written as comma-separated list of indices and inclusive ranges
+/// (e.g. `0-10,42`)
+#[derive(Clone, Debug)]
+pub struct IndexSet(Vec<RangeInclusive<usize>>);
+
+impl IndexSet {
+    pub fn contains(&self, index: usize) -> bool {
+        self.0.iter().any(|r| r.contains(&index))
+    }
+}
+
+impl FromStr for IndexSet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parse = |n: &str| n.trim().parse::<usize>()
+            .map_err(|_| anyhow::Error::new(PacError::InvalidInput(format!("invalid index: {n:?}"))));
+        let ranges = s.split(',')
+            .map(|part| match part.split_once('-') {
+                Some((start, end)) => {
+                    let (start, end) = (parse(start)?, parse(end)?);
+                    if start > end {
+                        bail!(PacError::InvalidInput(format!("invalid index range: {part} (start is greater than end)")))
+                    }
+                    Ok(start..=end)
+                },
+                None => parse(part).map(|i| i..=i),
+            })
+            .collect::<Result<_>>()?;
+        Ok(IndexSet(ranges))
+    }
+}
+
+impl<S: PacSource> PacArc<S> {
+    /// Turn archive into builder, keeping all entries with their original names and data.
+    /// Can be used to modify existing archive
+    pub fn into_builder(self) -> Result<PacArcBuilder> {
+        let mut entries = Vec::with_capacity(self.entries.len());
+        for e in self.entries.iter() {
+            entries.push(PacEntryWrite {
+                offset: 0,
+                size: 0,
+                data: EntryData::File(self.read_file(e)?),
+                name: NullString(e.name.0.clone()),
+                origin: None,
+            });
+        }
+
+        Ok(PacArcBuilder { entries, format: self.format.clone(), ..PacArcBuilder::new() })
+    }
+
+    /// Find first entry with decoded name equal to `name`, same as `get_entry_by_name`
+    pub fn find_entry(&self, name: &str) -> Option<&PacEntryRead> {
+        self.get_entry_by_name(name)
+    }
+
+    /// Find first entry with decoded name equal to `name`.
+    ///
+    /// Name index is built on first lookup, later ones don't scan the entry table. Index reflects `entries`
+    /// as of first lookup; if entries were added or removed since, lookups fall back to scanning
+    pub fn get_entry_by_name(&self, name: &str) -> Option<&PacEntryRead> {
+        match self.name_index() {
+            Some(index) => index.by_name.get(name).map(|&i| &self.entries[i]),
+            None => self.entries.iter().find(|e| e.name().is_ok_and(|n| n == name)),
+        }
+    }
+
+    /// Find first entry with name of exactly `raw_name` bytes (SHIFT-JIS, without null terminator)
+    pub fn get_entry_by_raw_name(&self, raw_name: &[u8]) -> Option<&PacEntryRead> {
+        match self.name_index() {
+            Some(index) => index.by_raw.get(raw_name).map(|&i| &self.entries[i]),
+            None => self.entries.iter().find(|e| e.name.0 == raw_name),
+        }
+    }
+
+    fn name_index(&self) -> Option<&NameIndex> {
+        let index = self.names.get_or_init(|| NameIndex::build(&self.entries));
+        (index.len == self.entries.len()).then_some(index)
+    }
+
+    /// Convert entry at `index` with built-in converters (sprites to BMP) and write it to `out`.
+    /// Returns number of written bytes
+    pub fn extract_entry<W: Write>(&self, index: usize, out: &mut W) -> Result<u64> {
+        let entry = self.entries.get(index)
+            .ok_or_else(|| PacError::InvalidInput(format!("no entry at index {index}, archive has {} entries", self.entries.len())))?;
+        self.extract_entry_to(entry, ImageFormat::default(), Converters::builtin(), out)
+    }
+
+    /// Extract and convert entries selected by `opts`, writing each to writer returned by `sink`
+    /// for its manifest entry (`file` being path it would be extracted to by `extract_all`),
+    /// entries for which `sink` returns `None` are skipped.
+    /// Entries are processed one by one in archive order; `jobs`, `dry_run`, `skip_existing` and `keep_raw` are ignored.
+    /// Returns manifest, describing written entries
+    pub fn extract_all_with<F, W>(&self, opts: &ExtractOptions, mut sink: F) -> Result<ExtractManifest>
+    where
+        F: FnMut(&ManifestEntry) -> Result<Option<W>>,
+        W: Write,
+    {
+        let targets = self.plan_extraction(opts, false)?;
+        let entries_total = self.entries.len();
+        let (mut entries, mut entries_done, mut bytes_written) = (vec![], 0, 0);
+        for (entry, target) in self.entries.iter().zip(targets) {
+            let Some(target) = target else {
+                continue
+            };
+            let manifest_entry = ManifestEntry { raw_file: None, ..target.manifest_entry() };
+            if let Some(mut out) = sink(&manifest_entry)? {
+                let context = || format!("Failed to extract {} to {}", self.describe_entry(entry), target.file);
+                let raw = self.read_raw(entry)?;
+                bytes_written += opts.converters.convert_raw_to(&target.name, &raw, opts.image_format, self.format.endian, &mut out)
+                    .and_then(|written| Ok(out.flush().map(|_| written)?))
+                    .with_context(context)?;
+                entries.push(manifest_entry);
+            }
+            entries_done += 1;
+            if let Some(progress) = &opts.progress {
+                progress(Progress { name: &target.name, entries_done, entries_total, bytes_written });
+            }
+        }
+        Ok(ExtractManifest { entries, archive_mtime: None })
+    }
+
+    /// Extract and convert all files.
+    /// Entries are processed in parallel by `opts.jobs` workers
+    /// Returns manifest, describing extracted files in archive order
+    pub fn extract_all(&self, out_dir: &str, opts: &ExtractOptions) -> Result<ExtractManifest> {
+        self.extract_impl(out_dir, opts, false).map(|(manifest, _)| manifest)
+    }
+
+    /// Same as `extract_all`, but entries that fail to extract are reported instead of aborting
+    /// extraction, and truncated BMZ streams are recovered as far as possible (rest of bitmap is zeroed).
+    /// Entries with undecodable names are extracted as `entry<index>`.
+    /// Returns manifest and list of damaged entries
+    pub fn salvage_all(&self, out_dir: &str, opts: &ExtractOptions) -> Result<(ExtractManifest, Vec<Problem>)> {
+        self.extract_impl(out_dir, opts, true)
+    }
+
+    fn extract_impl(&self, out_dir: &str, opts: &ExtractOptions, salvage: bool) -> Result<(ExtractManifest, Vec<Problem>)> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(opts.jobs)
+            .build()?;
+
+        let targets = self.plan_extraction(opts, salvage)?;
+        let entries_total = self.entries.len();
+        let entries_done = AtomicUsize::new(0);
+        let bytes_written = AtomicU64::new(0);
+
+        let extracted = pool.install(|| self.entries
+            .par_iter()
+            .zip(targets.par_iter())
+            .enumerate()
+            .map(|(index, (entry, target))| {
+                let Some(target) = target else {
+                    return Ok((None, None))
+                };
+                let name = &target.name;
+                let (extracted, problem) = match self.extract_entry_file(entry, target, out_dir, opts, salvage) {
+                    Ok(Extracted { entry, written, damage }) => {
+                        let problem = damage.map(|message| Problem { index: Some(index), name: name.clone(), message });
+                        (Some((entry, written)), problem)
+                    },
+                    Err(e) if salvage => {
+                        let message = format!("{e:#}");
+                        (None, Some(Problem { index: Some(index), name: name.clone(), message }))
+                    },
+                    Err(e) => return Err(e),
+                };
+                let written = extracted.as_ref().map_or(0, |(_, written)| *written);
+
+                if let Some(progress) = &opts.progress {
+                    progress(Progress {
+                        name,
+                        entries_done: entries_done.fetch_add(1, Ordering::Relaxed) + 1,
+                        entries_total,
+                        bytes_written: bytes_written.fetch_add(written, Ordering::Relaxed) + written,
+                    });
+                }
+                Ok((extracted.map(|(e, _)| e), problem))
+            })
+            .collect::<Result<Vec<_>>>()
+        )?;
+
+        let (entries, problems): (Vec<_>, Vec<_>) = extracted.into_iter().unzip();
+        let mut entries: Vec<ManifestEntry> = entries.into_iter().flatten().collect();
+        if !opts.dry_run {
+            for entry in entries.iter_mut() {
+                entry.mtime = file_mtime(Path::new(out_dir).join(&entry.file));
+            }
+        }
+        Ok((
+            ExtractManifest { entries, archive_mtime: None },
+            problems.into_iter().flatten().collect(),
+        ))
+    } 
+
+    /// Decide which entries are extracted and file names they are extracted to (`None` for skipped ones).
+    ///
+    /// Characters not allowed in file names on common filesystems are replaced with `_`,
+    /// file names already taken (ignoring case) are handled according to `opts.on_collision`.
+    /// Original names are kept in manifest.
+    pub(crate) fn plan_extraction(&self, opts: &ExtractOptions, salvage: bool) -> Result<Vec<Option<ExtractTarget>>> {
+        // lowercased file name -> name of entry extracted to it
+        let mut taken: HashMap<String, String> = HashMap::new();
+        let mut targets = Vec::with_capacity(self.entries.len());
+        for (index, entry) in self.entries.iter().enumerate() {
+            if !opts.selects_index(index) {
+                targets.push(None);
+                continue
+            }
+            let name = match entry.decode_name(opts.name_decoding) {
+                Ok(name) => name,
+                Err(_) if opts.indices.is_some() || salvage => self.fallback_name(index, entry)?,
+                Err(e) => return Err(e),
+            };
+            if !opts.selects(&name) {
+                targets.push(None);
+                continue
+            }
+
+            let full_name = opts.full_names.get(&name).unwrap_or(&name);
+            if !opts.trust_names && escapes_dir(full_name) {
+                bail!(PacError::Format(format!(
+                    "entry {index} ({full_name}) would be extracted outside of output directory, \
+                    extract it under sanitized name with --trust-names if archive is trusted"
+                )))
+            }
+            let safe_name = match opts.paths {
+                PathMode::Flatten => safe_file_name(full_name),
+                PathMode::Preserve => full_name.split(['/', '\\'])
+                    .filter(|c| !c.is_empty())
+                    .map(safe_file_name)
+                    .collect::<Vec<_>>()
+                    .join("/"),
+            };
+            let safe_name = match safe_name.is_empty() {
+                true => "_".to_string(),
+                false => safe_name,
+            };
+            let safe_path = Path::new(&safe_name);
+            let orig_ext = safe_path.extension().and_then(|e| e.to_str());
+            let ext = opts.converters.converted_ext(orig_ext.unwrap_or(""), opts.image_format);
+            // extension is replaced textually, `Path::with_extension` turns names like `..bmz` into `..`
+            let stem = &safe_name[..safe_name.len() - orig_ext.map_or(0, |e| e.len() + 1)];
+            let mut file = match ext {
+                "" => stem.to_string(),
+                ext => format!("{stem}.{ext}"),
+            };
+            if let Some(taken_by) = taken.get(&file.to_lowercase()) {
+                let suggested = match ext {
+                    "" => format!("{stem}~{index}"),
+                    ext => format!("{stem}~{index}.{ext}"),
+                };
+                let collision = Collision { name: &name, file: &file, taken_by, suggested: &suggested };
+                let resolved = match (opts.on_collision, &opts.collision_prompt) {
+                    (CollisionPolicy::Suffix, _) => Some(suggested.clone()),
+                    (CollisionPolicy::Prompt, Some(prompt)) => prompt(&collision)?,
+                    _ => bail!(PacError::InvalidInput(format!("{name} would be extracted to {file}, already used by {taken_by}"))),
+                };
+                match resolved {
+                    Some(resolved) if taken.contains_key(&resolved.to_lowercase()) => {
+                        bail!(PacError::InvalidInput(format!("{name} can't be extracted to {resolved}, file name is already used")))
+                    },
+                    Some(resolved) => file = resolved,
+                    None => {
+                        targets.push(None);
+                        continue
+                    },
+                }
+            }
+            taken.insert(file.to_lowercase(), name.clone());
+
+            // raw copy follows file name collisions were resolved to
+            let raw_file = match (opts.keep_raw, orig_ext) {
+                (true, Some(orig_ext)) if !ext.eq_ignore_ascii_case(orig_ext) => {
+                    let base = file.strip_suffix(&format!(".{ext}")).unwrap_or(&file);
+                    let raw_file = format!("{base}.{orig_ext}");
+                    if let Some(taken_by) = taken.get(&raw_file.to_lowercase()) {
+                        bail!(PacError::InvalidInput(format!("{name} can't be kept as stored in {raw_file}, already used by {taken_by}")))
+                    }
+                    taken.insert(raw_file.to_lowercase(), name.clone());
+                    Some(raw_file)
+                },
+                _ => None,
+            };
+
+            // keep exact name, if decoded one doesn't round trip
+            let raw_name = entry.name().is_err().then(|| ManifestEntry::encode_raw_name(&entry.name));
+            targets.push(Some(ExtractTarget { name, raw_name, file, raw_file }));
+        }
+
+        Ok(targets)
+    }
+
+    /// Name for entry which name can't be decoded: `entry<index>` with extension guessed from data
+    fn fallback_name(&self, index: usize, entry: &PacEntryRead) -> Result<String> {
+        let ext = match PacFile::from_raw_as(&self.read_raw(entry)?, self.format.endian)? {
+            PacFile::Other { .. } => "bin",
+            file => file.kind(),
+        };
+        Ok(format!("entry{index}.{ext}"))
+    }
+
+    /// Extract and convert single entry to `out_dir`.
+    /// Returns manifest record and number of written bytes
+    fn extract_entry_file(
+        &self,
+        entry: &PacEntryRead,
+        target: &ExtractTarget,
+        out_dir: &str,
+        opts: &ExtractOptions,
+        salvage: bool,
+    ) -> Result<Extracted> {
+        let path = Path::new(out_dir).join(&target.file);
+        let manifest_entry = target.manifest_entry();
+        let context = || format!("Failed to extract {} to {}", self.describe_entry(entry), path.display());
+
+        let raw = self.read_raw(entry).with_context(context)?;
+        // subdirectories of entries extracted with `PathMode::Preserve`
+        if !opts.dry_run && target.file.contains('/') {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).with_context(context)?;
+            }
+        }
+        let mut raw_written = 0;
+        if let Some(raw_file) = target.raw_file.as_ref().filter(|_| !opts.dry_run) {
+            let raw_path = Path::new(out_dir).join(raw_file);
+            let unchanged = opts.skip_existing && std::fs::metadata(&raw_path).is_ok_and(|m| m.len() == raw.len() as u64);
+            if !unchanged {
+                std::fs::write(&raw_path, &raw)
+                    .with_context(|| format!("Failed to extract {} to {}", self.describe_entry(entry), raw_path.display()))?;
+                raw_written = raw.len() as u64;
+            }
+        }
+        let existing_len = match opts.skip_existing {
+            true => std::fs::metadata(&path).ok().filter(|m| m.is_file()).map(|m| m.len()),
+            false => None,
+        };
+        // Size of formats without transcoding is known without conversion,
+        // others are compared by content after conversion
+        if let Some(len) = existing_len {
+            if opts.converters.converted_size_raw(&target.name, &raw, opts.image_format, self.format.endian) == Some(len) {
+                return Ok(Extracted { entry: manifest_entry, written: raw_written, damage: None })
+            }
+        }
+
+        // converted data is written as it's produced, so large bitmaps aren't kept in memory;
+        // existing files are compared and damaged entries recovered from data converted in memory
+        if existing_len.is_none() {
+            let written = match opts.dry_run {
+                true => opts.converters.convert_raw_to(&target.name, &raw, opts.image_format, self.format.endian, &mut std::io::sink()),
+                false => {
+                    let mut out = BufWriter::new(File::create(&path).with_context(context)?);
+                    opts.converters.convert_raw_to(&target.name, &raw, opts.image_format, self.format.endian, &mut out)
+                        .and_then(|written| Ok(out.flush().map(|_| written)?))
+                },
+            };
+            match written {
+                Ok(written) => return Ok(Extracted { entry: manifest_entry, written: written + raw_written, damage: None }),
+                Err(e) if !salvage => {
+                    if !opts.dry_run {
+                        let _ = std::fs::remove_file(&path);
+                    }
+                    return Err(e.context(context()))
+                },
+                Err(_) => {},
+            }
+        }
+
+        let mut damage = None;
+        let data = match opts.converters.convert_raw(&target.name, &raw, opts.image_format, self.format.endian) {
+            Ok(data) => data,
+            Err(e) => match PacFile::inflate_partial_as(&raw, self.format.endian) {
+                Some(bmp) if salvage => {
+                    damage = Some(format!("{e}, bitmap recovered partially"));
+                    opts.image_format.from_bmp(bmp).with_context(context)?
+                },
+                _ => return Err(e.context(context())),
+            },
+        };
+        if existing_len == Some(data.len() as u64) && std::fs::read(&path)? == data {
+            return Ok(Extracted { entry: manifest_entry, written: raw_written, damage })
+        }
+        if !opts.dry_run {
+            std::fs::write(&path, &data)?;
+        }
+
+        Ok(Extracted { entry: manifest_entry, written: data.len() as u64 + raw_written, damage })
+    }
+}
+
+/// Entry selected for extraction
+pub(crate) struct ExtractTarget {
+    /// Decoded entry name
+    pub name: String,
+    /// Hex-encoded name bytes, if `name` is not exact
+    pub raw_name: Option<String>,
+    /// Path of file relative to output directory, components are separated with `/`
+    pub file: String,
+    /// Path of file with entry data as is, with `ExtractOptions::keep_raw`
+    pub raw_file: Option<String>,
+}
+
+impl ExtractTarget {
+    pub fn manifest_entry(&self) -> ManifestEntry {
+        ManifestEntry {
+            name: self.name.clone(),
+            file: self.file.clone(),
+            raw_name: self.raw_name.clone(),
+            raw_file: self.raw_file.clone(),
+            mtime: None,
+        }
+    }
+}
+
+/// Check whether `name`, as path, points outside of directory it's joined to: it's absolute (including
+/// Windows drive and UNC paths) or has `..` components. Both `/` and `\` count as separators
+fn escapes_dir(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    let drive = bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':';
+    drive || name.starts_with(['/', '\\']) || name.split(['/', '\\']).any(|c| c.trim_end() == "..")
+}
+
+/// Make `name` usable as file name on common filesystems (Windows being most restrictive):
+/// disallowed characters and trailing dots and spaces are replaced with `_`,
+/// reserved device names (`CON`, `aux.wav`, ...) get `_` appended to the stem
+fn safe_file_name(name: &str) -> String {
+    let mut safe: String = name.chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    let trimmed_len = safe.trim_end_matches(['.', ' ']).len();
+    let trailing = safe.len() - trimmed_len;
+    safe.truncate(trimmed_len);
+    safe.extend(std::iter::repeat_n('_', trailing));
+
+    let stem_len = safe.find('.').unwrap_or(safe.len());
+    if is_reserved_name(safe[..stem_len].trim_end()) {
+        safe.insert(stem_len, '_');
+    }
+    safe
+}
+
+/// Convert binrw error of data parsed from `base` offset of archive,
+/// noting absolute offset of failure when binrw reports it
+fn parse_error(e: binrw::Error, base: u64) -> anyhow::Error {
+    let pos = match e.root_cause() {
+        binrw::Error::BadMagic { pos, .. }
+        | binrw::Error::AssertFail { pos, .. }
+        | binrw::Error::Custom { pos, .. }
+        | binrw::Error::NoVariantMatch { pos }
+        | binrw::Error::EnumErrors { pos, .. } => Some(base + pos),
+        _ => None,
+    };
+    match pos {
+        Some(pos) => anyhow::Error::new(PacError::Format(e.to_string())).context(format!("parsing failed at offset {pos}")),
+        None => PacError::Format(e.to_string()).into(),
+    }
+}
+
+/// Check whether `stem` is device name reserved on Windows
+fn is_reserved_name(stem: &str) -> bool {
+    const RESERVED: &[&str] = &["CON", "PRN", "AUX", "NUL", "CONIN$", "CONOUT$"];
+    let upper = stem.to_ascii_uppercase();
+    RESERVED.contains(&upper.as_str())
+        || matches!(upper.as_bytes(), [b'C', b'O', b'M', b'1'..=b'9'] | [b'L', b'P', b'T', b'1'..=b'9'])
+}
+
+/// Result of single entry extraction
+struct Extracted {
+    entry: ManifestEntry,
+    /// Number of written bytes
+    written: u64,
+    /// Description of damage, if entry was salvaged
+    damage: Option<String>,
+}
+
+/// Representation of files found in archive
+#[derive(BinRead, BinWrite)]
+#[br(import(size: u32))]
+pub enum PacFile {
+    /// BMP file compressed with zlib 
+    #[brw(magic = b"ZLC3")]
+    Bmz {
+        uncompressed_size: u32, 
+        #[br(count = size.saturating_sub(Self::BMZ_HEADER_SIZE as u32))]
+        compressed_data: Vec<u8>,
+    },
+    /// Animation
+    Ttp(TtpFile),
+    /// Any other file, stored as is
+    Other {
+        #[br(count = size, err_context("size = {}", size))]
+        data: Vec<u8>
+    }
+}
+
+impl PacFile {
+    const BMZ_HEADER_SIZE: usize = 8;
+
+    /// Parse entry data as stored in archive
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        Self::from_raw_as(raw, Endian::Little)
+    }
+
+    /// Parse entry data stored in archive with byte order `endian`
+    pub fn from_raw_as(raw: &[u8], endian: Endian) -> Result<Self> {
+        Ok(Cursor::new(raw).read_type_args(endian, (raw.len() as u32,))?)
+    }
+
+    /// Take contents of `.bmz` file as already compressed sprite, stored exactly as given.
+    /// Only ZLC3 header is checked, header size is read with byte order `endian`
+    pub fn from_bmz_as(data: &[u8], endian: Endian) -> Result<Self> {
+        if data.len() < Self::BMZ_HEADER_SIZE || !data.starts_with(BMZ_MAGIC) {
+            bail!(PacError::Format("not a ZLC3 container".to_string()))
+        }
+        Self::from_raw_as(data, endian)
+    }
+
+    /// Short name of file type
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PacFile::Bmz { .. } => "bmz",
+            PacFile::Ttp(_) => "ttp",
+            PacFile::Other { .. } => "other",
+        }
+    }
+
+    /// Size of decompressed data, known only for BMZ files
+    pub fn uncompressed_size(&self) -> Option<u32> {
+        match self {
+            PacFile::Bmz { uncompressed_size, .. } => Some(*uncompressed_size),
+            _ => None,
+        }
+    }
+
+    /// Get data as stored in archive
+    pub fn raw_data(&self) -> Result<Vec<u8>> {
+        self.raw_data_as(Endian::Little)
+    }
+
+    /// Get data as stored in archive with byte order `endian`
+    pub fn raw_data_as(&self, endian: Endian) -> Result<Vec<u8>> {
+        let mut out = Cursor::new(vec![]);
+        out.write_type(self, endian)?;
+        Ok(out.into_inner())
+    }
+
+    /// Get converted data directly from raw entry bytes, using built-in converters
+    pub fn convert_raw(raw: &[u8], image_format: ImageFormat) -> Result<Vec<u8>> {
+        Converters::builtin().convert_raw("", raw, image_format, Endian::Little)
+    }
+
+    /// Inflate as much as possible from (possibly truncated) BMZ stream.
+    /// Missing data is zero-filled up to size declared in header, `None` if nothing could be inflated
+    pub fn inflate_partial(raw: &[u8]) -> Option<Vec<u8>> {
+        Self::inflate_partial_as(raw, Endian::Little)
+    }
+
+    /// Same as `inflate_partial`, for BMZ header with byte order `endian`
+    pub fn inflate_partial_as(raw: &[u8], endian: Endian) -> Option<Vec<u8>> {
+        if raw.len() < Self::BMZ_HEADER_SIZE || !raw.starts_with(BMZ_MAGIC) {
+            return None
+        }
+        let declared: u32 = Cursor::new(&raw[4..8]).read_type(endian).ok()?;
+        let mut data = match deflate::backend().inflate(&raw[Self::BMZ_HEADER_SIZE..], bmz_limit(declared)) {
+            Ok(data) => data,
+            Err(e) => e.output,
+        };
+        let declared = declared as usize;
+        if data.is_empty() {
+            return None
+        }
+        if data.len() < declared {
+            data.resize(declared, 0);
+        }
+        Some(data)
+    }
+
+    /// Size of data `convert_raw` would return, if it can be told without conversion
+    pub fn converted_size_raw(raw: &[u8], image_format: ImageFormat) -> Option<u64> {
+        Converters::builtin().converted_size_raw("", raw, image_format, Endian::Little)
+    }
+
+    /// Get converted data
+    pub fn converted_data(&self) -> Result<Vec<u8>> {
+        self.converted_data_as(ImageFormat::Bmp)
+    }
+
+    /// Get converted data, transcoding BMZ sprites to `image_format`
+    pub fn converted_data_as(&self, image_format: ImageFormat) -> Result<Vec<u8>> {
+        match self {
+            PacFile::Bmz { uncompressed_size, compressed_data } => {
+                image_format.from_bmp(inflate_bmz(compressed_data, *uncompressed_size)?)
+            },
+            PacFile::Other { data } => Ok(data.clone()),
+            PacFile::Ttp(ttp) => Ok(serde_json::to_string_pretty(ttp)?.into_bytes()),
+        }
+    }
+
+    /// Get original (packed) extension, according to built-in converters
+    pub fn original_ext(conv_ext: &str) -> &str {
+        Converters::builtin().original_ext(conv_ext)
+    }
+
+    /// Get converted (extracted) extension, according to built-in converters
+    pub fn converted_ext(orig_ext: &str, image_format: ImageFormat) -> &str {
+        Converters::builtin().converted_ext(orig_ext, image_format)
+    }
+
+    /// Try to build file from raw data.
+    /// Expects extension of converted file
+    pub fn convert_back(data: Vec<u8>, conv_extension: &str) -> Result<Self> {
+        Self::convert_back_with(data, conv_extension, &PackOptions::default())
+    }
+
+    /// Try to build file from raw data, using converters and conversion settings from `opts`.
+    /// Expects extension of converted file
+    pub fn convert_back_with(data: Vec<u8>, conv_extension: &str, opts: &PackOptions) -> Result<Self> {
+        opts.converters.convert_back(data, conv_extension, opts)
+    }
+}
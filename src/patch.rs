@@ -0,0 +1,157 @@
+//! Content-addressed patches between archives.
+//!
+//! Patch lists entries of modified archive in order, each as name and SHA-256 of its data.
+//! Only data that can't be found in base archive is stored in patch,
+//! unmodified, renamed and reordered entries cost just their record.
+//!
+//! ```text
+//! magic "NPPATCH1"
+//! u32 entries count, then per entry: u8 name length, SHIFT-JIS name, [u8; 32] hash
+//! u32 blobs count, then per blob: [u8; 32] hash, u32 size, data as stored in archive
+//! ```
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek};
+use std::path::Path;
+use anyhow::{Result, Context, bail};
+use binrw::{binrw, BinRead, BinResult, BinWrite, ReadOptions};
+use encoding_rs::SHIFT_JIS;
+use sha2::{Digest, Sha256};
+
+use crate::error::PacError;
+use crate::pac::{PacArc, PacArcBuilder, PacFile};
+use crate::source::PacSource;
+
+/// SHA-256 of entry data
+pub type Hash = [u8; 32];
+
+/// Patch, turning base archive into modified one
+#[binrw]
+#[brw(little, magic = b"NPPATCH1")]
+pub struct Patch {
+    #[br(temp)]
+    #[bw(calc = entries.len() as u32)]
+    entries_count: u32,
+    /// Entries of modified archive, in archive order
+    #[br(count = entries_count)]
+    pub entries: Vec<PatchEntry>,
+
+    #[br(temp)]
+    #[bw(calc = blobs.len() as u32)]
+    blobs_count: u32,
+    /// Entry data missing from base archive
+    #[br(count = blobs_count)]
+    pub blobs: Vec<PatchBlob>,
+}
+
+/// Entry of modified archive
+#[binrw]
+pub struct PatchEntry {
+    #[br(temp)]
+    #[bw(calc = name.len() as u8)]
+    name_len: u8,
+    /// Name as stored in archive (SHIFT-JIS)
+    #[br(count = name_len)]
+    pub name: Vec<u8>,
+    pub hash: Hash,
+}
+
+/// Entry data, as stored in archive
+#[binrw]
+pub struct PatchBlob {
+    pub hash: Hash,
+    #[br(temp)]
+    #[bw(calc = data.len() as u32)]
+    size: u32,
+    #[br(parse_with = read_blob_data, args(size))]
+    pub data: Vec<u8>,
+}
+
+/// Read `size` bytes, growing buffer only as data actually arrives,
+/// so sizes declared by crafted patches can't cause huge allocations
+fn read_blob_data<R: Read + Seek>(reader: &mut R, _: &ReadOptions, (size,): (u32,)) -> BinResult<Vec<u8>> {
+    let mut data = vec![];
+    reader.take(size as u64).read_to_end(&mut data)?;
+    if data.len() != size as usize {
+        return Err(binrw::Error::Io(std::io::ErrorKind::UnexpectedEof.into()))
+    }
+    Ok(data)
+}
+
+fn hash(data: &[u8]) -> Hash {
+    Sha256::digest(data).into()
+}
+
+/// Map content hash -> index of first entry with such content
+fn index_by_hash<S: PacSource>(arc: &PacArc<S>) -> Result<HashMap<Hash, usize>> {
+    let mut index = HashMap::new();
+    for (idx, e) in arc.entries.iter().enumerate() {
+        index.entry(hash(&arc.read_raw(e)?)).or_insert(idx);
+    }
+    Ok(index)
+}
+
+impl Patch {
+    /// Create patch turning `base` into `modified`
+    pub fn create<A: PacSource, B: PacSource>(base: &PacArc<A>, modified: &PacArc<B>) -> Result<Self> {
+        let base_index = index_by_hash(base)?;
+
+        let mut entries = Vec::with_capacity(modified.entries.len());
+        let mut blobs: Vec<PatchBlob> = vec![];
+        for e in modified.entries.iter() {
+            let data = modified.read_raw(e)?;
+            let hash = hash(&data);
+            if !base_index.contains_key(&hash) && !blobs.iter().any(|b| b.hash == hash) {
+                blobs.push(PatchBlob { hash, data: data.into_owned() })
+            }
+            entries.push(PatchEntry { name: e.name.0.clone(), hash });
+        }
+
+        Ok(Patch { entries, blobs })
+    }
+
+    /// Rebuild modified archive from `base`.
+    /// Fails if data of some entry is neither in patch nor in `base`
+    pub fn apply<S: PacSource>(&self, base: &PacArc<S>) -> Result<PacArcBuilder> {
+        let base_index = index_by_hash(base)?;
+        let blobs: HashMap<&Hash, &[u8]> = self.blobs.iter()
+            .map(|b| (&b.hash, b.data.as_slice()))
+            .collect();
+
+        let mut builder = PacArcBuilder::new();
+        for e in self.entries.iter() {
+            let file = match (blobs.get(&e.hash), base_index.get(&e.hash)) {
+                (Some(data), _) => PacFile::from_raw_as(data, base.format.endian)?,
+                (None, Some(&idx)) => base.read_file(&base.entries[idx])?,
+                (None, None) => bail!(PacError::Format(format!(
+                    "data of entry {} is missing from both patch and base archive",
+                    SHIFT_JIS.decode(&e.name).0,
+                ))),
+            };
+            builder.add_entry_raw(file, e.name.clone())?;
+        }
+
+        Ok(builder)
+    }
+
+    /// Total size of data stored in patch
+    pub fn blobs_size(&self) -> u64 {
+        self.blobs.iter().map(|b| b.data.len() as u64).sum()
+    }
+
+    /// Read patch from file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut reader = BufReader::new(File::open(path)?);
+        Patch::read(&mut reader)
+            .map_err(|e| PacError::Format(e.to_string()))
+            .with_context(|| format!("Failed to read patch {}", path.display()))
+    }
+
+    /// Write patch to file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.write(&mut BufWriter::new(File::create(path)?))?;
+        Ok(())
+    }
+}
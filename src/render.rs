@@ -0,0 +1,426 @@
+//! Rendering of animations into animated images and sprite sheets, and of sprites into contact sheets
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::Write;
+use std::str::FromStr;
+use anyhow::{Result, Context, bail};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::codecs::webp::WebPEncoder;
+use image::{imageops, Delay, ExtendedColorType, Frame, ImageEncoder, Rgba, RgbaImage};
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::converter::Converters;
+use crate::error::PacError;
+use crate::font;
+use crate::img::ImageFormat;
+use crate::pac::{NameDecoding, PacArc, PacEntryRead, BMZ_MAGIC};
+use crate::source::PacSource;
+use crate::ttp::{ResKind, TtpFile};
+
+/// Sprites have no alpha, pixels of this color are treated as transparent
+const TRANSPARENT_KEY: [u8; 3] = [0, 255, 0];
+
+/// Composite frames of `ttp` from sprites and textboxes found in `arc`.
+///
+/// Each frame is drawn on transparent canvas of animation window size:
+/// sprite at `x_offset`, `y_offset`, then textbox at its own offsets
+pub fn render_frames<S: PacSource>(ttp: &TtpFile, arc: &PacArc<S>) -> Result<Vec<Frame>> {
+    let by_name = arc.entries.iter()
+        .map(|entry| Ok((entry.decode_name(NameDecoding::Replace)?.to_lowercase(), entry)))
+        .collect::<Result<HashMap<_, _>>>()?;
+    // decoded name -> image, most sprites are used by several frames
+    let mut images: HashMap<String, RgbaImage> = HashMap::new();
+
+    let mut frames = Vec::with_capacity(ttp.frames.len());
+    for ttp_frame in ttp.frames.iter() {
+        let mut canvas = RgbaImage::new(ttp.window_width, ttp.window_height);
+        for (kind, res) in ttp_frame.resources() {
+            let (x, y) = match kind {
+                ResKind::Sprite => (ttp_frame.x_offset, ttp_frame.y_offset),
+                ResKind::Textbox => (ttp_frame.x_offset_textbox, ttp_frame.y_offset_textbox),
+                ResKind::Se => continue,
+            };
+            let name = format!("{}.{}", res.decode()?, kind.ext());
+            if !images.contains_key(&name) {
+                let entry = by_name.get(&name.to_lowercase())
+                    .ok_or_else(|| PacError::Format(format!("{} {name} not found in archive", kind.field())))?;
+                let img = load_sprite(arc, entry)
+                    .with_context(|| format!("Failed to decode {name}"))?;
+                images.insert(name.clone(), img);
+            }
+            imageops::overlay(&mut canvas, &images[&name], x as i64, y as i64);
+        }
+        let delay = Delay::from_numer_denom_ms(ttp_frame.delay_ms, 1);
+        frames.push(Frame::from_parts(canvas, 0, 0, delay));
+    }
+
+    Ok(frames)
+}
+
+/// Decode BMZ sprite, making pixels of `TRANSPARENT_KEY` color transparent
+fn load_sprite<S: PacSource>(arc: &PacArc<S>, entry: &PacEntryRead) -> Result<RgbaImage> {
+    let bmp = arc.read_converted(entry, ImageFormat::Bmp, Converters::builtin())?;
+    let mut img = image::load_from_memory_with_format(&bmp, image::ImageFormat::Bmp)?.to_rgba8();
+    for px in img.pixels_mut().filter(|px| px.0[..3] == TRANSPARENT_KEY) {
+        px.0[3] = 0;
+    }
+    Ok(img)
+}
+
+/// Layout of contact sheet
+pub struct SheetOptions {
+    /// Sprites are scaled down to fit into square of this size (never scaled up)
+    pub thumb_size: u32,
+    /// Number of thumbnails per row
+    pub columns: u32,
+}
+
+impl Default for SheetOptions {
+    fn default() -> Self {
+        Self { thumb_size: 128, columns: 8 }
+    }
+}
+
+/// Tile thumbnails of all BMZ sprites of `arc` (in archive order) into single image,
+/// each labeled with entry index and name (truncated to thumbnail width, characters
+/// outside of ASCII are shown as `?`, so index is what tells sprites with non-latin names apart).
+/// Returns sheet and number of sprites on it
+pub fn contact_sheet<S: PacSource>(arc: &PacArc<S>, opts: &SheetOptions) -> Result<(RgbaImage, usize)> {
+    const PADDING: u32 = 4;
+    const LABEL_HEIGHT: u32 = font::GLYPH_HEIGHT + 2;
+    const BACKGROUND: Rgba<u8> = Rgba([48, 48, 48, 255]);
+    const LABEL_COLOR: Rgba<u8> = Rgba([230, 230, 230, 255]);
+
+    if opts.thumb_size == 0 || opts.columns == 0 {
+        bail!(PacError::InvalidInput("thumbnail size and number of columns must be positive".to_string()))
+    }
+    let thumbs = arc.entries.par_iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            if !arc.read_raw(entry)?.starts_with(BMZ_MAGIC) {
+                return Ok(None)
+            }
+            let img = load_sprite(arc, entry)
+                .with_context(|| format!("Failed to decode {}", arc.describe_entry(entry)))?;
+            let img = if img.width() > opts.thumb_size || img.height() > opts.thumb_size {
+                let scale = opts.thumb_size as f64 / img.width().max(img.height()) as f64;
+                let (w, h) = ((img.width() as f64 * scale).round().max(1.0), (img.height() as f64 * scale).round().max(1.0));
+                imageops::thumbnail(&img, w as u32, h as u32)
+            } else {
+                img
+            };
+            Ok(Some((format!("{index} {}", entry.decode_name(NameDecoding::Hex)?), img)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let thumbs: Vec<_> = thumbs.into_iter().flatten().collect();
+
+    let columns = opts.columns.min(thumbs.len().max(1) as u32);
+    let rows = (thumbs.len() as u32).div_ceil(columns).max(1);
+    let (cell_w, cell_h) = (opts.thumb_size + PADDING, opts.thumb_size + LABEL_HEIGHT + PADDING);
+    let mut sheet = RgbaImage::from_pixel(columns * cell_w + PADDING, rows * cell_h + PADDING, BACKGROUND);
+    let max_chars = ((opts.thumb_size + 1) / (font::GLYPH_WIDTH + 1)) as usize;
+    for (i, (name, img)) in thumbs.iter().enumerate() {
+        let (x, y) = (PADDING + i as u32 % columns * cell_w, PADDING + i as u32 / columns * cell_h);
+        let (dx, dy) = ((opts.thumb_size - img.width()) / 2, (opts.thumb_size - img.height()) / 2);
+        imageops::overlay(&mut sheet, img, (x + dx) as i64, (y + dy) as i64);
+
+        let label = match name.chars().count() > max_chars {
+            true => name.chars().take(max_chars.saturating_sub(1)).chain(['~']).collect(),
+            false => name.clone(),
+        };
+        let label_x = x + (opts.thumb_size - font::text_width(label.chars().count()).min(opts.thumb_size)) / 2;
+        font::draw_text(&mut sheet, label_x, y + opts.thumb_size + 2, &label, LABEL_COLOR);
+    }
+    Ok((sheet, thumbs.len()))
+}
+
+/// Atlas of sprite sheet, in JSON-array layout of TexturePacker / Aseprite,
+/// which importers of common game engines (Godot, Unity, Phaser) read
+#[derive(Serialize, Debug)]
+pub struct SheetAtlas {
+    pub frames: Vec<AtlasFrame>,
+    pub meta: AtlasMeta,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AtlasFrame {
+    pub filename: String,
+    /// Place of frame on sheet, identical frames share it
+    pub frame: AtlasRect,
+    pub rotated: bool,
+    pub trimmed: bool,
+    /// Place of trimmed frame inside of animation window
+    pub sprite_source_size: AtlasRect,
+    /// Animation window size
+    pub source_size: AtlasSize,
+    /// Frame delay in milliseconds
+    pub duration: u32,
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct AtlasRect {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AtlasSize {
+    pub w: u32,
+    pub h: u32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AtlasMeta {
+    pub app: &'static str,
+    pub version: &'static str,
+    /// Sheet image file name
+    pub image: String,
+    pub format: &'static str,
+    pub size: AtlasSize,
+    pub scale: &'static str,
+}
+
+/// Pack rendered frames of `ttp` into single sprite sheet.
+///
+/// Frames are trimmed to their non-transparent area, identical frames are stored once,
+/// then placed on shelves (tallest first) of roughly square sheet with 1 pixel gaps.
+/// `image` is sheet file name recorded in atlas
+pub fn sprite_sheet<S: PacSource>(ttp: &TtpFile, arc: &PacArc<S>, image: &str) -> Result<(RgbaImage, SheetAtlas)> {
+    const GAP: u32 = 1;
+
+    let frames = render_frames(ttp, arc)?;
+    // trimmed images and place of each one inside of window
+    let mut unique: Vec<RgbaImage> = vec![];
+    let mut placed = Vec::with_capacity(frames.len());
+    for frame in frames.iter() {
+        let (source, img) = trim(frame.buffer());
+        let index = match unique.iter().position(|u| *u == img) {
+            Some(index) => index,
+            None => {
+                unique.push(img);
+                unique.len() - 1
+            },
+        };
+        placed.push((index, source, delay_ms(frame)));
+    }
+
+    let area: u64 = unique.iter().map(|img| (img.width() + GAP) as u64 * (img.height() + GAP) as u64).sum();
+    let widest = unique.iter().map(|img| img.width()).max().unwrap_or(1);
+    let sheet_width = ((area as f64).sqrt().ceil() as u32).max(widest);
+    let mut order: Vec<usize> = (0..unique.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(unique[i].height()));
+
+    let mut rects = vec![AtlasRect { x: 0, y: 0, w: 0, h: 0 }; unique.len()];
+    let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+    for i in order {
+        let (w, h) = unique[i].dimensions();
+        if x > 0 && x + w > sheet_width {
+            (x, y, shelf_height) = (0, y + shelf_height + GAP, 0);
+        }
+        rects[i] = AtlasRect { x, y, w, h };
+        x += w + GAP;
+        shelf_height = shelf_height.max(h);
+    }
+
+    let mut sheet = RgbaImage::new(rects.iter().map(|r| r.x + r.w).max().unwrap_or(1), (y + shelf_height).max(1));
+    for (img, rect) in unique.iter().zip(rects.iter()) {
+        imageops::replace(&mut sheet, img, rect.x as i64, rect.y as i64);
+    }
+
+    let atlas = SheetAtlas {
+        frames: placed.into_iter().enumerate()
+            .map(|(i, (index, source, duration))| AtlasFrame {
+                filename: format!("frame{i}"),
+                frame: rects[index],
+                rotated: false,
+                trimmed: (source.w, source.h) != (ttp.window_width, ttp.window_height),
+                sprite_source_size: source,
+                source_size: AtlasSize { w: ttp.window_width, h: ttp.window_height },
+                duration,
+            })
+            .collect(),
+        meta: AtlasMeta {
+            app: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            image: image.to_string(),
+            format: "RGBA8888",
+            size: AtlasSize { w: sheet.width(), h: sheet.height() },
+            scale: "1",
+        },
+    };
+    Ok((sheet, atlas))
+}
+
+/// Crop `img` to bounding box of its non-transparent pixels (1x1 for fully transparent image)
+fn trim(img: &RgbaImage) -> (AtlasRect, RgbaImage) {
+    let (mut x0, mut y0, mut x1, mut y1) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y, px) in img.enumerate_pixels() {
+        if px.0[3] != 0 {
+            (x0, y0, x1, y1) = (x0.min(x), y0.min(y), x1.max(x + 1), y1.max(y + 1));
+        }
+    }
+    if x0 == u32::MAX {
+        (x0, y0, x1, y1) = (0, 0, 1, 1);
+    }
+    let rect = AtlasRect { x: x0, y: y0, w: x1 - x0, h: y1 - y0 };
+    (rect, imageops::crop_imm(img, x0, y0, rect.w, rect.h).to_image())
+}
+
+/// Format of rendered animation
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum AnimFormat {
+    /// 256 colors, 1-bit transparency
+    #[default]
+    Gif,
+    /// Full color with alpha
+    Apng,
+    /// Lossless, full color with alpha
+    Webp,
+}
+
+impl AnimFormat {
+    /// Encode rendered frames as endlessly looping animation
+    pub fn write(self, frames: Vec<Frame>, out: impl Write) -> Result<()> {
+        match self {
+            AnimFormat::Gif => write_gif(frames, out),
+            AnimFormat::Apng => write_apng(frames, out),
+            AnimFormat::Webp => write_webp(frames, out),
+        }
+    }
+}
+
+impl FromStr for AnimFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "gif" => Ok(AnimFormat::Gif),
+            "apng" => Ok(AnimFormat::Apng),
+            "webp" => Ok(AnimFormat::Webp),
+            _ => bail!(PacError::InvalidInput(format!("unknown animation format: {s} (expected gif, apng or webp)"))),
+        }
+    }
+}
+
+impl Display for AnimFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AnimFormat::Gif => "gif",
+            AnimFormat::Apng => "apng",
+            AnimFormat::Webp => "webp",
+        })
+    }
+}
+
+fn write_gif(frames: Vec<Frame>, out: impl Write) -> Result<()> {
+    let mut encoder = GifEncoder::new_with_speed(out, 10);
+    encoder.set_repeat(Repeat::Infinite)?;
+    encoder.encode_frames(frames)?;
+    Ok(())
+}
+
+fn write_apng(frames: Vec<Frame>, out: impl Write) -> Result<()> {
+    let Some(first) = frames.first() else {
+        bail!(PacError::Format("animation has no frames".to_string()))
+    };
+    let (width, height) = first.buffer().dimensions();
+    let mut encoder = png::Encoder::new(out, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, 0)?;
+    let mut writer = encoder.write_header()?;
+    for frame in frames.iter() {
+        let ms = delay_ms(frame);
+        let (num, den) = match u16::try_from(ms) {
+            Ok(ms) => (ms, 1000),
+            Err(_) => (u16::try_from(ms / 1000).unwrap_or(u16::MAX), 1),
+        };
+        writer.set_frame_delay(num, den)?;
+        writer.write_image_data(frame.buffer())?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Animated WebP container, frames are encoded as lossless VP8L bitstreams.
+/// See https://developers.google.com/speed/webp/docs/riff_container
+fn write_webp(frames: Vec<Frame>, mut out: impl Write) -> Result<()> {
+    let Some(first) = frames.first() else {
+        bail!(PacError::Format("animation has no frames".to_string()))
+    };
+    let (width, height) = first.buffer().dimensions();
+
+    const ALPHA_FLAG: u8 = 0x10;
+    const ANIMATION_FLAG: u8 = 0x02;
+    let mut vp8x = vec![ALPHA_FLAG | ANIMATION_FLAG, 0, 0, 0];
+    vp8x.extend(u24(width - 1)?);
+    vp8x.extend(u24(height - 1)?);
+    // transparent background, infinite loop
+    let anim = [0, 0, 0, 0, 0, 0];
+
+    let mut body = b"WEBP".to_vec();
+    push_chunk(&mut body, b"VP8X", &vp8x);
+    push_chunk(&mut body, b"ANIM", &anim);
+    for frame in frames.iter() {
+        let img = frame.buffer();
+        let mut still = vec![];
+        WebPEncoder::new_lossless(&mut still)
+            .write_image(img.as_raw(), img.width(), img.height(), ExtendedColorType::Rgba8)?;
+
+        let mut anmf = vec![];
+        anmf.extend(u24(0)?); // x / 2
+        anmf.extend(u24(0)?); // y / 2
+        anmf.extend(u24(img.width() - 1)?);
+        anmf.extend(u24(img.height() - 1)?);
+        anmf.extend(u24(delay_ms(frame).min(0xFF_FFFF))?);
+        anmf.push(0x02); // don't blend with previous frame, don't dispose
+        anmf.extend(vp8l_chunk(&still)?);
+        push_chunk(&mut body, b"ANMF", &anmf);
+    }
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&(body.len() as u32).to_le_bytes())?;
+    out.write_all(&body)?;
+    Ok(())
+}
+
+fn delay_ms(frame: &Frame) -> u32 {
+    let (num, den) = frame.delay().numer_denom_ms();
+    num / den.max(1)
+}
+
+fn u24(value: u32) -> Result<[u8; 3]> {
+    if value > 0xFF_FFFF {
+        bail!(PacError::SizeLimit(format!("value {value} doesn't fit into 24 bits")))
+    }
+    let [a, b, c, _] = value.to_le_bytes();
+    Ok([a, b, c])
+}
+
+fn push_chunk(out: &mut Vec<u8>, fourcc: &[u8; 4], data: &[u8]) {
+    out.extend(fourcc);
+    out.extend((data.len() as u32).to_le_bytes());
+    out.extend(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// Find VP8L chunk (with its header) in encoded still WebP
+fn vp8l_chunk(webp: &[u8]) -> Result<&[u8]> {
+    let mut chunks = webp.get(12..).unwrap_or_default();
+    while let [fourcc @ .., s0, s1, s2, s3] = chunks.get(..8).unwrap_or_default() {
+        let size = u32::from_le_bytes([*s0, *s1, *s2, *s3]) as usize;
+        let end = (8 + size + size % 2).min(chunks.len());
+        if fourcc == b"VP8L" {
+            return Ok(&chunks[..end])
+        }
+        chunks = &chunks[end..];
+    }
+    bail!(PacError::Decode("encoded frame has no VP8L chunk".to_string()))
+}
@@ -0,0 +1,233 @@
+//! Identification of files by content, for files that were renamed or dumped without extensions
+
+use std::fmt::Display;
+use std::io::Cursor;
+use anyhow::Result;
+use binrw::{BinReaderExt, Endian};
+
+use crate::error::PacError;
+use crate::format::PacFormat;
+use crate::img::BmpInfo;
+use crate::pac::{inflate, PacEntryRead, BMZ_MAGIC};
+use crate::source::PacSource;
+use crate::ttp::TtpFile;
+
+/// Kind of identified file
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FileKind {
+    /// `.pac` archive of any supported layout
+    Archive,
+    /// Standalone ZLC3 (zlib-compressed BMP) blob
+    Bmz,
+    /// Standalone `.ttp` animation
+    Ttp,
+    Unknown,
+}
+
+impl Display for FileKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FileKind::Archive => "pac archive",
+            FileKind::Bmz => "bmz sprite",
+            FileKind::Ttp => "ttp animation",
+            FileKind::Unknown => "unknown",
+        })
+    }
+}
+
+/// How strongly content supports the verdict
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Confidence {
+    Low,
+    Medium,
+    High,
+}
+
+impl Display for Confidence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Confidence::Low => "low",
+            Confidence::Medium => "medium",
+            Confidence::High => "high",
+        })
+    }
+}
+
+/// Verdict of `sniff` with human-readable evidence it's based on
+pub struct Sniffed {
+    pub kind: FileKind,
+    pub confidence: Confidence,
+    pub details: Vec<String>,
+    /// Detected layout, for archives
+    pub format: Option<PacFormat>,
+}
+
+impl Sniffed {
+    fn new(kind: FileKind, confidence: Confidence, details: Vec<String>) -> Self {
+        Self { kind, confidence, details, format: None }
+    }
+}
+
+/// Identify content of `source`: ZLC3 magic is checked first, then archive layouts
+/// (including damaged archives of default layout), then `.ttp` structure. Archives with empty entry table are reported only if nothing else fits,
+/// as any file starting with zero count fits them
+pub fn sniff<S: PacSource + ?Sized>(source: &S) -> Result<Sniffed> {
+    let len = source.size()?;
+    if len >= 8 && source.read_at(0, 4)?[..] == BMZ_MAGIC[..] {
+        return sniff_bmz(&source.read_at(0, len as usize)?)
+    }
+
+    let archive = match PacFormat::detect(source)? {
+        Some(format) => match sniff_archive(source, format, len)? {
+            archive if archive.confidence > Confidence::Low => return Ok(archive),
+            archive => Some(archive),
+        },
+        None => None,
+    };
+    if let Some(damaged) = sniff_damaged_archive(source, len)? {
+        return Ok(damaged)
+    }
+    if let Some(ttp) = sniff_ttp(&source.read_at(0, len as usize)?) {
+        return Ok(ttp)
+    }
+    Ok(archive.unwrap_or_else(|| Sniffed::new(FileKind::Unknown, Confidence::Low, vec![
+        format!("{len} bytes, no ZLC3 magic, no consistent archive entry table, not a ttp animation"),
+    ])))
+}
+
+fn sniff_bmz(data: &[u8]) -> Result<Sniffed> {
+    let mut details = vec!["starts with ZLC3 magic".to_string()];
+    let declared: u32 = Cursor::new(&data[4..8]).read_type(Endian::Little)?;
+    let confidence = match inflate(&data[8..]) {
+        Err(e) => {
+            details.push(format!("zlib stream doesn't inflate: {}", PacError::from(e)));
+            Confidence::Low
+        },
+        Ok(bmp) => {
+            let size_ok = bmp.len() == declared as usize;
+            details.push(match size_ok {
+                true => format!("inflates to declared size of {declared} bytes"),
+                false => format!("inflates to {} bytes, but header declares {declared}", bmp.len()),
+            });
+            let bitmap_ok = match BmpInfo::parse(&bmp) {
+                Ok(info) => {
+                    details.push(format!("bitmap {}x{}, {} bpp", info.width, info.height.abs(), info.bit_depth));
+                    true
+                },
+                Err(_) => {
+                    details.push("inflated data is not a bitmap".into());
+                    false
+                },
+            };
+            match (size_ok, bitmap_ok) {
+                (true, true) => Confidence::High,
+                (false, false) => Confidence::Low,
+                _ => Confidence::Medium,
+            }
+        },
+    };
+    Ok(Sniffed::new(FileKind::Bmz, confidence, details))
+}
+
+fn sniff_archive<S: PacSource + ?Sized>(source: &S, format: PacFormat, len: u64) -> Result<Sniffed> {
+    let count: u32 = Cursor::new(source.read_at(format.count_offset(), 4)?).read_type(format.endian)?;
+    let table_end = format.table_end(count as u64);
+    let table = source.read_at(format.table_offset(), (table_end - format.table_offset()) as usize)?;
+    let mut table = Cursor::new(table);
+    let (mut data_end, mut bmz) = (table_end, 0);
+    for _ in 0..count {
+        let e: PacEntryRead = table.read_type_args(format.endian, (format.name_size,))?;
+        data_end = data_end.max(e.offset as u64 + e.size as u64);
+        if e.size >= 4 && source.read_at(e.offset as u64, 4)?[..] == BMZ_MAGIC[..] {
+            bmz += 1;
+        }
+    }
+
+    let mut details = vec![
+        format!("layout: {format}"),
+        format!("entry table of {count} entries ends at {table_end}, all names and data ranges are consistent"),
+        format!("{bmz} entries start with ZLC3 magic"),
+    ];
+    let confidence = if count == 0 {
+        details.push("entry table is empty".into());
+        Confidence::Low
+    } else if data_end == len {
+        details.push("entry data ends exactly at end of file".into());
+        Confidence::High
+    } else {
+        details.push(format!("{} bytes after the end of last entry data", len - data_end));
+        Confidence::Medium
+    };
+    Ok(Sniffed { format: Some(format), ..Sniffed::new(FileKind::Archive, confidence, details) })
+}
+
+/// Archive of default layout (of either byte order) which entry table is present and has sane names,
+/// but some data ranges are outside of file
+fn sniff_damaged_archive<S: PacSource + ?Sized>(source: &S, len: u64) -> Result<Option<Sniffed>> {
+    for endian in [Endian::Little, Endian::Big] {
+        let format = PacFormat { endian, ..Default::default() };
+        if len < format.table_offset() {
+            return Ok(None)
+        }
+        let count: u32 = Cursor::new(source.read_at(format.count_offset(), 4)?).read_type(endian)?;
+        let table_end = format.table_end(count as u64);
+        if count == 0 || table_end > len {
+            continue
+        }
+        let table = source.read_at(format.table_offset(), (table_end - format.table_offset()) as usize)?;
+        let names_ok = table.chunks_exact(format.record_size())
+            .map(|record| &record[PacFormat::RECORD_FIELDS_SIZE..])
+            .all(|name| name[0] != 0 && name.contains(&0));
+        if !names_ok {
+            continue
+        }
+        let mut table = Cursor::new(table);
+        let mut outside = 0;
+        for _ in 0..count {
+            let e: PacEntryRead = table.read_type_args(endian, (format.name_size,))?;
+            if e.offset as u64 + e.size as u64 > len || (e.size > 0 && (e.offset as u64) < table_end) {
+                outside += 1;
+            }
+        }
+        let details = vec![
+            format!("layout: {format}"),
+            format!("entry table of {count} entries ends at {table_end}, all names are consistent"),
+            format!("data ranges of {outside} entries are outside of file, archive is damaged"),
+        ];
+        return Ok(Some(Sniffed { format: Some(format), ..Sniffed::new(FileKind::Archive, Confidence::Medium, details) }))
+    }
+    Ok(None)
+}
+
+fn sniff_ttp(data: &[u8]) -> Option<Sniffed> {
+    for endian in [Endian::Little, Endian::Big] {
+        let mut cursor = Cursor::new(data);
+        let Ok(ttp) = cursor.read_type::<TtpFile>(endian) else {
+            continue
+        };
+        if ttp.validate().is_err() {
+            continue
+        }
+        let trailing = data.len() as u64 - cursor.position();
+        let mut details = vec![format!(
+            "type {}, {} frames, window {}x{}", ttp.maybe_ttp_type, ttp.frame_count, ttp.window_width, ttp.window_height,
+        )];
+        if endian == Endian::Big {
+            details.push("big-endian".into());
+        }
+        match trailing {
+            0 => details.push("animation ends exactly at end of file".into()),
+            _ => details.push(format!("{trailing} bytes after the end of animation")),
+        }
+        let confidence = if ttp.frames.is_empty() {
+            details.push("animation has no frames".into());
+            Confidence::Low
+        } else if trailing > 0 {
+            Confidence::Medium
+        } else {
+            Confidence::High
+        };
+        return Some(Sniffed::new(FileKind::Ttp, confidence, details))
+    }
+    None
+}
@@ -0,0 +1,99 @@
+//! Storage backends archive entries are read from
+
+use std::borrow::Cow;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Mutex;
+use anyhow::{Result, bail};
+
+use crate::error::PacError;
+
+/// Random-access storage, archive is read from
+pub trait PacSource: Send + Sync {
+    /// Read `size` bytes at `offset`
+    fn read_at(&self, offset: u64, size: usize) -> Result<Cow<'_, [u8]>>;
+
+    /// Total size of source in bytes
+    fn size(&self) -> Result<u64>;
+}
+
+/// Type-erased source
+pub type DynSource = Box<dyn PacSource>;
+
+impl<S: PacSource + ?Sized> PacSource for Box<S> {
+    fn read_at(&self, offset: u64, size: usize) -> Result<Cow<'_, [u8]>> {
+        (**self).read_at(offset, size)
+    }
+
+    fn size(&self) -> Result<u64> {
+        (**self).size()
+    }
+}
+
+/// Source over any `Read + Seek` handle, reads are serialized by mutex
+pub struct ReaderSource<R>(Mutex<R>);
+
+impl<R> ReaderSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self(Mutex::new(reader))
+    }
+}
+
+impl<R: Read + Seek + Send> PacSource for ReaderSource<R> {
+    fn read_at(&self, offset: u64, size: usize) -> Result<Cow<'_, [u8]>> {
+        let mut reader = self.0.lock().expect("reader lock poisoned");
+        reader.seek(SeekFrom::Start(offset))?;
+
+        let mut data = vec![0; size];
+        reader.read_exact(&mut data)?;
+        Ok(Cow::Owned(data))
+    }
+
+    fn size(&self) -> Result<u64> {
+        let mut reader = self.0.lock().expect("reader lock poisoned");
+        Ok(reader.seek(SeekFrom::End(0))?)
+    }
+}
+
+/// Read range from in-memory data without copying
+fn slice_at(data: &[u8], offset: u64, size: usize) -> Result<Cow<'_, [u8]>> {
+    let range = usize::try_from(offset).ok()
+        .and_then(|start| Some(start..start.checked_add(size)?))
+        .filter(|r| r.end <= data.len());
+
+    match range {
+        Some(r) => Ok(Cow::Borrowed(&data[r])),
+        None => bail!(PacError::SizeLimit(format!("range of {size} bytes at offset {offset} is out of bounds ({} bytes)", data.len()))),
+    }
+}
+
+impl PacSource for Vec<u8> {
+    fn read_at(&self, offset: u64, size: usize) -> Result<Cow<'_, [u8]>> {
+        slice_at(self, offset, size)
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.len() as u64)
+    }
+}
+
+/// Memory-map file for use as source.
+///
+/// File must not be modified while mapping is alive
+#[cfg(feature = "mmap")]
+pub fn map_file(path: impl AsRef<std::path::Path>) -> Result<memmap2::Mmap> {
+    let file = std::fs::File::open(path)?;
+    // SAFETY: mapping is read-only, caller is responsible for file to not be modified meanwhile
+    Ok(unsafe { memmap2::Mmap::map(&file)? })
+}
+
+/// Memory-mapped file, entries are sliced directly from mapping
+#[cfg(feature = "mmap")]
+impl PacSource for memmap2::Mmap {
+    fn read_at(&self, offset: u64, size: usize) -> Result<Cow<'_, [u8]>> {
+        slice_at(self, offset, size)
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.len() as u64)
+    }
+}
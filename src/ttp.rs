@@ -1,8 +1,14 @@
 use serde::{Deserialize, Serialize, de::Visitor};
 use binrw::{BinRead, BinWrite};
+use std::collections::HashSet;
 
+use anyhow::{Result, bail};
 use encoding_rs::SHIFT_JIS;
 
+use crate::error::PacError;
+use crate::pac::{NameDecoding, PacArc};
+use crate::source::PacSource;
+
 /// Encoded animation
 #[derive(Serialize, Deserialize, BinRead, BinWrite)]
 pub struct TtpFile {
@@ -22,8 +28,71 @@ pub struct TtpFile {
     pub onetime_wakeup_dont_play_sound: Option<u8>,
 }
 
+impl TtpFile {
+    /// Check that header fields agree with the rest of the animation
+    pub fn validate(&self) -> Result<()> {
+        if self.frame_count as usize != self.frames.len() {
+            bail!(PacError::Format(format!("frame_count is {}, but {} frames present", self.frame_count, self.frames.len())))
+        }
+        match (self.maybe_ttp_type, &self.onetime_wakeup_dont_play_sound) {
+            (3, None) => bail!(PacError::Format("onetime_wakeup_dont_play_sound is required for ttp type 3".into())),
+            (typ, Some(_)) if typ != 3 => bail!(PacError::Format(format!(
+                "onetime_wakeup_dont_play_sound is only allowed for ttp type 3 (got type {typ})"
+            ))),
+            _ => Ok(())
+        }
+    }
+
+    /// Insert `frame` at `index`, shifting following frames
+    pub fn insert_frame(&mut self, index: usize, frame: TtpFrame) -> Result<()> {
+        if index > self.frames.len() {
+            bail!(PacError::InvalidInput(format!("frame index {index} out of range (animation has {} frames)", self.frames.len())))
+        }
+        self.frames.insert(index, frame);
+        self.frame_count = self.frames.len() as u32;
+        Ok(())
+    }
+
+    /// Remove frame at `index`
+    pub fn remove_frame(&mut self, index: usize) -> Result<TtpFrame> {
+        self.check_index(index)?;
+        let frame = self.frames.remove(index);
+        self.frame_count = self.frames.len() as u32;
+        Ok(frame)
+    }
+
+    /// Insert copy of frame at `index` right after it
+    pub fn duplicate_frame(&mut self, index: usize) -> Result<()> {
+        self.check_index(index)?;
+        self.insert_frame(index + 1, self.frames[index].clone())
+    }
+
+    /// Move frame at `from` so it ends up at index `to`
+    pub fn move_frame(&mut self, from: usize, to: usize) -> Result<()> {
+        self.check_index(from)?;
+        self.check_index(to)?;
+        let frame = self.frames.remove(from);
+        self.frames.insert(to, frame);
+        Ok(())
+    }
+
+    fn check_index(&self, index: usize) -> Result<()> {
+        if index >= self.frames.len() {
+            bail!(PacError::InvalidInput(format!("frame index {index} out of range (animation has {} frames)", self.frames.len())))
+        }
+        Ok(())
+    }
+
+    /// Replace delay of every frame with `f(delay_ms)`
+    pub fn map_delays(&mut self, f: impl Fn(u32) -> u32) {
+        for frame in self.frames.iter_mut() {
+            frame.delay_ms = f(frame.delay_ms);
+        }
+    }
+}
+
 /// Frame of animation
-#[derive(Serialize, Deserialize, BinRead, BinWrite)]
+#[derive(Clone, Serialize, Deserialize, BinRead, BinWrite)]
 pub struct TtpFrame {
     pub sprite_name: ResName,
     pub se_name: ResName,
@@ -36,23 +105,75 @@ pub struct TtpFrame {
     pub y_offset: u32,
 }
 
+impl TtpFrame {
+    /// Non-empty resource names referenced by frame
+    pub fn resources(&self) -> impl Iterator<Item = (ResKind, &ResName)> {
+        [
+            (ResKind::Sprite, &self.sprite_name),
+            (ResKind::Se, &self.se_name),
+            (ResKind::Textbox, &self.textbox_name),
+        ]
+        .into_iter()
+        .filter(|(_, name)| !name.is_empty())
+    }
+}
+
+/// Kind of resource referenced by frame
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResKind {
+    Sprite,
+    Se,
+    Textbox,
+}
+
+impl ResKind {
+    /// Name of frame field
+    pub fn field(self) -> &'static str {
+        match self {
+            ResKind::Sprite => "sprite_name",
+            ResKind::Se => "se_name",
+            ResKind::Textbox => "textbox_name",
+        }
+    }
+
+    /// Extension of archive entry holding resource
+    pub fn ext(self) -> &'static str {
+        match self {
+            ResKind::Sprite | ResKind::Textbox => "bmz",
+            ResKind::Se => "wav",
+        }
+    }
+}
+
 /// Variable-length SHIFT-JIS-encoded resource name
-#[derive(BinRead, BinWrite)]
+///
+/// When deserialized, `len` is always recomputed from the encoded string
+#[derive(Clone, BinRead, BinWrite)]
 pub struct ResName {
     len: u32,
     #[br(count = len)]
     sj_bytes: Vec<u8>
 }
 
+impl ResName {
+    pub fn is_empty(&self) -> bool {
+        self.sj_bytes.is_empty()
+    }
+
+    pub fn decode(&self) -> Result<String> {
+        match SHIFT_JIS.decode(&self.sj_bytes) {
+            (cow, _, false) => Ok(cow.into_owned()),
+            (_, _, true) => bail!(PacError::NameEncoding("failed to decode shift-jis".into())),
+        }
+    }
+}
+
 impl Serialize for ResName {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer {
         use serde::ser::Error;
-        let decoded = match SHIFT_JIS.decode(&self.sj_bytes) {
-            (cow, _, false) => cow,
-            (_, _, true) => return Err(Error::custom("failed to decode shift-jis")), 
-        };
+        let decoded = self.decode().map_err(Error::custom)?;
         serializer.serialize_str(&decoded)
     }
 }
@@ -86,4 +207,53 @@ impl<'de> Deserialize<'de> for ResName {
         D: serde::Deserializer<'de> {
         deserializer.deserialize_str(ResNameVisitor)
     }
-}
\ No newline at end of file
+}
+
+/// Resource referenced by animation, but absent in archive
+pub struct MissingRes {
+    /// Name of animation, as passed to `check_resources`
+    pub anim: String,
+    /// Index of referencing frame
+    pub frame: usize,
+    pub kind: ResKind,
+    pub name: String,
+}
+
+/// Result of checking animations against archive
+pub struct ResCheck {
+    pub missing: Vec<MissingRes>,
+    /// Names of bmz and wav entries not referenced by any animation
+    pub unused: Vec<String>,
+}
+
+/// Check that every resource referenced by `anims` (pairs of name and animation) is present in `arc`.
+/// Resource `name` is looked up as `name.bmz` (sprites, textboxes) or `name.wav` (sounds), ignoring case
+pub fn check_resources<S: PacSource>(anims: &[(String, TtpFile)], arc: &PacArc<S>) -> Result<ResCheck> {
+    let names = arc.entries.iter()
+        .map(|entry| entry.decode_name(NameDecoding::Replace))
+        .collect::<Result<Vec<_>>>()?;
+    let available: HashSet<String> = names.iter().map(|name| name.to_lowercase()).collect();
+
+    let mut referenced = HashSet::new();
+    let mut missing = vec![];
+    for (anim, ttp) in anims {
+        for (frame, ttp_frame) in ttp.frames.iter().enumerate() {
+            for (kind, res) in ttp_frame.resources() {
+                let name = res.decode()?;
+                let entry_name = format!("{name}.{}", kind.ext()).to_lowercase();
+                if !available.contains(&entry_name) {
+                    missing.push(MissingRes { anim: anim.clone(), frame, kind, name });
+                }
+                referenced.insert(entry_name);
+            }
+        }
+    }
+
+    let unused = names.into_iter()
+        .filter(|name| {
+            let lower = name.to_lowercase();
+            (lower.ends_with(".bmz") || lower.ends_with(".wav")) && !referenced.contains(&lower)
+        })
+        .collect();
+    Ok(ResCheck { missing, unused })
+}
@@ -0,0 +1,149 @@
+//! Archive integrity checks
+
+use binrw::BinReaderExt;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use anyhow::Result;
+use encoding_rs::SHIFT_JIS;
+
+use crate::format::PacFormat;
+use crate::pac::{inflate, PacEntryRead, BMZ_MAGIC};
+use crate::source::ReaderSource;
+
+/// Single problem found in archive
+pub struct Problem {
+    /// Index of affected entry, `None` for archive-wide problems
+    pub index: Option<usize>,
+    /// Name of affected entry (lossily decoded)
+    pub name: String,
+    pub message: String,
+}
+
+/// Result of archive verification
+#[derive(Default)]
+pub struct VerifyReport {
+    /// Number of entries in archive table
+    pub entries_count: usize,
+    /// Number of BMZ streams that were inflated
+    pub bmz_checked: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl VerifyReport {
+    /// No problems found
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    fn problem(&mut self, index: Option<usize>, name: &str, message: String) {
+        self.problems.push(Problem { index, name: name.to_string(), message })
+    }
+}
+
+/// Check archive consistency: entry table and data ranges must be within file,
+/// data ranges must not overlap (entries sharing exactly same range are fine), BMZ streams must inflate to size declared in their header.
+///
+/// Layout of archive is detected, default one is assumed if none fits.
+/// Only I/O errors are returned as `Err`, everything else is collected to report.
+pub fn verify<R: Read + Seek + Send>(reader: &mut R) -> Result<VerifyReport> {
+    let format = PacFormat::detect(&ReaderSource::new(&mut *reader))?.unwrap_or_default();
+    verify_as(reader, &format)
+}
+
+/// Same as `verify`, with layout `format`
+pub fn verify_as<R: Read + Seek>(reader: &mut R, format: &PacFormat) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    if file_len < format.table_offset() {
+        report.problem(None, "", format!("file is too short to be archive ({file_len} bytes)"));
+        return Ok(report)
+    }
+
+    let mut magic = vec![0; format.magic.len()];
+    reader.read_exact(&mut magic)?;
+    if magic != format.magic {
+        report.problem(None, "", format!("archive doesn't start with magic {}", String::from_utf8_lossy(&format.magic)));
+        return Ok(report)
+    }
+    let count: u32 = reader.read_type(format.endian)?;
+    let count = count as u64;
+    report.entries_count = count as usize;
+    let table_end = format.table_end(count);
+    if table_end > file_len {
+        report.problem(None, "", format!(
+            "entry table of {count} entries ends at {table_end}, past end of file ({file_len})"
+        ));
+        return Ok(report)
+    }
+
+    let mut entries = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let e: PacEntryRead = reader.read_type_args(format.endian, (format.name_size,))?;
+        let name = SHIFT_JIS.decode(&e.name).0.to_string();
+        entries.push((e, name));
+    }
+
+    for (idx, (e, name)) in entries.iter().enumerate() {
+        if SHIFT_JIS.decode_without_bom_handling_and_without_replacement(&e.name).is_none() {
+            report.problem(Some(idx), name, "name is not valid SHIFT-JIS".into());
+        }
+
+        let end = e.offset as u64 + e.size as u64;
+        if (e.offset as u64) < table_end && e.size > 0 {
+            report.problem(Some(idx), name, format!("data offset {} is inside entry table", e.offset));
+        }
+        if end > file_len {
+            report.problem(Some(idx), name, format!(
+                "data range {}..{end} is past end of file ({file_len})", e.offset
+            ));
+            continue
+        }
+
+        reader.seek(SeekFrom::Start(e.offset as u64))?;
+        let mut data = vec![0; e.size as usize];
+        reader.read_exact(&mut data)?;
+
+        if data.len() >= 8 && &data[..4] == BMZ_MAGIC {
+            report.bmz_checked += 1;
+            let uncompressed_size: u32 = Cursor::new(&data[4..8]).read_type(format.endian)?;
+            match inflate(&data[8..]) {
+                Ok(inflated) if inflated.len() != uncompressed_size as usize => 
+                    report.problem(Some(idx), name, format!(
+                        "bmz inflates to {} bytes, but header says {uncompressed_size}", inflated.len()
+                    )),
+                Ok(_) => (),
+                Err(e) => report.problem(Some(idx), name, format!("bmz stream does not inflate: {e}")),
+            }
+        }
+    }
+
+    // check overlapping ranges (out of bounds ones were already reported)
+    let mut ranges: Vec<_> = entries.iter()
+        .enumerate()
+        .map(|(idx, (e, _))| (e.offset as u64, e.offset as u64 + e.size as u64, idx))
+        .filter(|&(start, end, _)| start < end && end <= file_len)
+        .collect();
+    ranges.sort();
+
+    let mut prev: Option<(u64, u64, usize)> = None;
+    for (start, end, idx) in ranges {
+        if let Some((prev_start, prev_end, prev_idx)) = prev {
+            // deduplicated data
+            if (start, end) == (prev_start, prev_end) {
+                continue
+            }
+            if start < prev_end {
+                report.problem(Some(idx), &entries[idx].1, format!(
+                    "data overlaps with entry {prev_idx} ({})", entries[prev_idx].1
+                ));
+            }
+            if end <= prev_end {
+                continue
+            }
+        }
+        prev = Some((start, end, idx));
+    }
+
+    Ok(report)
+}
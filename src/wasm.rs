@@ -0,0 +1,110 @@
+//! Bindings for browser use (`wasm32-unknown-unknown` with `wasm-bindgen`).
+//!
+//! Everything works on in-memory buffers, so archives can be listed and extracted client-side:
+//! ```js
+//! const arc = new PacViewer(new Uint8Array(await file.arrayBuffer()), "png");
+//! for (const entry of JSON.parse(arc.entries())) {
+//!     const data = arc.readEntry(entry.index);
+//! }
+//! ```
+
+use anyhow::Result;
+use binrw::{BinRead, BinWriterExt, Endian};
+use serde::Serialize;
+use std::io::Cursor;
+use wasm_bindgen::prelude::*;
+
+use crate::img::ImageFormat;
+use crate::converter::Converters;
+use crate::pac::{ExtractOptions, ExtractTarget, PacArc};
+use crate::ttp::TtpFile;
+
+/// Archive loaded from memory, with entries converted to `image_format`
+#[wasm_bindgen]
+pub struct PacViewer {
+    arc: PacArc<Vec<u8>>,
+    image_format: ImageFormat,
+    targets: Vec<Option<ExtractTarget>>,
+}
+
+#[derive(Serialize)]
+struct ViewerEntry<'a> {
+    index: usize,
+    name: &'a str,
+    /// File name entry would be extracted to
+    file: &'a str,
+    /// Size as stored in archive
+    size: u32,
+}
+
+#[wasm_bindgen]
+impl PacViewer {
+    /// Parse archive from `data`. Sprites are converted to `image_format` (bmp, png or webp).
+    /// Entries with undecodable names are listed as `entry<index>`
+    #[wasm_bindgen(constructor)]
+    pub fn new(data: Vec<u8>, image_format: &str) -> Result<PacViewer, JsError> {
+        let image_format: ImageFormat = image_format.parse().map_err(js_error)?;
+        let arc = PacArc::from_source(data).map_err(js_error)?;
+        let opts = ExtractOptions { image_format, ..Default::default() };
+        let targets = arc.plan_extraction(&opts, true).map_err(js_error)?;
+        Ok(PacViewer { arc, image_format, targets })
+    }
+
+    /// Number of entries in archive
+    #[wasm_bindgen(getter, js_name = entryCount)]
+    pub fn entry_count(&self) -> usize {
+        self.arc.entries.len()
+    }
+
+    /// Entries as json array of `{index, name, file, size}` objects
+    pub fn entries(&self) -> Result<String, JsError> {
+        let entries: Vec<_> = self.arc.entries.iter().zip(&self.targets).enumerate()
+            .filter_map(|(index, (entry, target))| target.as_ref().map(|t| ViewerEntry {
+                index,
+                name: &t.name,
+                file: &t.file,
+                size: entry.size,
+            }))
+            .collect();
+        Ok(serde_json::to_string(&entries)?)
+    }
+
+    /// Converted data of entry at `index`, same as extracted file
+    #[wasm_bindgen(js_name = readEntry)]
+    pub fn read_entry(&self, index: usize) -> Result<Vec<u8>, JsError> {
+        self.read(index, |raw, endian| Converters::builtin().convert_raw("", raw, self.image_format, endian))
+    }
+
+    /// Data of entry at `index` as stored in archive
+    #[wasm_bindgen(js_name = readRaw)]
+    pub fn read_raw(&self, index: usize) -> Result<Vec<u8>, JsError> {
+        self.read(index, |raw, _| Ok(raw.to_vec()))
+    }
+
+    fn read(&self, index: usize, f: impl FnOnce(&[u8], Endian) -> Result<Vec<u8>>) -> Result<Vec<u8>, JsError> {
+        let entry = self.arc.entries.get(index)
+            .ok_or_else(|| JsError::new(&format!("no entry at index {index}")))?;
+        self.arc.read_raw(entry).and_then(|raw| f(&raw, self.arc.format.endian)).map_err(js_error)
+    }
+}
+
+/// Decode binary `ttp` animation to pretty-printed json
+#[wasm_bindgen(js_name = ttpToJson)]
+pub fn ttp_to_json(data: &[u8]) -> Result<String, JsError> {
+    let ttp = TtpFile::read_le(&mut Cursor::new(data))?;
+    Ok(serde_json::to_string_pretty(&ttp)?)
+}
+
+/// Encode json animation, as produced by `ttpToJson`, back to binary `ttp`
+#[wasm_bindgen(js_name = jsonToTtp)]
+pub fn json_to_ttp(json: &str) -> Result<Vec<u8>, JsError> {
+    let ttp: TtpFile = serde_json::from_str(json)?;
+    ttp.validate().map_err(js_error)?;
+    let mut out = Cursor::new(vec![]);
+    out.write_le(&ttp)?;
+    Ok(out.into_inner())
+}
+
+fn js_error(e: anyhow::Error) -> JsError {
+    JsError::new(&format!("{e:#}"))
+}
@@ -0,0 +1,41 @@
+//! Entry table and entry data of untrusted archives must lie inside the file, so crafted counts
+//! and sizes are refused on open instead of causing huge allocations
+
+use nipaa_pac::{ErrorKind, PacArc, PacArcBuilder, PacFile, PacFormat, ENTRY_RECORD_SIZE};
+
+/// Kind of error opening `packed` with default layout, so detection can't pick another one that fits
+fn open_err(packed: Vec<u8>) -> Option<ErrorKind> {
+    ErrorKind::of(&PacArc::from_source_as(packed, PacFormat::default()).err().expect("archive opened"))
+}
+
+fn set_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+#[test]
+fn table_and_data_must_be_inside_file() {
+    let mut builder = PacArcBuilder::new();
+    builder.add_entry(PacFile::Other { data: b"first".to_vec() }, "a.txt").unwrap();
+    builder.add_entry(PacFile::Other { data: b"second".to_vec() }, "b.txt").unwrap();
+    let mut packed = vec![];
+    builder.pack_to(&mut packed).unwrap();
+    let last_record = 4 + ENTRY_RECORD_SIZE;
+
+    assert_eq!(open_err(packed[..2].to_vec()), Some(ErrorKind::Format));
+    assert_eq!(open_err(packed[..packed.len() - 1].to_vec()), Some(ErrorKind::SizeLimit));
+
+    let mut too_many = packed.clone();
+    set_u32(&mut too_many, 0, 1000);
+    assert_eq!(open_err(too_many), Some(ErrorKind::SizeLimit));
+
+    let mut past_end = packed.clone();
+    set_u32(&mut past_end, last_record, packed.len() as u32);
+    assert_eq!(open_err(past_end), Some(ErrorKind::SizeLimit));
+
+    let mut overflowing = packed.clone();
+    set_u32(&mut overflowing, last_record, u32::MAX);
+    set_u32(&mut overflowing, last_record + 4, u32::MAX);
+    assert_eq!(open_err(overflowing), Some(ErrorKind::SizeLimit));
+
+    assert!(PacArc::from_source_as(packed, PacFormat::default()).is_ok());
+}
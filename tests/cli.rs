@@ -0,0 +1,193 @@
+//! Commands rewriting archives on disk, run end to end: resulting archive is reopened
+//! and its entry names and data are checked. Requests naming missing or already taken entries
+//! fail with exit code 2 without touching archive
+
+use std::path::Path;
+use std::process::{Command, Output};
+use nipaa_pac::{PacArc, PacArcBuilder, PacFile, StreamConversion};
+
+fn nipaa(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_nipaa-pac")).args(args).output().unwrap()
+}
+
+fn succeeds(args: &[&str]) {
+    let out = nipaa(args);
+    assert!(out.status.success(), "{args:?} failed: {}", String::from_utf8_lossy(&out.stderr));
+}
+
+/// Path of `name` in `dir` as string, for passing it as argument
+fn path(dir: &Path, name: &str) -> String {
+    dir.join(name).to_str().unwrap().to_string()
+}
+
+/// Pack `files` as plain entries to `path`
+fn write_archive(path: &str, files: &[(&str, &[u8])]) {
+    let mut builder = PacArcBuilder::new();
+    for (name, data) in files {
+        builder.add_entry(PacFile::Other { data: data.to_vec() }, name).unwrap();
+    }
+    builder.pack(path).unwrap();
+}
+
+/// Names and stored data of all entries
+fn contents(path: &str) -> Vec<(String, Vec<u8>)> {
+    let arc = PacArc::open(path).unwrap();
+    arc.entries().map(|e| (e.name().unwrap(), e.read_raw().unwrap().into_owned())).collect()
+}
+
+fn expected(files: &[(&str, &[u8])]) -> Vec<(String, Vec<u8>)> {
+    files.iter().map(|(name, data)| (name.to_string(), data.to_vec())).collect()
+}
+
+const FILES: [(&str, &[u8]); 3] = [("a.txt", b"first"), ("b.txt", b"second"), ("c.txt", b"third")];
+
+#[test]
+fn add_appends_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let arc = path(dir.path(), "a.pac");
+    write_archive(&arc, &FILES);
+    std::fs::write(dir.path().join("d.txt"), b"fourth").unwrap();
+
+    succeeds(&["add", &arc, &path(dir.path(), "d.txt")]);
+    assert_eq!(contents(&arc), expected(&[FILES[0], FILES[1], FILES[2], ("d.txt", b"fourth")]));
+}
+
+#[test]
+fn remove_writes_copy_or_edits_in_place() {
+    let dir = tempfile::tempdir().unwrap();
+    let arc = path(dir.path(), "a.pac");
+    let out = path(dir.path(), "out.pac");
+    write_archive(&arc, &FILES);
+
+    succeeds(&["remove", &arc, "b.txt", "-o", &out]);
+    assert_eq!(contents(&out), expected(&[FILES[0], FILES[2]]));
+    assert_eq!(contents(&arc), expected(&FILES));
+
+    succeeds(&["remove", &arc, "a.txt", "--in-place"]);
+    assert_eq!(contents(&arc), expected(&[FILES[1], FILES[2]]));
+}
+
+#[test]
+fn replace_edits_in_place_or_over_itself() {
+    let dir = tempfile::tempdir().unwrap();
+    let arc = path(dir.path(), "a.pac");
+    write_archive(&arc, &FILES);
+    let replacement = path(dir.path(), "new.txt");
+    std::fs::write(&replacement, b"replaced first entry").unwrap();
+
+    succeeds(&["replace", &arc, "a.txt", &replacement]);
+    assert_eq!(contents(&arc), expected(&[("a.txt", b"replaced first entry"), FILES[1], FILES[2]]));
+
+    std::fs::write(&replacement, b"replaced last").unwrap();
+    succeeds(&["replace", &arc, "c.txt", &replacement, "-o", &arc]);
+    assert_eq!(contents(&arc), expected(&[("a.txt", b"replaced first entry"), FILES[1], ("c.txt", b"replaced last")]));
+}
+
+#[test]
+fn rename_keeps_data() {
+    let dir = tempfile::tempdir().unwrap();
+    let arc = path(dir.path(), "a.pac");
+    let out = path(dir.path(), "out.pac");
+    write_archive(&arc, &FILES);
+
+    succeeds(&["rename", &arc, "b.txt", "renamed.txt"]);
+    assert_eq!(contents(&arc), expected(&[FILES[0], ("renamed.txt", b"second"), FILES[2]]));
+
+    succeeds(&["rename", &arc, "a.txt", "x.txt", "-o", &out]);
+    assert_eq!(contents(&out), expected(&[("x.txt", b"first"), ("renamed.txt", b"second"), FILES[2]]));
+}
+
+#[test]
+fn fix_corrects_declared_bmz_size() {
+    let dir = tempfile::tempdir().unwrap();
+    let arc = path(dir.path(), "a.pac");
+    let bitmap = vec![0x42; 1000];
+    let mut builder = PacArcBuilder::new();
+    builder.add_entry(PacFile::Other { data: b"plain".to_vec() }, "a.txt").unwrap();
+    builder.add_entry_from_reader("b.bmz", &bitmap[..], bitmap.len() as u64, StreamConversion::Bmz).unwrap();
+    builder.pack(&arc).unwrap();
+    let mut packed = std::fs::read(&arc).unwrap();
+    let offset = PacArc::open(&arc).unwrap().entries[1].offset as usize;
+    packed[offset + 4..offset + 8].copy_from_slice(&2000u32.to_le_bytes());
+    std::fs::write(&arc, &packed).unwrap();
+
+    succeeds(&["fix", &arc]);
+    let arc = PacArc::open(&arc).unwrap();
+    let names: Vec<String> = arc.entries().map(|e| e.name().unwrap()).collect();
+    assert_eq!(names, ["a.txt", "b.bmz"]);
+    let file = arc.read_file(&arc.entries[1]).unwrap();
+    assert_eq!(file.uncompressed_size(), Some(1000));
+    assert_eq!(file.converted_data().unwrap(), bitmap);
+}
+
+#[test]
+fn optimize_and_merge_keep_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let (a, b) = (path(dir.path(), "a.pac"), path(dir.path(), "b.pac"));
+    write_archive(&a, &FILES[..2]);
+    write_archive(&b, &[("b.txt", b"newer"), FILES[2]]);
+
+    let optimized = path(dir.path(), "optimized.pac");
+    succeeds(&["optimize", &a, &optimized]);
+    assert_eq!(contents(&optimized), expected(&FILES[..2]));
+
+    let merged = path(dir.path(), "merged.pac");
+    succeeds(&["merge", &merged, &a, &b]);
+    assert_eq!(contents(&merged), expected(&[FILES[0], ("b.txt", b"newer"), FILES[2]]));
+}
+
+#[test]
+fn zip_conversion_round_trips() {
+    let dir = tempfile::tempdir().unwrap();
+    let (arc, zip, back) = (path(dir.path(), "a.pac"), path(dir.path(), "a.zip"), path(dir.path(), "back.pac"));
+    write_archive(&arc, &FILES);
+
+    succeeds(&["convert", &arc, &zip]);
+    succeeds(&["convert", &zip, &back]);
+    assert_eq!(contents(&back), expected(&FILES));
+}
+
+#[test]
+fn patch_reproduces_modified_archive() {
+    let dir = tempfile::tempdir().unwrap();
+    let (base, modified) = (path(dir.path(), "base.pac"), path(dir.path(), "modified.pac"));
+    write_archive(&base, &FILES);
+    write_archive(&modified, &[FILES[0], ("b.txt", b"changed"), ("d.txt", b"added")]);
+
+    let (patch, patched) = (path(dir.path(), "a.patch"), path(dir.path(), "patched.pac"));
+    succeeds(&["patch", "create", &base, &modified, &patch]);
+    succeeds(&["patch", "apply", &base, &patch, &patched]);
+    assert_eq!(contents(&patched), contents(&modified));
+}
+
+#[test]
+fn pack_stores_directory_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    std::fs::create_dir(&src).unwrap();
+    for (name, data) in FILES {
+        std::fs::write(src.join(name), data).unwrap();
+    }
+
+    let arc = path(dir.path(), "a.pac");
+    succeeds(&["pack", &arc, src.to_str().unwrap(), "--no-manifest", "--quiet"]);
+    assert_eq!(contents(&arc), expected(&FILES));
+}
+
+#[test]
+fn missing_and_conflicting_entries_exit_with_2() {
+    let dir = tempfile::tempdir().unwrap();
+    let arc = path(dir.path(), "a.pac");
+    write_archive(&arc, &FILES);
+
+    for args in [
+        vec!["cat", &arc, "nope"],
+        vec!["info", &arc, "nope"],
+        vec!["rename", &arc, "nope", "x.txt"],
+        vec!["rename", &arc, "a.txt", "b.txt"],
+        vec!["remove", &arc, "nope", "--in-place"],
+    ] {
+        assert_eq!(nipaa(&args).status.code(), Some(2), "{args:?}");
+    }
+    assert_eq!(contents(&arc), expected(&FILES));
+}
@@ -0,0 +1,10 @@
+//! Fixture shared by integration tests
+
+use nipaa_pac::{PacArc, PacArcBuilder};
+
+/// Pack `builder` in memory and open the result
+pub fn reopen(builder: PacArcBuilder) -> PacArc<Vec<u8>> {
+    let mut packed = vec![];
+    builder.pack_to(&mut packed).unwrap();
+    PacArc::from_source(packed).unwrap()
+}
@@ -0,0 +1,32 @@
+//! Entry data is converted with converters passed by caller, matched by entry name and data
+
+mod common;
+
+use nipaa_pac::{Converters, ExtMapping, ImageFormat, PacArcBuilder, StreamConversion};
+
+#[test]
+fn conversion_uses_given_converters_and_entry_name() {
+    let bitmap = vec![0x42; 64];
+    let mut builder = PacArcBuilder::new();
+    builder.add_entry_from_reader("x.dat", &bitmap[..], bitmap.len() as u64, StreamConversion::Bmz).unwrap();
+    builder.add_entry_from_reader("x.bin", &bitmap[..], bitmap.len() as u64, StreamConversion::Bmz).unwrap();
+    let arc = common::reopen(builder);
+    let raw: Vec<Vec<u8>> = arc.entries.iter().map(|e| arc.read_raw(e).unwrap().into_owned()).collect();
+
+    let converted = |converters: &Converters| -> Vec<Vec<u8>> {
+        arc.entries().map(|e| e.read_converted(ImageFormat::Bmp, converters).unwrap()).collect()
+    };
+    assert_eq!(converted(Converters::builtin()), [bitmap.clone(), bitmap.clone()]);
+
+    let mut without_bmz = Converters::default();
+    without_bmz.disable("bmz").unwrap();
+    assert_eq!(converted(&without_bmz), raw);
+
+    let mapping = ExtMapping { original: "dat".to_string(), converted: "bmp".to_string(), zlc3: true };
+    let mapped = Converters::empty().with_mappings(&[mapping]).unwrap();
+    assert_eq!(converted(&mapped), [bitmap.clone(), raw[1].clone()]);
+
+    let mut out = vec![];
+    arc.extract_entry_to(&arc.entries[0], ImageFormat::Bmp, &mapped, &mut out).unwrap();
+    assert_eq!(out, bitmap);
+}
@@ -0,0 +1,40 @@
+//! `--output ndjson`: stdout of every command carries only json events, ending with `complete` or `error`
+
+use std::process::Command;
+use nipaa_pac::{PacArcBuilder, PacFile};
+
+/// Events printed by command run with `--output ndjson`
+fn events(args: &[&str]) -> Vec<serde_json::Value> {
+    let out = Command::new(env!("CARGO_BIN_EXE_nipaa-pac")).arg("--output=ndjson").args(args).output().unwrap();
+    String::from_utf8(out.stdout).unwrap().lines().map(|line| serde_json::from_str(line).expect(line)).collect()
+}
+
+fn kinds(events: &[serde_json::Value]) -> Vec<&str> {
+    events.iter().map(|e| e["event"].as_str().unwrap()).collect()
+}
+
+#[test]
+fn every_command_ends_stream() {
+    let dir = tempfile::tempdir().unwrap();
+    let arc = dir.path().join("a.pac").to_str().unwrap().to_string();
+    let mut builder = PacArcBuilder::new();
+    builder.add_entry(PacFile::Other { data: b"first".to_vec() }, "a.txt").unwrap();
+    builder.add_entry(PacFile::Other { data: b"second".to_vec() }, "b.txt").unwrap();
+    builder.pack(&arc).unwrap();
+
+    let renamed = events(&["rename", &arc, "a.txt", "c.txt"]);
+    assert_eq!(kinds(&renamed), ["archive-written", "complete"]);
+    assert_eq!(renamed[0]["entries"], 2);
+    assert_eq!(renamed[1]["command"], "rename");
+
+    let listed = events(&["list", &arc]);
+    assert_eq!(kinds(&listed), ["entry-listed", "entry-listed", "list-complete", "complete"]);
+    assert_eq!(listed[0]["name"], "c.txt");
+
+    assert_eq!(kinds(&events(&["info", &arc, "b.txt"])), ["entry-info", "complete"]);
+    assert_eq!(kinds(&events(&["stats", &arc])), ["complete"]);
+
+    let failed = events(&["cat", &arc, "b.txt"]);
+    assert_eq!(kinds(&failed), ["error"]);
+    assert_eq!(failed[0]["exit_code"], 2);
+}
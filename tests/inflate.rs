@@ -0,0 +1,22 @@
+//! BMZ streams may not inflate past the bitmap size declared in their header
+
+use nipaa_pac::{Converters, ErrorKind, ImageFormat, PacArc, PacArcBuilder, PacFile, StreamConversion};
+
+#[test]
+fn inflation_is_capped_by_declared_size() {
+    let bitmap = vec![0x42; 1000];
+    let mut builder = PacArcBuilder::new();
+    builder.add_entry_from_reader("a.bmz", &bitmap[..], bitmap.len() as u64, StreamConversion::Bmz).unwrap();
+    let mut packed = vec![];
+    builder.pack_to(&mut packed).unwrap();
+    // declare bitmap smaller than stream inflates to
+    let header = PacArc::from_source(packed.clone()).unwrap().entries[0].offset as usize;
+    packed[header + 4..header + 8].copy_from_slice(&10u32.to_le_bytes());
+    let arc = PacArc::from_source(packed).unwrap();
+    let entry = &arc.entries[0];
+
+    let err = PacFile::from_raw(&arc.read_raw(entry).unwrap()).unwrap().converted_data().unwrap_err();
+    assert_eq!(ErrorKind::of(&err), Some(ErrorKind::SizeLimit));
+    let err = arc.extract_entry_to(entry, ImageFormat::Bmp, Converters::builtin(), &mut vec![]).unwrap_err();
+    assert_eq!(ErrorKind::of(&err), Some(ErrorKind::SizeLimit));
+}
@@ -0,0 +1,124 @@
+//! Serialized layout of packed archives: entry table records must be exactly `PacFormat::record_size` bytes,
+//! so offsets computed from it point at entry data
+
+mod common;
+
+use binrw::Endian;
+use nipaa_pac::{PacArc, PacArcBuilder, PacFile, PacFormat, StreamConversion, ENTRY_NAME_SIZE, ENTRY_RECORD_SIZE};
+
+fn other(data: &[u8]) -> PacFile {
+    PacFile::Other { data: data.to_vec() }
+}
+
+fn pack(builder: PacArcBuilder) -> Vec<u8> {
+    let mut out = vec![];
+    builder.pack_to(&mut out).unwrap();
+    out
+}
+
+fn u32_at(data: &[u8], offset: usize, endian: Endian) -> u32 {
+    let bytes = data[offset..offset + 4].try_into().unwrap();
+    match endian {
+        Endian::Little => u32::from_le_bytes(bytes),
+        Endian::Big => u32::from_be_bytes(bytes),
+    }
+}
+
+#[test]
+fn default_record_is_64_bytes() {
+    let format = PacFormat::default();
+    assert_eq!(ENTRY_RECORD_SIZE, 64);
+    assert_eq!(format.record_size(), ENTRY_RECORD_SIZE);
+    assert_eq!(format.table_offset(), 4);
+    assert_eq!(format.table_end(3), 4 + 3 * 64);
+}
+
+#[test]
+fn entry_data_follows_table() {
+    let files: [(&str, &[u8]); 3] = [("a.txt", b"first"), ("b.wav", b"second entry"), ("c.bin", b"")];
+    let mut builder = PacArcBuilder::new();
+    for (name, data) in files {
+        builder.add_entry(other(data), name).unwrap();
+    }
+    let packed = pack(builder);
+
+    assert_eq!(u32_at(&packed, 0, Endian::Little), 3);
+    let mut expected_offset = 4 + 3 * ENTRY_RECORD_SIZE;
+    for (index, (name, data)) in files.iter().enumerate() {
+        let record = &packed[4 + index * ENTRY_RECORD_SIZE..4 + (index + 1) * ENTRY_RECORD_SIZE];
+        let (offset, size) = (u32_at(record, 0, Endian::Little) as usize, u32_at(record, 4, Endian::Little) as usize);
+        assert_eq!((offset, size), (expected_offset, data.len()), "record of {name}");
+        assert_eq!(&record[8..8 + name.len()], name.as_bytes());
+        assert!(record[8 + name.len()..].iter().all(|b| *b == 0), "name field of {name} isn't null-padded");
+        assert_eq!(&packed[offset..offset + size], *data);
+        expected_offset += size;
+    }
+    assert_eq!(packed.len(), expected_offset);
+}
+
+#[test]
+fn name_must_leave_room_for_terminator() {
+    let mut builder = PacArcBuilder::new();
+    let longest = "n".repeat(ENTRY_NAME_SIZE - 1);
+    builder.add_entry(other(b"data"), &longest).unwrap();
+    assert!(builder.add_entry(other(b"data"), &"n".repeat(ENTRY_NAME_SIZE)).is_err());
+
+    let packed = pack(builder);
+    assert_eq!(packed.len(), 4 + ENTRY_RECORD_SIZE + 4);
+    let arc = PacArc::from_source(packed).unwrap();
+    assert_eq!(arc.entries[0].name().unwrap(), longest);
+}
+
+#[test]
+fn custom_layout_round_trips() {
+    let format = PacFormat { magic: b"PAC1".to_vec(), name_size: 32, endian: Endian::Big };
+    let mut builder = PacArcBuilder::new().with_format(format.clone()).unwrap();
+    builder.add_entry(other(b"hello"), "hello.txt").unwrap();
+    builder.add_entry(other(b"world!"), "world.txt").unwrap();
+    let packed = pack(builder);
+
+    assert_eq!(&packed[..4], b"PAC1");
+    assert_eq!(u32_at(&packed, 4, Endian::Big), 2);
+    let first = format.table_end(2) as usize;
+    assert_eq!(u32_at(&packed, 8, Endian::Big) as usize, first);
+    assert_eq!(u32_at(&packed, 8 + format.record_size(), Endian::Big) as usize, first + 5);
+
+    let arc = PacArc::from_source(packed).unwrap();
+    assert_eq!(arc.format, format);
+    let names: Vec<String> = arc.entries.iter().map(|e| e.name().unwrap()).collect();
+    assert_eq!(names, ["hello.txt", "world.txt"]);
+    assert_eq!(&arc.read_raw(&arc.entries[1]).unwrap()[..], b"world!");
+}
+
+#[test]
+fn stored_bmz_is_byte_exact() {
+    for endian in [Endian::Little, Endian::Big] {
+        let mut builder = PacArcBuilder::new().with_format(PacFormat { endian, ..Default::default() }).unwrap();
+        builder.add_entry_from_reader("a.bmz", &[0x5a; 1000][..], 1000, StreamConversion::Bmz).unwrap();
+        let arc = common::reopen(builder);
+        let raw = arc.read_raw(&arc.entries[0]).unwrap();
+
+        let mut builder = PacArcBuilder::new().with_format(arc.format.clone()).unwrap();
+        builder.add_entry(PacFile::from_bmz_as(&raw, endian).unwrap(), "a.bmz").unwrap();
+        let repacked = common::reopen(builder);
+        assert_eq!(repacked.read_raw(&repacked.entries[0]).unwrap(), raw);
+    }
+    assert!(PacFile::from_bmz_as(b"BM not compressed", Endian::Little).is_err());
+}
+
+#[test]
+fn estimated_size_matches_packed_size() {
+    let bitmap = vec![0x5a; 10_000];
+    let mut builder = PacArcBuilder::new().with_align(16);
+    builder.add_entry(other(b"odd length"), "a.txt").unwrap();
+    builder.add_entry_from_reader("b.bmz", &bitmap[..], bitmap.len() as u64, StreamConversion::Bmz).unwrap();
+    builder.add_entry_from_reader("c.bin", &b"streamed"[..], 8, StreamConversion::Store).unwrap();
+    assert_eq!(builder.entry_count(), 3);
+    let estimated = builder.estimated_size().unwrap();
+    let packed = pack(builder);
+    assert_eq!(estimated, packed.len() as u64);
+
+    let arc = PacArc::from_source(packed).unwrap();
+    assert!(arc.entries.iter().all(|e| e.offset % 16 == 0));
+    assert_eq!(arc.read_file(&arc.entries[1]).unwrap().converted_data().unwrap(), bitmap);
+}
@@ -0,0 +1,30 @@
+//! Modification times recorded in extraction manifest tell which extracted files changed afterwards
+
+mod common;
+
+use std::path::Path;
+use nipaa_pac::manifest::{file_mtime, set_file_mtime};
+use nipaa_pac::{ExtractOptions, PacArcBuilder, PacFile};
+
+#[test]
+fn changed_files_are_detected() {
+    let mut builder = PacArcBuilder::new();
+    builder.add_entry(PacFile::Other { data: b"first".to_vec() }, "a.txt").unwrap();
+    builder.add_entry(PacFile::Other { data: b"second".to_vec() }, "b.txt").unwrap();
+    let arc = common::reopen(builder);
+
+    let dir = tempfile::tempdir().unwrap();
+    let out_dir = dir.path().to_str().unwrap();
+    let manifest = arc.extract_all(out_dir, &ExtractOptions::default()).unwrap();
+    for entry in manifest.entries.iter() {
+        assert_eq!(entry.mtime, file_mtime(dir.path().join(&entry.file)), "{}", entry.name);
+        assert!(entry.mtime.is_some());
+    }
+    assert_eq!(manifest.changed_entries(dir.path()).count(), 0);
+
+    let touched = dir.path().join("b.txt");
+    set_file_mtime(&touched, file_mtime(&touched).unwrap() + 1_000_000_000).unwrap();
+    std::fs::remove_file(dir.path().join("a.txt")).unwrap();
+    let changed: Vec<&str> = manifest.changed_entries(Path::new(out_dir)).map(|e| e.name.as_str()).collect();
+    assert_eq!(changed, ["a.txt", "b.txt"]);
+}
@@ -0,0 +1,92 @@
+//! File names of extracted entries: names that would escape output directory are refused unless trusted,
+//! path separators are replaced or, with `PathMode::Preserve`, kept as subdirectories;
+//! raw copies kept with `keep_raw` follow names of converted files
+
+mod common;
+
+use nipaa_pac::{CollisionPolicy, ErrorKind, ExtractOptions, PacArc, PacArcBuilder, PacFile, PathMode, StreamConversion};
+
+fn archive(names: &[&str]) -> PacArc<Vec<u8>> {
+    let mut builder = PacArcBuilder::new();
+    for name in names {
+        builder.add_entry(PacFile::Other { data: name.as_bytes().to_vec() }, name).unwrap();
+    }
+    common::reopen(builder)
+}
+
+fn extracted_files(arc: &PacArc<Vec<u8>>, opts: &ExtractOptions) -> anyhow::Result<Vec<String>> {
+    let manifest = arc.extract_all_with(opts, |_| Ok(Some(std::io::sink())))?;
+    Ok(manifest.entries.into_iter().map(|e| e.file).collect())
+}
+
+#[test]
+fn escaping_names_are_refused() {
+    for name in ["..\\..\\evil.exe", "../evil.exe", "sub/../../evil.exe", "/etc/evil", "\\\\host\\share\\evil", "C:evil.exe", ".. /evil"] {
+        let arc = archive(&["fine.txt", name]);
+        let err = extracted_files(&arc, &ExtractOptions::default()).expect_err(name);
+        assert_eq!(ErrorKind::of(&err), Some(ErrorKind::Format), "{name}");
+    }
+}
+
+#[test]
+fn dots_inside_names_are_fine() {
+    let arc = archive(&["a..b.txt", "..hidden", "x/..y"]);
+    assert_eq!(extracted_files(&arc, &ExtractOptions::default()).unwrap(), ["a..b.txt", "..hidden", "x_..y"]);
+}
+
+#[test]
+fn preserved_paths_use_slashes() {
+    let arc = archive(&["chara/mion01.txt", "chara\\sub\\rena.txt", "chara//con.txt", "top?.txt"]);
+    let opts = ExtractOptions { paths: PathMode::Preserve, ..Default::default() };
+    assert_eq!(
+        extracted_files(&arc, &opts).unwrap(),
+        ["chara/mion01.txt", "chara/sub/rena.txt", "chara/con_.txt", "top_.txt"],
+    );
+}
+
+#[test]
+fn preserved_paths_stay_inside_output_directory() {
+    let arc = archive(&["../evil.exe", "/etc/evil"]);
+    let opts = ExtractOptions { paths: PathMode::Preserve, ..Default::default() };
+    assert!(extracted_files(&arc, &opts).is_err());
+    let opts = ExtractOptions { trust_names: true, ..opts };
+    assert_eq!(extracted_files(&arc, &opts).unwrap(), ["__/evil.exe", "etc/evil"]);
+}
+
+#[test]
+fn trusted_names_are_sanitized() {
+    let arc = archive(&["..\\..\\evil.exe", "/etc/evil"]);
+    let opts = ExtractOptions { trust_names: true, ..Default::default() };
+    assert_eq!(extracted_files(&arc, &opts).unwrap(), [".._.._evil.exe", "_etc_evil"]);
+}
+
+#[test]
+fn raw_copies_follow_converted_names() {
+    let bitmap = vec![0x42; 64];
+    let mut builder = PacArcBuilder::new();
+    builder.add_entry(PacFile::Other { data: b"plain".to_vec() }, "a.bmp").unwrap();
+    builder.add_entry_from_reader("a.bmz", &bitmap[..], bitmap.len() as u64, StreamConversion::Bmz).unwrap();
+    let arc = common::reopen(builder);
+
+    let dir = tempfile::tempdir().unwrap();
+    let opts = ExtractOptions { keep_raw: true, ..Default::default() };
+    let manifest = arc.extract_all(dir.path().to_str().unwrap(), &opts).unwrap();
+    let files: Vec<(&str, Option<&str>)> = manifest.entries.iter().map(|e| (e.file.as_str(), e.raw_file.as_deref())).collect();
+    assert_eq!(files, [("a.bmp", None), ("a~1.bmp", Some("a~1.bmz"))]);
+    assert_eq!(std::fs::read(dir.path().join("a~1.bmp")).unwrap(), bitmap);
+    assert_eq!(std::fs::read(dir.path().join("a~1.bmz")).unwrap(), arc.read_raw(&arc.entries[1]).unwrap()[..]);
+}
+
+#[test]
+fn suffixed_names_without_extension_have_no_trailing_dot() {
+    let arc = archive(&["readme", "README"]);
+    assert_eq!(extracted_files(&arc, &ExtractOptions::default()).unwrap(), ["readme", "README~1"]);
+}
+
+#[test]
+fn collisions_are_invalid_input_when_refused() {
+    let arc = archive(&["readme", "README"]);
+    let opts = ExtractOptions { on_collision: CollisionPolicy::Error, ..Default::default() };
+    let err = extracted_files(&arc, &opts).unwrap_err();
+    assert_eq!(ErrorKind::of(&err), Some(ErrorKind::InvalidInput));
+}
@@ -0,0 +1,110 @@
+//! In-place update of archive seeded with `PacArcBuilder::from_existing`: data of leading untouched
+//! entries is neither rewritten nor read, only data after them is moved. Archive may be rewritten
+//! over the file it was opened from
+
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+use nipaa_pac::{PacArc, PacArcBuilder, PacFile, PacSource};
+
+/// Offsets and sizes of reads
+type ReadLog = Arc<Mutex<Vec<(u64, usize)>>>;
+
+/// In-memory source recording every read
+struct CountingSource {
+    data: Vec<u8>,
+    reads: ReadLog,
+}
+
+impl PacSource for CountingSource {
+    fn read_at(&self, offset: u64, size: usize) -> anyhow::Result<Cow<'_, [u8]>> {
+        self.reads.lock().unwrap().push((offset, size));
+        self.data.read_at(offset, size)
+    }
+
+    fn size(&self) -> anyhow::Result<u64> {
+        Ok(self.data.len() as u64)
+    }
+}
+
+fn other(data: &[u8]) -> PacFile {
+    PacFile::Other { data: data.to_vec() }
+}
+
+/// Pack entries to `path` and open it again through counting source, returns log of its reads
+fn seeded(path: &std::path::Path, files: &[(&str, &[u8])]) -> (PacArc<CountingSource>, ReadLog) {
+    let mut builder = PacArcBuilder::new();
+    for (name, data) in files {
+        builder.add_entry(other(data), name).unwrap();
+    }
+    builder.pack(path.to_str().unwrap()).unwrap();
+    let reads = Arc::new(Mutex::new(vec![]));
+    let arc = PacArc::from_source(CountingSource { data: std::fs::read(path).unwrap(), reads: reads.clone() }).unwrap();
+    reads.lock().unwrap().clear();
+    (arc, reads)
+}
+
+fn contents(path: &std::path::Path) -> Vec<(String, Vec<u8>)> {
+    let arc = PacArc::open(path).unwrap();
+    arc.entries().map(|e| (e.name().unwrap(), e.read_raw().unwrap().into_owned())).collect()
+}
+
+#[test]
+fn leading_entries_are_not_read() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("a.pac");
+    let (big, small) = (vec![b'a'; 100_000], vec![b'c'; 50]);
+    let (arc, reads) = seeded(&path, &[("a.bin", &big), ("b.bin", b"old"), ("c.bin", &small)]);
+    let (a_offset, c_offset) = (arc.entries[0].offset as u64, arc.entries[2].offset as u64);
+
+    let mut builder = PacArcBuilder::from_existing(arc).unwrap();
+    assert!(builder.replace_entry("b.bin", other(b"replaced")));
+    let stats = builder.update(path.to_str().unwrap()).unwrap();
+
+    // only moved entry after replaced one is read
+    assert_eq!(*reads.lock().unwrap(), [(c_offset, small.len())]);
+    assert!(reads.lock().unwrap().iter().all(|&(offset, _)| offset >= a_offset + big.len() as u64));
+    assert_eq!(stats.kept_in_place, big.len() as u64);
+    let expected = [("a.bin", big), ("b.bin", b"replaced".to_vec()), ("c.bin", small)].map(|(n, d)| (n.to_string(), d));
+    assert_eq!(contents(&path), expected);
+}
+
+#[test]
+fn renaming_and_truncating_reads_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("a.pac");
+    let (arc, reads) = seeded(&path, &[("a.bin", b"first"), ("b.bin", b"second"), ("c.bin", b"third")]);
+
+    let mut builder = PacArcBuilder::from_existing(arc).unwrap();
+    assert!(builder.rename_entry("a.bin", "renamed.bin").unwrap());
+    assert!(builder.remove_entry("c.bin"));
+    builder.update(path.to_str().unwrap()).unwrap();
+
+    assert!(reads.lock().unwrap().is_empty());
+    let expected = [("renamed.bin", b"first".to_vec()), ("b.bin", b"second".to_vec())].map(|(n, d)| (n.to_string(), d));
+    assert_eq!(contents(&path), expected);
+}
+
+#[test]
+fn full_rewrite_of_opened_file_keeps_seeded_data() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("a.pac");
+    let files: [(&str, &[u8]); 3] = [("a.bin", b"first"), ("b.bin", b"second"), ("c.bin", b"third")];
+    drop(seeded(&path, &files));
+
+    // appended entry grows table into data of first entry, and first entry is replaced
+    let mut builder = PacArcBuilder::from_existing(PacArc::open(&path).unwrap()).unwrap();
+    builder.add_entry(other(b"fourth"), "d.bin").unwrap();
+    assert!(builder.replace_entry("a.bin", other(b"replaced")));
+    let stats = builder.update(path.to_str().unwrap()).unwrap();
+
+    assert_eq!(stats.kept_in_place, 0);
+    let expected = [("a.bin", &b"replaced"[..]), ("b.bin", b"second"), ("c.bin", b"third"), ("d.bin", b"fourth")]
+        .map(|(n, d)| (n.to_string(), d.to_vec()));
+    assert_eq!(contents(&path), expected);
+
+    // seeded archive written over itself with `pack`
+    let mut builder = PacArcBuilder::from_existing(PacArc::open(&path).unwrap()).unwrap();
+    assert!(builder.remove_entry("d.bin"));
+    builder.pack(path.to_str().unwrap()).unwrap();
+    assert_eq!(contents(&path), expected[..3]);
+}